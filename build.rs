@@ -5,7 +5,7 @@ use chrono::UTC;
 use std::env;
 use std::ffi::OsString;
 use std::fs::File;
-use std::io::Write;
+use std::io::{Read, Write};
 use std::process::Command;
 
 fn main() {
@@ -29,18 +29,44 @@ fn main() {
         .next()
         .unwrap_or("no-SHA".into());
 
+    // Algorithmia client version as resolved in Cargo.lock
+    let client_version = locked_version("algorithmia").unwrap_or("unknown-version".into());
+
     // Write it all to version.rs
     let mut f = File::create("src/version.rs").unwrap();
     write!(
         f,
         r#"
-pub static VERSION: &'static str =
-    "mia {pkg_ver} ({git_sha} {build_date})\n{rustc_version}";
+pub static PKG_VERSION: &str = "{pkg_ver}";
+pub static BUILD_INFO: &str = "{git_sha} {build_date}";
+pub static CLIENT_VERSION: &str = "{client_version}";
+pub static RUSTC_VERSION: &str = "{rustc_version}";
 "#,
         pkg_ver = option_env!("CARGO_PKG_VERSION").unwrap_or("unknown-version"),
         git_sha = git_sha.trim(),
         build_date = UTC::today().format("%Y-%m-%d"),
         rustc_version = rustc_version.trim(),
+        client_version = client_version,
     )
     .unwrap();
 }
+
+// Finds the version of a dependency in Cargo.lock
+fn locked_version(package: &str) -> Option<String> {
+    let mut lock = String::new();
+    File::open("Cargo.lock")
+        .and_then(|mut f| f.read_to_string(&mut lock))
+        .ok()?;
+
+    let name_line = format!("name = \"{}\"", package);
+    let mut lines = lock.lines();
+    while let Some(line) = lines.next() {
+        if line.trim() == name_line {
+            return lines
+                .next()
+                .and_then(|l| l.trim().strip_prefix("version = "))
+                .map(|v| v.trim_matches('"').to_string());
+        }
+    }
+    None
+}
@@ -0,0 +1,67 @@
+// Reads the system clipboard for `mia run --from-clipboard`
+//   Rather than linking a windowing system, this runs the platform's clipboard tool
+//   (pbpaste on macOS, PowerShell on Windows, and wl-paste, xclip, or xsel elsewhere).
+//   Builds without the 'clipboard' feature report that clipboard access isn't available.
+
+#[cfg(feature = "clipboard")]
+use std::io::ErrorKind;
+#[cfg(feature = "clipboard")]
+use std::process::Command;
+
+#[cfg(not(feature = "clipboard"))]
+pub fn read() -> Result<Vec<u8>, String> {
+    Err("Clipboard access isn't available in this build of mia \
+         (it requires the 'clipboard' feature)"
+        .to_string())
+}
+
+// Returns the clipboard contents, trying each of the platform's tools until one is found
+#[cfg(feature = "clipboard")]
+pub fn read() -> Result<Vec<u8>, String> {
+    for tool in tools() {
+        let output = match Command::new(tool[0]).args(&tool[1..]).output() {
+            Ok(output) => output,
+            Err(ref err) if err.kind() == ErrorKind::NotFound => continue,
+            Err(err) => return Err(format!("Error running {}: {}", tool[0], err)),
+        };
+        if !output.status.success() {
+            return Err(format!(
+                "Error reading the clipboard with {}: {}",
+                tool[0],
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        return Ok(output.stdout);
+    }
+    Err(format!(
+        "Clipboard access isn't available: none of {} could be found",
+        tools()
+            .iter()
+            .map(|tool| tool[0])
+            .collect::<Vec<_>>()
+            .join(", ")
+    ))
+}
+
+#[cfg(all(feature = "clipboard", target_os = "macos"))]
+fn tools() -> Vec<&'static [&'static str]> {
+    vec![&["pbpaste"]]
+}
+
+#[cfg(all(feature = "clipboard", windows))]
+fn tools() -> Vec<&'static [&'static str]> {
+    vec![&["powershell", "-NoProfile", "-Command", "Get-Clipboard -Raw"]]
+}
+
+#[cfg(all(feature = "clipboard", not(any(target_os = "macos", windows))))]
+fn tools() -> Vec<&'static [&'static str]> {
+    // wl-paste only works under Wayland, while the others need an X server (or XWayland)
+    let mut tools: Vec<&'static [&'static str]> = vec![
+        &["xclip", "-selection", "clipboard", "-out"],
+        &["xsel", "--clipboard", "--output"],
+    ];
+    if std::env::var_os("WAYLAND_DISPLAY").is_some() {
+        tools.insert(0, &["wl-paste", "--no-newline"]);
+    }
+    tools
+}
@@ -0,0 +1,149 @@
+use rustc_serialize::json::Json;
+
+// A JSONPath expression for `mia run --result-jsonpath`
+//
+// Supports the subset that picks fields out of a result: the root `$`, child keys as `.key` or
+//   `['key']`, array indexes as `[0]` (or `[-1]` from the end), and wildcards as `.*` or `[*]`.
+//   Filters, slices, and recursive descent (`..`) aren't supported.
+pub struct JsonPath {
+    steps: Vec<Step>,
+}
+
+enum Step {
+    Key(String),
+    Index(i64),
+    Wildcard,
+}
+
+impl JsonPath {
+    pub fn parse(path: &str) -> Result<JsonPath, String> {
+        let invalid = |reason: &str| format!("invalid JSONPath '{}': {}", path, reason);
+        let rest = path
+            .strip_prefix('$')
+            .ok_or_else(|| invalid("must start with '$'"))?;
+
+        let mut steps = Vec::new();
+        let mut chars = rest.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '.' => {
+                    let mut key = String::new();
+                    while let Some(&c) = chars.peek() {
+                        if c == '.' || c == '[' {
+                            break;
+                        }
+                        key.push(c);
+                        chars.next();
+                    }
+                    steps.push(match key.as_str() {
+                        "" if chars.peek() == Some(&'.') => {
+                            return Err(invalid("recursive descent ('..') is not supported"))
+                        }
+                        "" => return Err(invalid("expected a key after '.'")),
+                        "*" => Step::Wildcard,
+                        _ => Step::Key(key),
+                    });
+                }
+                '[' => match chars.peek() {
+                    Some(&quote) if quote == '\'' || quote == '"' => {
+                        chars.next();
+                        let key = take_until(&mut chars, quote)
+                            .ok_or_else(|| invalid("missing closing quote"))?;
+                        if chars.next() != Some(']') {
+                            return Err(invalid("expected ']' after a quoted key"));
+                        }
+                        steps.push(Step::Key(key));
+                    }
+                    _ => {
+                        let inner =
+                            take_until(&mut chars, ']').ok_or_else(|| invalid("missing ']'"))?;
+                        steps.push(match inner.as_str() {
+                            "*" => Step::Wildcard,
+                            _ => Step::Index(inner.parse().map_err(|_| {
+                                invalid(&format!("unsupported selector '[{}]'", inner))
+                            })?),
+                        });
+                    }
+                },
+                _ => return Err(invalid(&format!("unexpected '{}'", c))),
+            }
+        }
+        Ok(JsonPath { steps })
+    }
+
+    // The values the path matches, in document order (a wildcard matches every item)
+    pub fn select<'a>(&self, value: &'a Json) -> Vec<&'a Json> {
+        let mut matches = vec![value];
+        for step in &self.steps {
+            matches = matches
+                .into_iter()
+                .flat_map(|value| step.select(value))
+                .collect();
+        }
+        matches
+    }
+}
+
+// Consumes the chars up to and including `end`, returning those before it (or None if there
+//   is no `end`)
+fn take_until<I: Iterator<Item = char>>(chars: &mut I, end: char) -> Option<String> {
+    let mut taken = String::new();
+    for c in chars {
+        if c == end {
+            return Some(taken);
+        }
+        taken.push(c);
+    }
+    None
+}
+
+impl Step {
+    fn select<'a>(&self, value: &'a Json) -> Vec<&'a Json> {
+        match (self, value) {
+            (Step::Key(key), Json::Object(object)) => object.get(key).into_iter().collect(),
+            (Step::Index(index), Json::Array(items)) => {
+                let index = if *index < 0 {
+                    items.len() as i64 + index
+                } else {
+                    *index
+                };
+                if index < 0 {
+                    return vec![];
+                }
+                items.get(index as usize).into_iter().collect()
+            }
+            (Step::Wildcard, Json::Array(items)) => items.iter().collect(),
+            (Step::Wildcard, Json::Object(object)) => object.values().collect(),
+            _ => vec![],
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_select() {
+        let doc = Json::from_str(
+            r#"{"data": {"value": 42, "items": [{"id": "a"}, {"id": "b"}], "odd key": true}}"#,
+        )
+        .unwrap();
+        let select = |path: &str| -> Vec<String> {
+            let path = JsonPath::parse(path).unwrap();
+            path.select(&doc).iter().map(|v| v.to_string()).collect()
+        };
+        assert_eq!(select("$.data.value"), vec!["42"]);
+        assert_eq!(select("$.data.items[*].id"), vec![r#""a""#, r#""b""#]);
+        assert_eq!(select("$.data.items[-1].id"), vec![r#""b""#]);
+        assert_eq!(select("$['data']['odd key']"), vec!["true"]);
+        assert_eq!(select("$"), vec![doc.to_string()]);
+        assert!(select("$.data.missing").is_empty());
+        assert!(select("$.data.items[5]").is_empty());
+
+        assert!(JsonPath::parse("data.value").is_err());
+        assert!(JsonPath::parse("$..value").is_err());
+        assert!(JsonPath::parse("$.items[?(@.id)]").is_err());
+        assert!(JsonPath::parse("$.items[0").is_err());
+    }
+}
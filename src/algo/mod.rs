@@ -1,23 +1,44 @@
 pub use self::clone::GitClone;
 pub use self::run::Run;
 
+mod clipboard;
 mod clone;
+mod jsonpath;
+mod rate_limit;
+mod resume;
 mod run;
+mod schema;
+mod stream;
 
+use self::jsonpath::JsonPath;
+use crate::data::size_with_suffix;
+use crate::logging::{self, Level};
+use crate::output::{self, OutputFormat};
 use crate::{color_choice, BRIGHT_RED, GRAY};
-use algorithmia::algo::{AlgoResponse, Response};
+use algorithmia::algo::{AlgoIo, AlgoOptions, AlgoResponse, Response};
+use algorithmia::data::{DataFile, HasDataPath};
+use algorithmia::error::Error;
+use algorithmia::Algorithmia;
+use regex::Regex;
+use rustc_serialize::base64::{self, FromBase64, ToBase64};
 use rustc_serialize::json::Json;
+use std::collections::BTreeMap;
 use std::fs::File;
 use std::io::{self, Read, Write};
 use std::path::Path;
+use std::time::Instant;
 use std::vec::IntoIter;
 use termcolor::{Color, ColorSpec, StandardStream, WriteColor};
 
-#[derive(Debug)]
-enum InputData {
+#[derive(Clone, Debug)]
+pub enum InputData {
     Text(String),
     Json(String),
     Binary(Vec<u8>),
+    // URL-encoded form fields (application/x-www-form-urlencoded)
+    Form(String),
+    // Encoded multipart/form-data parts, and the boundary between them
+    Multipart(Vec<u8>, String),
 }
 
 impl InputData {
@@ -40,6 +61,26 @@ impl InputData {
         }
     }
 
+    // Reads an input file (for -D and --part @<file>), detecting its type per --detect-by
+    fn file(path: &str, detect: Detect) -> InputData {
+        let extension = Path::new(path)
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(str::to_lowercase);
+        let mut src = get_src(path);
+        match (detect, extension.as_deref()) {
+            (Detect::Extension, Some("json")) => InputData::json(&mut src),
+            (Detect::Extension, Some(ext)) if TEXT_EXTENSIONS.contains(&ext) => {
+                InputData::text(&mut src)
+            }
+            (Detect::Extension, Some(ext)) if BINARY_EXTENSIONS.contains(&ext) => {
+                InputData::binary(&mut src)
+            }
+            // Other extensions (and STDIN) are detected by content
+            _ => InputData::auto(&mut src),
+        }
+    }
+
     fn text(reader: &mut dyn Read) -> InputData {
         let mut data = String::new();
         match reader.read_to_string(&mut data) {
@@ -63,6 +104,238 @@ impl InputData {
             Err(err) => quit_err!("Read error: {}", err),
         }
     }
+
+    // The Content-Type the input is sent with
+    fn content_type(&self) -> mime::Mime {
+        match self {
+            InputData::Text(_) => mime::TEXT_PLAIN,
+            InputData::Json(_) => mime::APPLICATION_JSON,
+            InputData::Binary(_) => mime::APPLICATION_OCTET_STREAM,
+            InputData::Form(_) => mime::APPLICATION_WWW_FORM_URLENCODED,
+            InputData::Multipart(_, boundary) => {
+                format!("multipart/form-data; boundary={}", boundary)
+                    .parse()
+                    .unwrap()
+            }
+        }
+    }
+
+    // The start of the input, for printing (binary input is only described)
+    fn preview(&self, max_chars: usize) -> String {
+        match self {
+            InputData::Text(s) | InputData::Json(s) | InputData::Form(s) => {
+                match s.char_indices().nth(max_chars) {
+                    Some((end, _)) => format!("{}... (truncated)", &s[..end]),
+                    None => s.clone(),
+                }
+            }
+            InputData::Binary(_) => "(binary)".to_string(),
+            InputData::Multipart(..) => "(multipart/form-data)".to_string(),
+        }
+    }
+
+    // The size of the request body the input is sent as
+    fn size(&self) -> u64 {
+        match self {
+            InputData::Text(s) | InputData::Json(s) | InputData::Form(s) => s.len() as u64,
+            InputData::Binary(bytes) | InputData::Multipart(bytes, _) => bytes.len() as u64,
+        }
+    }
+
+    // The request body the input is sent as
+    fn into_bytes(self) -> Vec<u8> {
+        match self {
+            InputData::Text(s) | InputData::Json(s) | InputData::Form(s) => s.into_bytes(),
+            InputData::Binary(bytes) | InputData::Multipart(bytes, _) => bytes,
+        }
+    }
+
+    // Decode base64-encoded input into the raw binary input
+    fn decode_base64(self) -> InputData {
+        if let InputData::Multipart(..) = self {
+            quit_msg!("--input-encoding does not apply to --multipart input");
+        }
+        let encoded = self.into_bytes();
+        let trimmed = String::from_utf8_lossy(&encoded).trim().to_string();
+        match trimmed.from_base64() {
+            Ok(bytes) => InputData::Binary(bytes),
+            Err(err) => quit_err!("Failed to decode base64 input: {}", err),
+        }
+    }
+
+    // Combine named parts into a single JSON object
+    //   JSON parts are embedded as-is, text parts as strings,
+    //   and binary parts as base64-encoded strings
+    fn parts(parts: Vec<(String, InputData)>) -> InputData {
+        let mut object = BTreeMap::new();
+        for (name, part) in parts {
+            let value = match part {
+                InputData::Json(data) => Json::from_str(&data)
+                    .unwrap_or_else(|err| quit_err!("Invalid JSON for part '{}': {}", name, err)),
+                InputData::Text(text) | InputData::Form(text) => Json::String(text),
+                InputData::Binary(bytes) | InputData::Multipart(bytes, _) => {
+                    Json::String(bytes.to_base64(base64::STANDARD))
+                }
+            };
+            if object.insert(name.clone(), value).is_some() {
+                quit_msg!("Part '{}' specified more than once", name);
+            }
+        }
+        InputData::Json(Json::Object(object).to_string())
+    }
+
+    // URL-encodes form fields (in order, allowing repeated names) into a form body
+    fn form(fields: Vec<(String, String)>) -> InputData {
+        let body = url::form_urlencoded::Serializer::new(String::new())
+            .extend_pairs(fields)
+            .finish();
+        InputData::Form(body)
+    }
+
+    // Encodes parts as multipart/form-data, separated by a boundary that none of them contain
+    fn multipart(parts: Vec<MultipartPart>) -> InputData {
+        let boundary = (0..)
+            .map(|n| format!("mia-form-boundary-{}", n))
+            .find(|boundary| {
+                let boundary = boundary.as_bytes();
+                !parts
+                    .iter()
+                    .any(|part| part.data.windows(boundary.len()).any(|w| w == boundary))
+            })
+            .unwrap();
+
+        let mut body = Vec::new();
+        for part in parts {
+            let mut headers = format!(
+                "--{}\r\nContent-Disposition: form-data; name=\"{}\"",
+                boundary,
+                escape_quoted(&part.name)
+            );
+            if let Some(ref filename) = part.filename {
+                headers.push_str(&format!("; filename=\"{}\"", escape_quoted(filename)));
+            }
+            if let Some(ref content_type) = part.content_type {
+                headers.push_str(&format!("\r\nContent-Type: {}", content_type));
+            }
+            headers.push_str("\r\n\r\n");
+            body.extend_from_slice(headers.as_bytes());
+            body.extend_from_slice(&part.data);
+            body.extend_from_slice(b"\r\n");
+        }
+        body.extend_from_slice(format!("--{}--\r\n", boundary).as_bytes());
+        InputData::Multipart(body, boundary)
+    }
+
+    // Combines several JSON inputs into one, either as an array of the inputs (in order)
+    //   or by deep-merging them as objects (later inputs win for conflicting keys)
+    fn combine(inputs: Vec<InputData>, strategy: &str) -> InputData {
+        let values = inputs.into_iter().map(|input| match input {
+            InputData::Json(data) => {
+                Json::from_str(&data).unwrap_or_else(|err| quit_err!("Invalid JSON input: {}", err))
+            }
+            _ => quit_msg!("--combine requires JSON inputs (e.g. -j or -J)"),
+        });
+        let combined = match strategy {
+            "array" => Json::Array(values.collect()),
+            "merge" => values.fold(Json::Object(BTreeMap::new()), |merged, value| {
+                if !value.is_object() {
+                    quit_msg!("--combine merge requires every input to be a JSON object");
+                }
+                deep_merge(merged, value)
+            }),
+            other => quit_msg!(
+                "Unsupported --combine strategy '{}'. Supported: array, merge",
+                other
+            ),
+        };
+        InputData::Json(combined.to_string())
+    }
+
+    // Fills in an --input-template with the data URI of an uploaded input, where every
+    //   {{uri}} is replaced by the URI escaped for use inside a JSON string
+    fn from_template(template: &str, uri: &str) -> InputData {
+        let escaped = Json::String(uri.to_string()).to_string();
+        let json = template.replace("{{uri}}", &escaped[1..escaped.len() - 1]);
+        if let Err(err) = Json::from_str(&json) {
+            quit_err!("Invalid --input-template: {}", err);
+        }
+        InputData::Json(json)
+    }
+}
+
+// A part of a multipart/form-data input (--multipart)
+#[derive(Debug)]
+struct MultipartPart {
+    name: String,
+    filename: Option<String>,
+    content_type: Option<mime::Mime>,
+    data: Vec<u8>,
+}
+
+// Escapes a name for a quoted multipart header parameter, like browsers do
+fn escape_quoted(value: &str) -> String {
+    value
+        .replace('"', "%22")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A")
+}
+
+// How the type of an input file is detected (--detect-by)
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Detect {
+    // From the file's contents, like --data (the default)
+    Content,
+    // From the file's extension, falling back to its contents for unknown extensions
+    Extension,
+}
+
+// The largest input data given inline (e.g. with -d) that is sent without --allow-large
+const MAX_INLINE_INPUT: usize = 64 * 1024;
+
+// Extensions of files read as text or binary with --detect-by extension (.json is JSON)
+static TEXT_EXTENSIONS: &[&str] = &[
+    "txt", "text", "md", "csv", "tsv", "log", "html", "htm", "xml", "yaml", "yml", "toml",
+];
+static BINARY_EXTENSIONS: &[&str] = &[
+    "png", "jpg", "jpeg", "gif", "bmp", "webp", "tif", "tiff", "ico", "pdf", "zip", "gz", "tar",
+    "mp3", "wav", "flac", "mp4", "mov", "avi", "bin", "npy", "pb", "onnx",
+];
+
+// An algorithm's result and the metadata reported with it (see Run::run)
+#[derive(Debug)]
+pub struct RunResult {
+    pub result: AlgoIo,
+    // Seconds the algorithm ran for
+    pub duration: f32,
+    // How the result was encoded: text, json, or binary
+    pub content_type: String,
+}
+
+impl From<AlgoResponse> for RunResult {
+    fn from(response: AlgoResponse) -> RunResult {
+        RunResult {
+            result: response.result,
+            duration: response.metadata.duration,
+            content_type: response.metadata.content_type,
+        }
+    }
+}
+
+// Merges `overlay` into `base`, recursing into objects present in both
+fn deep_merge(base: Json, overlay: Json) -> Json {
+    match (base, overlay) {
+        (Json::Object(mut base), Json::Object(overlay)) => {
+            for (key, value) in overlay {
+                let merged = match base.remove(&key) {
+                    Some(existing) => deep_merge(existing, value),
+                    None => value,
+                };
+                base.insert(key, merged);
+            }
+            Json::Object(base)
+        }
+        (_, overlay) => overlay,
+    }
 }
 
 // The device specified by --output flag
@@ -97,6 +370,107 @@ impl OutputDevice {
         self.write(bytes);
         self.write(b"\n");
     }
+
+    fn flush(&mut self) {
+        if let Err(err) = self.writer.flush() {
+            quit_err!("Error writing output: {}", err);
+        }
+    }
+}
+
+// Normalizes an algorithm given as an algo:// URI, or as a URL from the web console or API
+//   (e.g. https://algorithmia.com/algorithms/USERNAME/ALGONAME/VERSION), to the
+//   USERNAME/ALGONAME[/VERSION] form (which is returned as-is)
+fn algorithm_id(algo: &str) -> Result<String, String> {
+    if let Some(id) = algo.strip_prefix("algo://") {
+        return Ok(id.trim_end_matches('/').to_string());
+    }
+    if !algo.starts_with("https://") && !algo.starts_with("http://") {
+        return Ok(algo.to_string());
+    }
+    let url = url::Url::parse(algo)
+        .map_err(|err| format!("Invalid algorithm URL '{}': {}", algo, err))?;
+    let segments: Vec<&str> = match url.path_segments() {
+        Some(segments) => segments.filter(|s| !s.is_empty()).collect(),
+        None => Vec::new(),
+    };
+    let id = segments
+        .iter()
+        .position(|&s| s == "algorithms" || s == "algo")
+        .map(|i| &segments[i + 1..])
+        .filter(|id| id.len() == 2 || id.len() == 3);
+    match id {
+        Some(id) => Ok(id.join("/")),
+        None => Err(format!(
+            "Invalid algorithm URL '{}'. Expected .../algorithms/USERNAME/ALGONAME[/VERSION]",
+            algo
+        )),
+    }
+}
+
+// TODO: `run --check-limits` to compare the input's size to the algorithm's input limit before
+//   sending it, failing fast instead of with a 413 partway through the upload. Blocked on the
+//   API exposing a per-algorithm input limit (the client has no algorithm metadata call at all)
+
+// Calls an algorithm with the given input, returning the raw HTTP response
+fn call_algorithm(
+    client: &Algorithmia,
+    algo: &str,
+    input_data: InputData,
+    opts: AlgoOptions,
+) -> Result<Response, Error> {
+    let mut algorithm = client.algo(algo);
+    let algorithm = algorithm.set_options(opts);
+
+    let content_type = input_data.content_type();
+    match input_data {
+        InputData::Text(body) | InputData::Json(body) | InputData::Form(body) => {
+            algorithm.pipe_as(body, content_type)
+        }
+        InputData::Binary(bytes) | InputData::Multipart(bytes, _) => {
+            algorithm.pipe_as(bytes, content_type)
+        }
+    }
+}
+
+// AlgoOptions isn't Clone, but concurrent calls each need their own copy
+fn copy_options(opts: &AlgoOptions) -> AlgoOptions {
+    let mut copy = AlgoOptions::default();
+    for (k, v) in opts.iter() {
+        copy.insert(k.clone(), v.clone());
+    }
+    copy
+}
+
+// Smart output of a result: text as-is and JSON compactly (each followed by a newline),
+//   while binary is written as raw bytes (or as a base64 line with --output-encoding)
+fn render_result(result: &AlgoIo, output_encoding: Option<&str>) -> Vec<u8> {
+    match result.as_string() {
+        Some(s) => format!("{}\n", s).into_bytes(),
+        None => match result.to_json() {
+            Some(j) => format!("{}\n", j).into_bytes(),
+            None => {
+                let bytes = result.as_bytes().unwrap();
+                match output_encoding {
+                    Some("base64") => {
+                        format!("{}\n", bytes.to_base64(base64::STANDARD)).into_bytes()
+                    }
+                    _ => bytes.to_vec(),
+                }
+            }
+        },
+    }
+}
+
+// The exact bytes of a result: a string as-is, JSON serialized, or binary unencoded
+fn result_body(result: &AlgoIo) -> Vec<u8> {
+    match result.as_string() {
+        Some(s) => s.as_bytes().to_vec(),
+        None => match result.to_json() {
+            Some(j) => j.into_bytes(),
+            None => result.as_bytes().unwrap().to_vec(),
+        },
+    }
 }
 
 fn get_src(src: &str) -> Box<dyn Read> {
@@ -115,28 +489,175 @@ fn open_file(path: &Path) -> Box<dyn Read> {
     Box::new(file)
 }
 
+// Reads a response body, stopping once it exceeds `max_bytes` (if set)
+//   Returns the body (truncated to `max_bytes`) and whether it was truncated
+fn read_body<R: Read>(response: &mut R, max_bytes: Option<u64>) -> io::Result<(Vec<u8>, bool)> {
+    let mut body = Vec::new();
+    match max_bytes {
+        Some(max) => {
+            response
+                .by_ref()
+                .take(max.saturating_add(1))
+                .read_to_end(&mut body)?;
+            let truncated = body.len() as u64 > max;
+            body.truncate(max as usize);
+            Ok((body, truncated))
+        }
+        None => response.read_to_end(&mut body).map(|_| (body, false)),
+    }
+}
+
+// Records when the first byte is read from a response (for --show-ttfb)
+struct FirstByteTimer<R: Read> {
+    inner: R,
+    first_byte: Option<Instant>,
+}
+
+impl<R: Read> Read for FirstByteTimer<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        if n > 0 && self.first_byte.is_none() {
+            self.first_byte = Some(Instant::now());
+        }
+        Ok(n)
+    }
+}
+
+fn truncation_notice(max_bytes: Option<u64>) -> String {
+    format!(
+        "Response exceeded --max-output-bytes ({}B) and was not read in full",
+        size_with_suffix(max_bytes.unwrap_or(0))
+    )
+}
+
+// How much besides the result is printed (to STDERR)
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Verbosity {
+    // Alerts, timing, and other notices, as well as errors
+    Normal,
+    // Only errors (--quiet)
+    Quiet,
+    // Nothing, though failures still exit with an error (--silence)
+    Silent,
+}
+
 struct ResponseConfig {
     flag_response_body: bool,
     flag_response: bool,
-    flag_silence: bool,
+    verbosity: Verbosity,
     flag_debug: bool,
     flag_output: Option<String>,
+    // Written with a Data API PUT instead of to the output device (--output-data)
+    output_data: Option<DataFile>,
+    flag_response_meta: Option<String>,
+    flag_output_encoding: Option<String>,
+    // Write the result's exact bytes, without the newline that render_result adds
+    only_result_body: bool,
+    max_output_bytes: Option<u64>,
+    format: OutputFormat,
+    // When the request was started, for the timing printed by --show-ttfb and --show-metadata
+    request_started: Instant,
+    show_ttfb: bool,
+    // The size of the input that was sent, if printing payload sizes (--show-metadata)
+    input_size: Option<u64>,
+    // Print only the values of a JSON result that this matches (--result-jsonpath)
+    result_jsonpath: Option<JsonPath>,
+    // Exit with an error if --result-jsonpath matches nothing
+    require_match: bool,
+}
+
+// Writes the HTTP status and headers as a JSON object to a separate file
+//   so that wrappers can inspect them while the result still goes to the output device
+fn write_response_meta(response: &Response, path: &str) {
+    let mut headers: BTreeMap<String, Json> = BTreeMap::new();
+    for (name, value) in response.headers() {
+        let value = String::from_utf8_lossy(value.as_bytes()).into_owned();
+        let joined = match headers.remove(name.as_str()) {
+            Some(Json::String(prev)) => format!("{}, {}", prev, value),
+            _ => value,
+        };
+        headers.insert(name.as_str().to_owned(), Json::String(joined));
+    }
+
+    let mut meta = BTreeMap::new();
+    meta.insert(
+        "status".to_owned(),
+        Json::U64(u64::from(response.status().as_u16())),
+    );
+    meta.insert("headers".to_owned(), Json::Object(headers));
+
+    let written = File::create(path).and_then(|mut f| writeln!(f, "{}", Json::Object(meta)));
+    if let Err(err) = written {
+        quit_err!("Unable to write response metadata: {}", err);
+    }
 }
 
 fn display_response(mut response: Response, config: ResponseConfig) {
+    let body = read_response(&mut response, &config);
+    display_body(response, body, config)
+}
+
+// Reads a JSON response (up to --max-output-bytes), timing the first byte for --show-ttfb
+//   Returns the body and whether it was truncated
+fn read_response(response: &mut Response, config: &ResponseConfig) -> (Vec<u8>, bool) {
+    let mut timer = FirstByteTimer {
+        inner: response,
+        first_byte: None,
+    };
+    let (body, truncated) = read_body(&mut timer, config.max_output_bytes)
+        .unwrap_or_else(|err| quit_err!("Error reading response: {}", err));
+    if config.verbosity == Verbosity::Silent {
+        return (body, truncated);
+    }
+
+    let started = config.request_started;
+    let elapsed = started.elapsed().as_secs_f64();
+    let mut t_err = StandardStream::stderr(color_choice());
+    let _ = t_err.set_color(ColorSpec::new().set_fg(Some(GRAY)));
+    if config.show_ttfb {
+        let first_byte = timer.first_byte.unwrap_or_else(Instant::now) - started;
+        let line = format!(
+            "Time to first byte: {:.3} seconds (response read in {:.3} seconds)",
+            first_byte.as_secs_f64(),
+            elapsed
+        );
+        let _ = writeln!(t_err, "{}", line);
+        logging::record(Level::Info, &line);
+    }
+    if let Some(input_size) = config.input_size {
+        // Throughput counts the bytes both sent and received over the whole call
+        let received = body.len() as u64;
+        let per_sec = if elapsed > 0.0 {
+            ((input_size + received) as f64 / elapsed) as u64
+        } else {
+            0
+        };
+        let line = format!(
+            "Sent {}B of input and received a {}B response in {:.3} seconds ({}B/s)",
+            size_with_suffix(input_size),
+            size_with_suffix(received),
+            elapsed,
+            size_with_suffix(per_sec)
+        );
+        let _ = writeln!(t_err, "{}", line);
+        logging::record(Level::Info, &line);
+    }
+    let _ = t_err.reset();
+    (body, truncated)
+}
+
+// Displays a response whose body has already been read (see read_response)
+fn display_body(response: Response, (body, truncated): (Vec<u8>, bool), config: ResponseConfig) {
     // Open up an output device for the result/response
     let mut output = OutputDevice::new(&config.flag_output);
     let mut t_err = StandardStream::stderr(color_choice());
 
-    // Read JSON response - scoped so that we can re-borrow response
-    let mut json_response = String::new();
-    {
-        if let Err(err) = response.read_to_string(&mut json_response) {
-            quit_err!("Error reading response: {}", err)
-        };
+    if let Some(ref path) = config.flag_response_meta {
+        write_response_meta(&response, path);
     }
 
     // Handle --response and --response-body (ignoring other flags)
+    //   These print the raw body, so a truncated body is printed before aborting
     if config.flag_response || config.flag_response_body {
         if config.flag_response {
             let preamble = format!(
@@ -147,59 +668,90 @@ fn display_response(mut response: Response, config: ResponseConfig) {
             );
             output.writeln(preamble.as_bytes());
         };
-        output.writeln(json_response.as_bytes());
+        output.writeln(&body);
+        if truncated {
+            quit_msg!("{}", truncation_notice(config.max_output_bytes));
+        }
     } else {
+        if truncated {
+            quit_msg!("{}", truncation_notice(config.max_output_bytes));
+        }
+        let json_response = String::from_utf8(body)
+            .unwrap_or_else(|err| quit_err!("Error reading response: {}", err));
         match json_response.parse::<AlgoResponse>() {
             Ok(response) => {
                 // Printing any API alerts
+                // TODO: --show-credits to print the remaining account credits after a call, once
+                //   the API reports them (e.g. in a response header). A low balance is only
+                //   reported as one of these alerts today
                 if let Some(ref alerts) = response.metadata.alerts {
-                    if !config.flag_silence {
+                    if config.verbosity == Verbosity::Normal {
                         let _ = t_err.set_color(ColorSpec::new().set_fg(Some(Color::Blue)));
                         for alert in alerts {
                             let _ = writeln!(t_err, "{}", alert);
+                            logging::record(Level::Warn, alert);
                         }
                         let _ = t_err.reset();
                     }
                 }
 
-                // Printing algorithm stdout
-                if let Some(ref stdout) = response.metadata.stdout {
-                    if config.flag_debug {
+                // Printing algorithm stdout (which --format json includes in its document instead)
+                let stdout = response.metadata.stdout.clone();
+                if let Some(ref stdout) = stdout {
+                    if config.flag_debug && config.format != OutputFormat::Json {
                         let _ = t_err.set_color(ColorSpec::new().set_fg(Some(GRAY)));
                         let _ = writeln!(t_err, "{}", stdout);
+                        logging::record(Level::Debug, stdout);
                         let _ = t_err.reset();
                     }
                 }
 
+                let run_result = RunResult::from(response);
+                // An empty result would otherwise look like nothing happened
+                if is_empty_result(&run_result.result) && config.verbosity == Verbosity::Normal {
+                    let _ = t_err.set_color(ColorSpec::new().set_fg(Some(GRAY)));
+                    let _ = writeln!(t_err, "(algorithm returned empty result)");
+                    logging::record(Level::Info, "(algorithm returned empty result)");
+                    let _ = t_err.reset();
+                }
+                match config.format {
+                    OutputFormat::Env => return display_env(&run_result, output, &config),
+                    OutputFormat::Json => {
+                        let stdout = stdout.filter(|_| config.flag_debug);
+                        return display_json(&run_result, stdout, output, &config);
+                    }
+                    _ => (),
+                }
+
                 // Printing metadata
-                if !config.flag_silence {
+                if config.verbosity == Verbosity::Normal {
                     let _ = t_err.set_color(ColorSpec::new().set_fg(Some(GRAY)));
-                    let _ = writeln!(
-                        t_err,
-                        "Completed in {:.1} seconds",
-                        response.metadata.duration
-                    );
+                    let completed = format!("Completed in {:.1} seconds", run_result.duration);
+                    let _ = writeln!(t_err, "{}", completed);
+                    logging::record(Level::Info, &completed);
                     let _ = t_err.reset();
                 }
 
+                if let Some(ref path) = config.result_jsonpath {
+                    return write_matches(&run_result, path, &mut output, &config);
+                }
+
                 // Smart output of result
-                match response.result.as_string() {
-                    Some(s) => output.writeln(s.as_bytes()),
-                    None => match response.result.to_json() {
-                        Some(j) => output.writeln(j.as_bytes()),
-                        None => output.write(response.result.as_bytes().unwrap()),
-                    }
-                };
+                write_result(&run_result.result, &mut output, &config);
             }
             Err(ref error) if error.api_error().is_some() => {
+                if config.verbosity == Verbosity::Silent {
+                    ::std::process::exit(1);
+                }
                 let err = error.api_error().unwrap();
                 let mut t_err = StandardStream::stderr(color_choice());
                 let _ = t_err.set_color(ColorSpec::new().set_fg(Some(BRIGHT_RED)));
                 let _ = writeln!(t_err, "API error: {}", err.message);
+                logging::record(Level::Error, &format!("API error: {}", err.message));
                 let _ = t_err.reset();
 
                 if let Some(ref trace) = err.stacktrace {
-                    eprintln!("{}", trace)
+                    notice!(Debug, "{}", trace)
                 }
                 ::std::process::exit(1);
             }
@@ -213,12 +765,203 @@ fn display_response(mut response: Response, config: ResponseConfig) {
     }
 }
 
+// The error an algorithm raised, if the response is one that `retry_if` matches (for
+//   --retry-app-errors), as "<type>: <message>" or just the message if it has no type
+fn retry_error(body: &[u8], retry_if: &Regex) -> Option<String> {
+    let body = std::str::from_utf8(body).ok()?;
+    let err = body.parse::<AlgoResponse>().err()?;
+    let api_err = err.api_error()?;
+    let description = match api_err.error_type {
+        Some(ref error_type) => format!("{}: {}", error_type, api_err.message),
+        None => api_err.message.clone(),
+    };
+    if retry_if.is_match(&description) {
+        Some(description)
+    } else {
+        None
+    }
+}
+
+// Prints the result and metadata as KEY=value lines (for --format env)
+//   With --output, the result is still written to the file and ALGO_OUTPUT names it instead
+fn display_env(run_result: &RunResult, mut output: OutputDevice, config: &ResponseConfig) {
+    let mut vars = vec![
+        (
+            "ALGO_CONTENT_TYPE".to_string(),
+            run_result.content_type.clone(),
+        ),
+        ("ALGO_DURATION".to_string(), run_result.duration.to_string()),
+    ];
+    let dest = match config.output_data {
+        Some(ref file) => Some(file.to_data_uri()),
+        None => config.flag_output.clone(),
+    };
+    match dest {
+        Some(dest) => {
+            write_result(&run_result.result, &mut output, config);
+            vars.push(("ALGO_OUTPUT".to_string(), dest));
+        }
+        None => {
+            let result = &run_result.result;
+            let value = match result.as_string() {
+                Some(s) => s.to_string(),
+                None => match result.to_json() {
+                    Some(j) => j,
+                    None => result.as_bytes().unwrap().to_base64(base64::STANDARD),
+                },
+            };
+            vars.push(("ALGO_RESULT".to_string(), value));
+        }
+    }
+    output::print_env(&vars);
+}
+
+// Prints the result, metadata, and algorithm STDOUT (with --debug) as one JSON object
+//   (for --format json), with a binary result base64-encoded. With --output or --output-data,
+//   the result is still written there and "output" names it instead of "result"
+fn display_json(
+    run_result: &RunResult,
+    stdout: Option<String>,
+    mut output: OutputDevice,
+    config: &ResponseConfig,
+) {
+    let mut metadata = BTreeMap::new();
+    metadata.insert(
+        "content_type".to_string(),
+        Json::String(run_result.content_type.clone()),
+    );
+    metadata.insert(
+        "duration".to_string(),
+        Json::F64(f64::from(run_result.duration)),
+    );
+
+    let mut doc = BTreeMap::new();
+    doc.insert("metadata".to_string(), Json::Object(metadata));
+    doc.insert(
+        "stdout".to_string(),
+        stdout.map_or(Json::Null, Json::String),
+    );
+    let dest = match config.output_data {
+        Some(ref file) => Some(file.to_data_uri()),
+        None => config.flag_output.clone(),
+    };
+    match dest {
+        Some(dest) => {
+            write_result(&run_result.result, &mut output, config);
+            doc.insert("output".to_string(), Json::String(dest));
+        }
+        None => {
+            let result = &run_result.result;
+            let value = match result.as_string() {
+                Some(s) => Json::String(s.to_string()),
+                None => match result.to_json() {
+                    Some(j) => Json::from_str(&j)
+                        .unwrap_or_else(|err| quit_msg!("Error parsing JSON result: {}", err)),
+                    None => Json::String(result.as_bytes().unwrap().to_base64(base64::STANDARD)),
+                },
+            };
+            doc.insert("result".to_string(), value);
+        }
+    }
+
+    if let Err(err) = writeln!(io::stdout(), "{}", Json::Object(doc)) {
+        quit_err!("Error writing output: {}", err);
+    }
+}
+
+// Writes the values of a JSON result that --result-jsonpath matches, one per line, with strings
+//   printed as-is and other values as JSON. A result that isn't JSON matches nothing.
+fn write_matches(
+    run_result: &RunResult,
+    path: &JsonPath,
+    output: &mut OutputDevice,
+    config: &ResponseConfig,
+) {
+    let result = match run_result.result.to_json() {
+        Some(json) if run_result.content_type == "json" => Some(
+            Json::from_str(&json)
+                .unwrap_or_else(|err| quit_msg!("Error parsing JSON result: {}", err)),
+        ),
+        _ => {
+            if config.verbosity == Verbosity::Normal {
+                notice!(
+                    Warn,
+                    "The result is {}, not JSON, so --result-jsonpath matches nothing",
+                    run_result.content_type
+                );
+            }
+            None
+        }
+    };
+    let matches = result
+        .as_ref()
+        .map_or_else(Vec::new, |result| path.select(result));
+    for value in &matches {
+        match value.as_string() {
+            Some(s) => output.writeln(s.as_bytes()),
+            None => output.writeln(value.to_string().as_bytes()),
+        }
+    }
+    output.flush();
+    if config.require_match && matches.is_empty() {
+        if config.verbosity == Verbosity::Silent {
+            ::std::process::exit(1);
+        }
+        quit_msg!("--result-jsonpath matched nothing in the result");
+    }
+}
+
+// Whether a result is an empty string, JSON null, or no bytes
+fn is_empty_result(result: &AlgoIo) -> bool {
+    match result.as_string() {
+        Some(s) => s.is_empty(),
+        None => match result.to_json() {
+            Some(j) => j == "null" || j == r#""""#,
+            None => result.as_bytes().map_or(false, |bytes| bytes.is_empty()),
+        },
+    }
+}
+
+// Writes the rendered result to the output device, or its exact bytes to the Data API with
+//   --output-data
+fn write_result(result: &AlgoIo, output: &mut OutputDevice, config: &ResponseConfig) {
+    match config.output_data {
+        Some(ref file) => {
+            if let Err(err) = file.put(result_body(result)) {
+                quit_err!("Error writing result to {}: {}", file.to_data_uri(), err);
+            }
+            if config.verbosity == Verbosity::Normal {
+                notice!(Info, "Wrote result to {}", file.to_data_uri());
+            }
+        }
+        None if config.only_result_body => output.write(&result_body(result)),
+        None => output.write(&render_result(
+            result,
+            config.flag_output_encoding.as_deref(),
+        )),
+    }
+}
+
+// Validates the value of --input-encoding or --output-encoding
+fn check_encoding(encoding: &Option<String>) {
+    match encoding.as_deref() {
+        None | Some("base64") => (),
+        Some(other) => quit_msg!("Unsupported encoding '{}'. Supported: base64", other),
+    }
+}
+
 // separates input-defining args from other args
 fn split_args(argv: IntoIter<String>, usage: &'static str) -> (Vec<InputData>, Vec<String>) {
     let mut input_args: Vec<InputData> = Vec::new();
     let mut other_args: Vec<String> = Vec::new();
+    let mut parts: Vec<(String, InputData)> = Vec::new();
+    let mut form_fields: Vec<(String, String)> = Vec::new();
+    let mut multipart: Vec<MultipartPart> = Vec::new();
 
-    let mut argv_mut = argv.collect::<Vec<String>>().into_iter();
+    let argv = argv.collect::<Vec<String>>();
+    let detect = parse_detect_by(&argv);
+    let allow_large = argv.iter().any(|arg| arg == "--allow-large");
+    let mut argv_mut = argv.into_iter();
     let next_arg = |argv_iter: &mut IntoIter<String>| {
         argv_iter
             .next()
@@ -227,15 +970,23 @@ fn split_args(argv: IntoIter<String>, usage: &'static str) -> (Vec<InputData>, V
     while let Some(flag) = argv_mut.next() {
         match &*flag {
             "-d" | "--data" => {
-                input_args.push(InputData::auto(&mut next_arg(&mut argv_mut).as_bytes()))
+                let data = inline_arg(&flag, next_arg(&mut argv_mut), allow_large);
+                input_args.push(InputData::auto(&mut data.as_bytes()))
+            }
+            "-j" | "--json" => {
+                let data = inline_arg(&flag, next_arg(&mut argv_mut), allow_large);
+                input_args.push(InputData::Json(data))
+            }
+            "-t" | "--text" => {
+                let data = inline_arg(&flag, next_arg(&mut argv_mut), allow_large);
+                input_args.push(InputData::Text(data))
             }
-            "-j" | "--json" => input_args.push(InputData::Json(next_arg(&mut argv_mut))),
-            "-t" | "--text" => input_args.push(InputData::Text(next_arg(&mut argv_mut))),
             "-b" | "--binary" => {
-                input_args.push(InputData::Binary(next_arg(&mut argv_mut).into_bytes()))
+                let data = inline_arg(&flag, next_arg(&mut argv_mut), allow_large);
+                input_args.push(InputData::Binary(data.into_bytes()))
             }
             "-D" | "--data-file" => {
-                input_args.push(InputData::auto(&mut get_src(&next_arg(&mut argv_mut))))
+                input_args.push(InputData::file(&next_arg(&mut argv_mut), detect))
             }
             "-J" | "--json-file" => {
                 input_args.push(InputData::json(&mut get_src(&next_arg(&mut argv_mut))))
@@ -246,16 +997,416 @@ fn split_args(argv: IntoIter<String>, usage: &'static str) -> (Vec<InputData>, V
             "-B" | "--binary-file" => {
                 input_args.push(InputData::binary(&mut get_src(&next_arg(&mut argv_mut))))
             }
+            "--part" => parts.push(parse_part(&next_arg(&mut argv_mut), detect, usage)),
+            "--form" => form_fields.push(parse_form_field(&next_arg(&mut argv_mut), usage)),
+            "--multipart" => multipart.push(parse_multipart_part(
+                &next_arg(&mut argv_mut),
+                detect,
+                usage,
+            )),
             _ => other_args.push(flag),
         };
     }
 
-    // Validating args and options
-    if input_args.len() < 1 {
-        quit_msg!("Must specify an input data option\n\n{}", usage);
-    } else if input_args.len() > 1 {
-        quit_msg!("Multiple input data sources is currently not supported");
+    // All parts are combined into a single input
+    if !parts.is_empty() {
+        input_args.push(InputData::parts(parts));
+    }
+    // As are all form fields
+    if !form_fields.is_empty() {
+        input_args.push(InputData::form(form_fields));
+    }
+    if !multipart.is_empty() {
+        input_args.push(InputData::multipart(multipart));
     }
 
     (input_args, other_args)
 }
+
+// Checks that inline input data isn't accidentally huge (e.g. a pasted file), which should be
+//   read from a file instead unless --allow-large is given
+fn inline_arg(flag: &str, data: String, allow_large: bool) -> String {
+    if data.len() > MAX_INLINE_INPUT && !allow_large {
+        let file_flag = match flag {
+            "-d" | "--data" => "-D",
+            "-j" | "--json" => "-J",
+            "-t" | "--text" => "-T",
+            _ => "-B",
+        };
+        quit_msg!(
+            "The input data given with {} is {}B, over the {}B limit for inline data. \
+             Read it from a file with {} <file> instead, or add --allow-large to send it anyway",
+            flag,
+            size_with_suffix(data.len() as u64),
+            size_with_suffix(MAX_INLINE_INPUT as u64),
+            file_flag
+        );
+    }
+    data
+}
+
+// Finds --detect-by, which is needed before the input options are read
+//   (it is also left in the arguments for Docopt to check)
+fn parse_detect_by(argv: &[String]) -> Detect {
+    let mut detect_by = None;
+    for (i, arg) in argv.iter().enumerate() {
+        if arg == "--detect-by" {
+            detect_by = argv.get(i + 1).map(String::as_str);
+        } else if let Some(value) = arg.strip_prefix("--detect-by=") {
+            detect_by = Some(value);
+        }
+    }
+    match detect_by {
+        None | Some("content") => Detect::Content,
+        Some("extension") => Detect::Extension,
+        Some(other) => quit_msg!(
+            "Unsupported --detect-by '{}'. Supported: content, extension",
+            other
+        ),
+    }
+}
+
+// Parses `<name>=@<file>` (or `<name>=<data>` for inline data) into a named input part
+fn parse_part(arg: &str, detect: Detect, usage: &'static str) -> (String, InputData) {
+    let mut split = arg.splitn(2, '=');
+    match (split.next(), split.next()) {
+        (Some(name), Some(value)) if !name.is_empty() => {
+            let data = match value.strip_prefix('@') {
+                Some(path) => InputData::file(path, detect),
+                None => InputData::auto(&mut value.as_bytes()),
+            };
+            (name.to_string(), data)
+        }
+        _ => quit_msg!(
+            "Invalid part '{}'. Expected <name>=@<file> or <name>=<data>\n\n{}",
+            arg,
+            usage
+        ),
+    }
+}
+
+// Parses `<name>=<value>` (or `<name>=@<file>` to read the value from a file) into a form field
+fn parse_form_field(arg: &str, usage: &'static str) -> (String, String) {
+    let mut split = arg.splitn(2, '=');
+    match (split.next(), split.next()) {
+        (Some(name), Some(value)) if !name.is_empty() => {
+            let value = match value.strip_prefix('@') {
+                Some(path) => {
+                    let mut contents = String::new();
+                    if let Err(err) = get_src(path).read_to_string(&mut contents) {
+                        quit_err!("Error reading form field '{}': {}", name, err);
+                    }
+                    contents
+                }
+                None => value.to_string(),
+            };
+            (name.to_string(), value)
+        }
+        _ => quit_msg!(
+            "Invalid form field '{}'. Expected <name>=<value> or <name>=@<file>\n\n{}",
+            arg,
+            usage
+        ),
+    }
+}
+
+// Parses `<name>=@<file>` or `<name>=<value>` into a multipart/form-data part, followed by
+//   optional `;type=<mime>` and `;filename=<name>` parameters
+//   A file part is named after the file and typed like --part @<file> unless they're given
+fn parse_multipart_part(arg: &str, detect: Detect, usage: &'static str) -> MultipartPart {
+    let invalid = || -> ! {
+        quit_msg!(
+            "Invalid multipart part '{}'. Expected <name>=@<file> or <name>=<value>, \
+             optionally followed by ;type=<mime> and ;filename=<name>\n\n{}",
+            arg,
+            usage
+        )
+    };
+
+    // Parameters are taken off the end, so that a value may contain ';'
+    let mut spec = arg;
+    let (mut content_type, mut filename) = (None, None);
+    while let Some(i) = spec.rfind(';') {
+        let param = &spec[i + 1..];
+        if let Some(mime) = param.strip_prefix("type=") {
+            let mime = mime.parse::<mime::Mime>().unwrap_or_else(|_| invalid());
+            content_type = content_type.or(Some(mime));
+        } else if let Some(name) = param.strip_prefix("filename=") {
+            filename = filename.or_else(|| Some(name.to_string()));
+        } else {
+            break;
+        }
+        spec = &spec[..i];
+    }
+
+    let mut split = spec.splitn(2, '=');
+    let (name, value) = match (split.next(), split.next()) {
+        (Some(name), Some(value)) if !name.is_empty() => (name, value),
+        _ => invalid(),
+    };
+    let data = if let Some(path) = value.strip_prefix('@') {
+        let input = InputData::file(path, detect);
+        let file_name = Path::new(path).file_name().filter(|_| path != "-");
+        filename = filename.or_else(|| file_name.map(|f| f.to_string_lossy().into_owned()));
+        content_type = content_type.or_else(|| Some(input.content_type()));
+        input.into_bytes()
+    } else {
+        value.as_bytes().to_vec()
+    };
+    MultipartPart {
+        name: name.to_string(),
+        filename,
+        content_type,
+        data,
+    }
+}
+
+// Parses a timeout like "90", "90s", "5m", or "1h30m" into seconds (plain numbers are seconds)
+fn parse_timeout(input: &str) -> Result<u32, String> {
+    let trimmed = input.trim();
+    let invalid = || format!("invalid duration '{}'", input);
+    if let Ok(secs) = trimmed.parse::<u32>() {
+        return Ok(secs);
+    }
+    if trimmed.is_empty() {
+        return Err(invalid());
+    }
+
+    let mut total = 0u64;
+    let mut number = String::new();
+    // Units must be in order (h, then m, then s) and each may appear once
+    let mut last_unit = None;
+    for c in trimmed.chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+        let (rank, multiplier) = match c {
+            'h' => (0, 3600),
+            'm' => (1, 60),
+            's' => (2, 1),
+            _ => return Err(invalid()),
+        };
+        if number.is_empty() || last_unit.map_or(false, |last| last >= rank) {
+            return Err(invalid());
+        }
+        last_unit = Some(rank);
+        let n = number.parse::<u64>().map_err(|_| invalid())?;
+        total = total.saturating_add(n.saturating_mul(multiplier));
+        number.clear();
+    }
+    if !number.is_empty() {
+        return Err(invalid());
+    }
+    if total > u64::from(u32::MAX) {
+        return Err(format!("duration '{}' is too long", input));
+    }
+    Ok(total as u32)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_combine_parts() {
+        let parts = vec![
+            (
+                "params".to_string(),
+                InputData::Json(r#"{"size":[64,64]}"#.into()),
+            ),
+            ("name".to_string(), InputData::Text("kitten".into())),
+            (
+                "image".to_string(),
+                InputData::Binary(vec![0xff, 0xd8, 0xff]),
+            ),
+        ];
+        match InputData::parts(parts) {
+            InputData::Json(json) => assert_eq!(
+                json,
+                r#"{"image":"/9j/","name":"kitten","params":{"size":[64,64]}}"#
+            ),
+            other => panic!("Expected JSON input, received {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_form() {
+        let fields = vec![
+            ("q".to_string(), "cute kittens".to_string()),
+            ("tag".to_string(), "a&b=c".to_string()),
+            ("tag".to_string(), "é".to_string()),
+        ];
+        match InputData::form(fields) {
+            InputData::Form(body) => assert_eq!(body, "q=cute+kittens&tag=a%26b%3Dc&tag=%C3%A9"),
+            other => panic!("Expected form input, received {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_multipart() {
+        let parts = vec![
+            MultipartPart {
+                name: "image".to_string(),
+                filename: Some("kitten \"1\".png".to_string()),
+                content_type: Some(mime::IMAGE_PNG),
+                data: vec![0x89, b'P', b'N', b'G'],
+            },
+            MultipartPart {
+                name: "caption".to_string(),
+                filename: None,
+                content_type: None,
+                data: b"see --mia-form-boundary-0".to_vec(),
+            },
+        ];
+        let input = InputData::multipart(parts);
+        assert_eq!(
+            input.content_type().to_string(),
+            "multipart/form-data; boundary=mia-form-boundary-1"
+        );
+        let mut expected = b"--mia-form-boundary-1\r\n\
+            Content-Disposition: form-data; name=\"image\"; filename=\"kitten %221%22.png\"\r\n\
+            Content-Type: image/png\r\n\r\n\x89PNG\r\n"
+            .to_vec();
+        expected.extend_from_slice(
+            b"--mia-form-boundary-1\r\n\
+            Content-Disposition: form-data; name=\"caption\"\r\n\r\n\
+            see --mia-form-boundary-0\r\n\
+            --mia-form-boundary-1--\r\n",
+        );
+        assert_eq!(input.into_bytes(), expected);
+    }
+
+    #[test]
+    fn test_parse_multipart_part() {
+        let part = parse_multipart_part("q=a;b;type=text/csv;filename=q.csv", Detect::Content, "");
+        assert_eq!(part.name, "q");
+        assert_eq!(part.data, b"a;b");
+        assert_eq!(part.content_type, Some(mime::TEXT_CSV));
+        assert_eq!(part.filename.as_deref(), Some("q.csv"));
+    }
+
+    #[test]
+    fn test_algorithm_id() {
+        let id = |algo| algorithm_id(algo).unwrap();
+        assert_eq!(id("nlp/Summarizer/0.1"), "nlp/Summarizer/0.1");
+        assert_eq!(id("algo://nlp/Summarizer"), "nlp/Summarizer");
+        assert_eq!(
+            id("https://algorithmia.com/algorithms/nlp/Summarizer/0.1.2?tab=docs"),
+            "nlp/Summarizer/0.1.2"
+        );
+        assert_eq!(
+            id("https://api.algorithmia.com/v1/algo/nlp/Summarizer/"),
+            "nlp/Summarizer"
+        );
+        assert!(algorithm_id("https://algorithmia.com/algorithms/nlp").is_err());
+        assert!(algorithm_id("https://algorithmia.com/nlp/Summarizer").is_err());
+    }
+
+    #[test]
+    fn test_combine_merge() {
+        let inputs = vec![
+            InputData::Json(r#"{"a":1,"opts":{"x":1,"y":[1]}}"#.into()),
+            InputData::Json(r#"{"b":2,"opts":{"y":[2],"z":3}}"#.into()),
+        ];
+        match InputData::combine(inputs, "merge") {
+            InputData::Json(json) => {
+                assert_eq!(json, r#"{"a":1,"b":2,"opts":{"x":1,"y":[2],"z":3}}"#)
+            }
+            other => panic!("Expected JSON input, received {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_from_template() {
+        let uri = "data://.my/mia-tmp/a \"b\".png";
+        match InputData::from_template(r#"{"image":"{{uri}}","n":1}"#, uri) {
+            InputData::Json(json) => {
+                assert_eq!(json, r#"{"image":"data://.my/mia-tmp/a \"b\".png","n":1}"#)
+            }
+            other => panic!("Expected JSON input, received {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_parse_detect_by() {
+        let args = |args: &[&str]| args.iter().map(|a| a.to_string()).collect::<Vec<_>>();
+        assert_eq!(parse_detect_by(&args(&["-D", "a.json"])), Detect::Content);
+        assert_eq!(
+            parse_detect_by(&args(&["--detect-by", "extension", "-D", "a.json"])),
+            Detect::Extension
+        );
+        assert_eq!(
+            parse_detect_by(&args(&["-D", "a.json", "--detect-by=content"])),
+            Detect::Content
+        );
+    }
+
+    #[test]
+    fn test_parse_timeout() {
+        assert_eq!(parse_timeout("90"), Ok(90));
+        assert_eq!(parse_timeout("90s"), Ok(90));
+        assert_eq!(parse_timeout("5m"), Ok(300));
+        assert_eq!(parse_timeout("1h30m"), Ok(5400));
+        assert_eq!(parse_timeout("1h0m15s"), Ok(3615));
+        assert!(parse_timeout("").is_err());
+        assert!(parse_timeout("m").is_err());
+        assert!(parse_timeout("30m1h").is_err());
+        assert!(parse_timeout("1h30").is_err());
+        assert!(parse_timeout("5d").is_err());
+        assert!(parse_timeout("-5").is_err());
+    }
+
+    #[test]
+    fn test_retry_error() {
+        let retry_if = Regex::new("timed out|Unavailable").unwrap();
+        let error = |err_type: &str, message: &str| {
+            format!(
+                r#"{{"error":{{"error_type":"{}","message":"{}"}}}}"#,
+                err_type, message
+            )
+        };
+        assert_eq!(
+            retry_error(
+                error("AlgorithmError", "db timed out").as_bytes(),
+                &retry_if
+            ),
+            Some("AlgorithmError: db timed out".to_string())
+        );
+        assert!(retry_error(error("ServiceUnavailable", "busy").as_bytes(), &retry_if).is_some());
+        assert!(
+            retry_error(error("InputError", "expected a list").as_bytes(), &retry_if).is_none()
+        );
+        let success = r#"{"result":"timed out","metadata":{"content_type":"text","duration":0.1}}"#;
+        assert!(retry_error(success.as_bytes(), &retry_if).is_none());
+    }
+
+    #[test]
+    fn test_is_empty_result() {
+        let result = |result: &str, content_type: &str| {
+            let body = format!(
+                r#"{{"result":{},"metadata":{{"content_type":"{}","duration":0.1}}}}"#,
+                result, content_type
+            );
+            body.parse::<AlgoResponse>().unwrap().result
+        };
+        assert!(is_empty_result(&result(r#""""#, "text")));
+        assert!(is_empty_result(&result("null", "json")));
+        assert!(is_empty_result(&result(r#""""#, "json")));
+        assert!(is_empty_result(&result(r#""""#, "binary")));
+        assert!(!is_empty_result(&result(r#"" ""#, "text")));
+        assert!(!is_empty_result(&result("0", "json")));
+        assert!(!is_empty_result(&result(r#""AA==""#, "binary")));
+    }
+
+    #[test]
+    fn test_parse_inline_part() {
+        match parse_part("params={\"k\":3}", Detect::Content, "") {
+            (name, InputData::Json(json)) => {
+                assert_eq!(name, "params");
+                assert_eq!(json, r#"{"k":3}"#);
+            }
+            other => panic!("Expected JSON part, received {:?}", other),
+        }
+    }
+}
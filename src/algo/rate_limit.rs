@@ -0,0 +1,95 @@
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Token bucket limiting how often algorithm calls are initiated
+// Shared (via Arc) by any workers making calls so the limit applies to the whole invocation
+pub struct RateLimiter {
+    per_sec: f64,
+    capacity: f64,
+    bucket: Mutex<Bucket>,
+}
+
+struct Bucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(per_sec: f64) -> RateLimiter {
+        // Allow bursting up to one second worth of calls (but always at least one call)
+        let capacity = per_sec.max(1.0);
+        RateLimiter {
+            per_sec,
+            capacity,
+            bucket: Mutex::new(Bucket {
+                tokens: capacity,
+                last_refill: Instant::now(),
+            }),
+        }
+    }
+
+    // Blocks until a call may be initiated
+    pub fn acquire(&self) {
+        loop {
+            let wait = {
+                let mut bucket = self.bucket.lock().unwrap();
+                let now = Instant::now();
+                let elapsed = now.duration_since(bucket.last_refill).as_secs_f64();
+                bucket.tokens = (bucket.tokens + elapsed * self.per_sec).min(self.capacity);
+                bucket.last_refill = now;
+
+                if bucket.tokens >= 1.0 {
+                    bucket.tokens -= 1.0;
+                    return;
+                }
+                (1.0 - bucket.tokens) / self.per_sec
+            };
+            thread::sleep(Duration::from_secs_f64(wait));
+        }
+    }
+}
+
+// Parses a rate like "5", "5/s", "5/sec", or "0.5/s" into calls per second
+pub fn parse_rate(input: &str) -> Result<f64, String> {
+    let trimmed = input.trim();
+    let number = match trimmed.find('/') {
+        Some(i) => match &trimmed[i + 1..] {
+            "s" | "sec" | "second" => &trimmed[..i],
+            unit => return Err(format!("unsupported rate unit '{}'", unit)),
+        },
+        None => trimmed,
+    };
+    match number.parse::<f64>() {
+        Ok(rate) if rate > 0.0 && rate.is_finite() => Ok(rate),
+        _ => Err(format!("invalid rate '{}'", input)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_rate() {
+        assert_eq!(parse_rate("5"), Ok(5.0));
+        assert_eq!(parse_rate("5/s"), Ok(5.0));
+        assert_eq!(parse_rate("5/sec"), Ok(5.0));
+        assert_eq!(parse_rate("0.5/s"), Ok(0.5));
+        assert!(parse_rate("0").is_err());
+        assert!(parse_rate("-1").is_err());
+        assert!(parse_rate("5/min").is_err());
+        assert!(parse_rate("fast").is_err());
+    }
+
+    #[test]
+    fn test_rate_limiter_throttles() {
+        let limiter = RateLimiter::new(20.0);
+        let start = Instant::now();
+        // The first 20 calls are a burst, the next 10 must wait ~0.5s
+        for _ in 0..30 {
+            limiter.acquire();
+        }
+        assert!(start.elapsed() >= Duration::from_millis(400));
+    }
+}
@@ -0,0 +1,147 @@
+// The state file of `mia run --stream --resume-token <file>`, so that an interrupted batch
+//   can be re-run without calling the algorithm again for the lines that already succeeded
+//
+// Each input line's status is appended as one JSON object per line as its result comes in
+//   (so a crash loses at most the calls in flight), and a line's last entry wins, for example:
+//   {"line":3,"input_hash":"af63df4c8601f1a5","status":"ok","error":null}
+//   {"line":4,"input_hash":"af63de4c8601eff2","status":"failed","error":"..."}
+// A line only counts as done if its input is unchanged, so re-running with a different STDIN
+//   doesn't skip the wrong lines
+
+use rustc_serialize::json;
+use std::collections::HashSet;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+
+#[derive(RustcEncodable, RustcDecodable)]
+struct Entry {
+    line: usize,
+    input_hash: String,
+    status: String,
+    error: Option<String>,
+}
+
+// The input lines that succeeded in earlier runs, by line number and input hash
+pub struct Completed(HashSet<(usize, String)>);
+
+pub struct ResumeLog {
+    path: String,
+    file: File,
+}
+
+// Reads the state file (if it exists yet), then opens it to append to
+pub fn open(path: &str) -> Result<(Completed, ResumeLog), String> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(ref err) if err.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(err) => return Err(format!("Error reading {}: {}", path, err)),
+    };
+    let (completed, unreadable) = parse(&contents);
+    if unreadable > 0 {
+        notice!(
+            Warn,
+            "Ignoring {} unreadable line(s) in {}",
+            unreadable,
+            path
+        );
+    }
+
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|err| format!("Error opening {}: {}", path, err))?;
+    // Finish a line cut short by a crash, so the next entry starts on its own line
+    if !contents.is_empty() && !contents.ends_with('\n') {
+        writeln!(file).map_err(|err| format!("Error writing {}: {}", path, err))?;
+    }
+    Ok((
+        completed,
+        ResumeLog {
+            path: path.to_string(),
+            file,
+        },
+    ))
+}
+
+fn parse(contents: &str) -> (Completed, usize) {
+    let mut done = HashSet::new();
+    let mut unreadable = 0;
+    for line in contents.lines().filter(|line| !line.trim().is_empty()) {
+        match json::decode::<Entry>(line) {
+            Ok(entry) if entry.status == "ok" => {
+                done.insert((entry.line, entry.input_hash));
+            }
+            Ok(entry) => {
+                done.remove(&(entry.line, entry.input_hash));
+            }
+            Err(_) => unreadable += 1,
+        }
+    }
+    (Completed(done), unreadable)
+}
+
+impl Completed {
+    pub fn contains(&self, line_no: usize, input: &str) -> bool {
+        self.0.contains(&(line_no, input_hash(input)))
+    }
+}
+
+impl ResumeLog {
+    // Records how an input line went, with the error if it failed
+    pub fn record(&mut self, line_no: usize, input_hash: String, error: Option<&str>) {
+        let entry = Entry {
+            line: line_no,
+            input_hash,
+            status: if error.is_some() { "failed" } else { "ok" }.to_string(),
+            error: error.map(String::from),
+        };
+        let line = json::encode(&entry).expect("resume entry is encodable");
+        if let Err(err) = writeln!(self.file, "{}", line).and_then(|_| self.file.flush()) {
+            quit_msg!("Error writing {}: {}", self.path, err);
+        }
+    }
+}
+
+// A hash of an input line that is the same across runs and mia versions (64-bit FNV-1a)
+pub fn input_hash(input: &str) -> String {
+    let hash = input.bytes().fold(0xcbf2_9ce4_8422_2325u64, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    });
+    format!("{:016x}", hash)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let entry = |line, input: &str, status| {
+            format!(
+                r#"{{"line":{},"input_hash":"{}","status":"{}","error":null}}"#,
+                line,
+                input_hash(input),
+                status
+            )
+        };
+        let contents = [
+            entry(1, "a", "ok"),
+            entry(2, "b", "failed"),
+            entry(3, "c", "ok"),
+            entry(3, "c", "failed"),
+            entry(4, "d", "failed"),
+            entry(4, "d", "ok"),
+            r#"{"line":5,"input_"#.to_string(),
+        ]
+        .join("\n");
+        let (completed, unreadable) = parse(&contents);
+        assert_eq!(unreadable, 1);
+        assert!(completed.contains(1, "a"));
+        assert!(!completed.contains(1, "changed"));
+        assert!(!completed.contains(2, "b"));
+        assert!(!completed.contains(3, "c"));
+        assert!(completed.contains(4, "d"));
+        assert_eq!(input_hash(""), "cbf29ce484222325");
+    }
+}
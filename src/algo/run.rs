@@ -1,15 +1,45 @@
-use super::{display_response, split_args, InputData, ResponseConfig};
+use super::clipboard;
+use super::jsonpath::JsonPath;
+use super::rate_limit::{self, RateLimiter};
+use super::schema;
+use super::stream::{self, StreamConfig};
+use super::{
+    algorithm_id, call_algorithm, check_encoding, copy_options, display_body, display_response,
+    parse_timeout, read_response, retry_error, split_args, InputData, ResponseConfig, RunResult,
+    Verbosity,
+};
 use crate::config::Profile;
-use crate::CmdRunner;
-use algorithmia::algo::{AlgoOptions, Response};
+use crate::deadline::Deadline;
+use crate::output::OutputFormat;
+use crate::{data, CmdRunner};
+use algorithmia::algo::{AlgoOptions, AlgoResponse};
+use algorithmia::data::{DataAcl, DataFile, HasDataPath};
 use algorithmia::Algorithmia;
+use chrono::Utc;
 use docopt::Docopt;
+use regex::Regex;
+use reqwest::StatusCode;
+use rustc_serialize::json::Json;
+use std::fs::File;
+use std::io::{Cursor, Read};
+use std::path::Path;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
 use std::vec::IntoIter;
 
+// Where --upload-input files are uploaded
+static UPLOAD_DIR: &str = "data://.my/mia-tmp";
+
+// How much of a text input --dry-run prints
+const DRY_RUN_PREVIEW_CHARS: usize = 200;
+
 static USAGE: &'static str = r##"Usage:
   mia run [options] <algorithm>
 
   <algorithm> syntax: USERNAME/ALGONAME[/VERSION]
+    (or as algo://USERNAME/ALGONAME[/VERSION], or the algorithm's URL such as
+    https://algorithmia.com/algorithms/USERNAME/ALGONAME/VERSION)
   Recommend specifying a version since algorithm costs can change between minor versions.
 
   Input Data Options:
@@ -20,6 +50,10 @@ static USAGE: &'static str = r##"Usage:
       -d, --data <data>             If the data parses as JSON, assume JSON, else if the data
                                       is valid UTF-8, assume text, else assume binary
       -D, --data-file <file>        Same as --data, but the input data is read from a file
+      --detect-by <strategy>        How -D and --part @<file> detect the type of a file:
+                                      'content' (default), or 'extension' to read .json as
+                                      JSON, .txt, .csv, etc. as text, and .png, .jpg, etc. as
+                                      binary (other extensions are detected by content)
 
     JSON Data:
       -j, --json <data>             Algorithm input data as JSON (application/json)
@@ -32,6 +66,48 @@ static USAGE: &'static str = r##"Usage:
     Binary Data:
       -b, --binary <data>           Algorithm input data as binary (application/octet-stream)
       -B, --binary-file <file>      Same as --data, but the input data is read from a file
+      --input-encoding <enc>        Decode input data before sending it as binary (enc: base64)
+
+    Composite Data:
+      --part <part>                 Adds a named part to a JSON object input, where <part> is
+                                      <name>=@<file> (read and auto-detected like --data-file)
+                                      or <name>=<data> (inline). Binary parts are base64-encoded.
+                                      Repeat to add more parts.
+      --form <field>                Adds a field to a URL-encoded form input (sent as
+                                      application/x-www-form-urlencoded), where <field> is
+                                      <name>=<value> or <name>=@<file> to read the value from a
+                                      file. Repeat to add more fields.
+      --multipart <part>            Adds a part to a multipart/form-data input, where <part> is
+                                      <name>=@<file> (sent with the file's name, and typed like
+                                      a data file) or <name>=<value>, optionally followed by
+                                      ;type=<mime> and ;filename=<name>. Repeat to add more parts.
+      --combine <strategy>          Combine several JSON inputs (e.g. -J a.json -J b.json) into
+                                      one input for a single call. <strategy> is 'array' for an
+                                      array of the inputs, or 'merge' to deep-merge objects
+
+    Remote Data:
+      --input-data <uri>            Read the input data from a file in the Data API, detecting
+                                      its type like --data (the 'data://' prefix is optional)
+
+    Clipboard Data:
+      --from-clipboard              Read the input data from the system clipboard, detecting its
+                                      type like --data (using pbpaste on macOS, PowerShell on
+                                      Windows, or wl-paste, xclip, or xsel elsewhere)
+
+    Large Data:
+      --allow-large                 Send inline input data (-d, -j, -t, or -b) over 64kB, which is
+                                      otherwise an error suggesting a file option like -D
+
+    Uploaded Data:
+      --upload-input <file>         Upload a local file to data://.my/mia-tmp/ and send its data
+                                      URI as JSON input (instead of an input data option)
+      --input-template <json>       JSON input to send with --upload-input, where {{uri}} is
+                                      replaced by the data URI [Default: "{{uri}}"]
+      --cleanup                     Delete the uploaded file once the algorithm call completes
+
+    Input Validation:
+      --schema <file>               Validate JSON input against a JSON Schema before calling the
+                                      algorithm, aborting with the validation errors if invalid
 
 
   Output Options:
@@ -42,17 +118,78 @@ static USAGE: &'static str = r##"Usage:
     --no-debug                      Don't print algorithm's STDOUT (default)
     --response-body                 Print HTTP response body (replaces result)
     --response                      Print full HTTP response including headers (replaces result)
-    -s, --silence                   Suppress any output not explicitly requested (except result)
+    -q, --quiet                     Suppress notices (alerts, timing, algorithm's STDOUT) but
+                                      still print errors
+    -s, --silence                   Suppress any output not explicitly requested (except result),
+                                      including errors (failures still exit with an error)
+    --only-result-body              Print exactly the bytes of the result, without a trailing
+                                      newline or any other framing (implies --silence), so that
+                                      redirecting it to a file is byte-for-byte
     -o, --output <file>             Print result to a file
+    --output-data <data-file>       Write result to a file in the Algorithmia Data API instead
+                                      (e.g. data://.my/out.json), storing the result's exact bytes
+    --response-meta <file>          Write HTTP status and headers as JSON to a file
+    --output-encoding <enc>         Encode a binary result before printing it (enc: base64)
+    --max-output-bytes <size>       Abort reading a response larger than <size> (e.g. 10M)
+    --show-ttfb                     Print the time from starting the request to the first byte
+                                      of the response, and to reading all of it, to STDERR
+    --show-metadata                 Print the size of the input sent and of the response
+                                      received, and the throughput of the call, to STDERR
+    --format <format>               Output format: 'text' (default), 'env' to print the result
+                                      and metadata as shell-escaped KEY=value lines, or 'json'
+                                      to print one object with the result (base64 if binary),
+                                      metadata, and the algorithm's STDOUT (with --debug)
+    --result-jsonpath <path>        Print only the values of a JSON result matching a JSONPath
+                                      (e.g. '$.data.value' or '$.items[*].id'), one per line
+    --require-match                 Exit with an error if --result-jsonpath matches nothing
+
+  Stream Options:
+    --stream                        Call the algorithm once per line of STDIN (instead of using an
+                                      input data option), printing results in input order
+    --stdin-json-lines              Like --stream for JSONL: each line of STDIN must be a JSON
+                                      input, and each result is printed as one line of JSON
+    -c <CONCURRENCY>                Number of concurrent calls with --stream [Default: 4]
+    --deadline <seconds>            Stop starting new calls after <seconds>, then summarize and
+                                      exit with an error. Calls in progress get 5 more seconds
+                                      to finish before they are cut off
+    --resume-token <file>           Record each input line's status in <file>, and skip the lines
+                                      that already succeeded when re-run with the same <file>
+                                      (printing nothing for them, so append with >> to keep the
+                                      earlier run's results)
 
   Other Options:
-    --timeout <seconds>             Sets algorithm timeout
+    --dry-run                       Check the API key and build the input, then print the request
+                                      that would be sent instead of calling the algorithm
+    --timeout <duration>            Sets algorithm timeout, in seconds or with units
+                                      (e.g. 90, 90s, 5m, 1h30m)
+    --rate-limit <rate>             Limits how often algorithm calls are started (e.g. 5/s)
+                                      when multiple calls are made
+    --retry-app-errors <n>          Retry up to <n> times when the algorithm raises an error
+                                      matching --retry-if (waiting 1s, 2s, 4s, ... between
+                                      attempts). Other errors, such as for invalid input, are
+                                      not retried [Default: 0]
+    --retry-if <regex>              Pattern for errors to retry, matched against the error's
+                                      type and message as "<type>: <message>"
 
   Examples:
     mia run kenny/factor/0.1.0 -d '79'                   Run algorithm with specified data input
     mia run anowell/Dijkstra -D routes.json              Run algorithm with file input
     mia run anowell/Dijkstra -D - < routes.json          Same as above but using STDIN
     mia run opencv/SmartThumbnail -D in.png -o out.png   Run algorithm saving output to a file
+    mia run opencv/SmartThumbnail -D in.png --output-data data://.my/img/out.png
+                                                         Run algorithm saving output to a data file
+    mia run nlp/Sentiment --stream < lines.txt           Run algorithm on each line of a file
+    mia run nlp/Sentiment --stream --resume-token state.json < lines.txt >> out.txt
+                                                         Same as above, restartable if interrupted
+    jq -c '.[]' docs.json | mia run nlp/Summarize --stdin-json-lines | jq .summary
+                                                         Run algorithm on each JSON line, as JSONL
+    mia run nlp/Caption --part image=@img.png --part params=@cfg.json
+                                                         Run algorithm with a JSON object of parts
+    mia run web/Search --form q=kittens --form limit=10  Run algorithm with form-encoded input
+    mia run nlp/Summarize -J a.json -J b.json --combine array
+                                                         Run algorithm once with an array of inputs
+    mia run opencv/SmartThumbnail --upload-input big.png --input-template '{"image":"{{uri}}"}'
+                                                         Run algorithm on an uploaded local file
 "##;
 
 #[derive(RustcDecodable, Debug)]
@@ -61,15 +198,45 @@ struct Args {
     arg_algorithm: String,
     flag_response_body: bool,
     flag_response: bool,
+    flag_quiet: bool,
     flag_silence: bool,
+    flag_only_result_body: bool,
     flag_debug: bool,
     flag_no_debug: bool,
     flag_output: Option<String>,
-    flag_timeout: Option<u32>,
+    flag_output_data: Option<String>,
+    flag_response_meta: Option<String>,
+    flag_timeout: Option<String>,
+    flag_rate_limit: Option<String>,
+    flag_retry_app_errors: u32,
+    flag_retry_if: Option<String>,
+    flag_input_encoding: Option<String>,
+    flag_output_encoding: Option<String>,
+    flag_max_output_bytes: Option<String>,
+    flag_show_ttfb: bool,
+    flag_show_metadata: bool,
+    flag_dry_run: bool,
+    flag_format: Option<String>,
+    flag_result_jsonpath: Option<String>,
+    flag_require_match: bool,
+    flag_schema: Option<String>,
+    flag_combine: Option<String>,
+    flag_input_data: Option<String>,
+    flag_from_clipboard: bool,
+    flag_upload_input: Option<String>,
+    flag_input_template: String,
+    flag_cleanup: bool,
+    flag_stream: bool,
+    flag_stdin_json_lines: bool,
+    flag_c: usize,
+    flag_deadline: Option<u64>,
+    flag_resume_token: Option<String>,
 }
 
 pub struct Run {
     client: Algorithmia,
+    base_path: Option<String>,
+    output_format: Option<String>,
 }
 impl CmdRunner for Run {
     fn get_usage() -> &'static str {
@@ -84,30 +251,336 @@ impl CmdRunner for Run {
         let args: Args = Docopt::new(USAGE)
             .and_then(|d| d.argv(other_args).decode())
             .unwrap_or_else(|e| e.exit());
+        let algorithm =
+            algorithm_id(&args.arg_algorithm).unwrap_or_else(|err| quit_msg!("{}", err));
+        let streaming = args.flag_stream || args.flag_stdin_json_lines;
+        // The streaming option to name in errors
+        let stream_flag = if args.flag_stdin_json_lines {
+            "--stdin-json-lines"
+        } else {
+            "--stream"
+        };
 
-        // --debug can override --silence, but the lack of --debug respects --silence
-        let debug = args.flag_debug || !(args.flag_no_debug || args.flag_silence);
+        check_encoding(&args.flag_input_encoding);
+        check_encoding(&args.flag_output_encoding);
+
+        if let Some(ref path) = args.flag_input_data {
+            input_args.push(self.input_from_data(path));
+        }
+        if args.flag_from_clipboard {
+            let bytes = clipboard::read().unwrap_or_else(|err| quit_msg!("{}", err));
+            input_args.push(InputData::auto(&mut Cursor::new(bytes)));
+        }
+        if let Some(ref strategy) = args.flag_combine {
+            if !input_args.is_empty() {
+                input_args = vec![InputData::combine(input_args, strategy)];
+            }
+        }
+
+        if args.flag_upload_input.is_some() {
+            if streaming || !input_args.is_empty() {
+                quit_msg!(
+                    "--upload-input cannot be combined with {} or input data options",
+                    stream_flag
+                );
+            }
+            // Check the template before anything is uploaded
+            InputData::from_template(&args.flag_input_template, "data://.my/mia-tmp/input");
+        } else if args.flag_cleanup {
+            quit_msg!("--cleanup requires --upload-input");
+        }
+
+        // Validating input options
+        if streaming {
+            if !input_args.is_empty() {
+                quit_msg!(
+                    "{} reads input from STDIN and cannot be used with input data options",
+                    stream_flag
+                );
+            }
+        } else if input_args.is_empty() && args.flag_upload_input.is_none() {
+            quit_msg!("Must specify an input data option\n\n{}", USAGE);
+        } else if input_args.len() > 1 {
+            quit_msg!(
+                "Multiple input data sources is currently not supported \
+                 (use --combine to combine JSON inputs)"
+            );
+        }
+
+        let verbosity = if args.flag_silence || args.flag_only_result_body {
+            Verbosity::Silent
+        } else if args.flag_quiet {
+            Verbosity::Quiet
+        } else {
+            Verbosity::Normal
+        };
+
+        // --debug can override --quiet and --silence, but the lack of --debug respects them
+        let debug = args.flag_debug || !(args.flag_no_debug || verbosity != Verbosity::Normal);
 
         let mut opts = AlgoOptions::default();
         if debug {
             opts.stdout(true);
         }
-        if let Some(timeout) = args.flag_timeout {
-            opts.timeout(timeout);
+        if let Some(ref timeout) = args.flag_timeout {
+            let secs = parse_timeout(timeout)
+                .unwrap_or_else(|err| quit_msg!("Invalid --timeout: {}", err));
+            opts.timeout(secs);
+        }
+
+        let limiter = args.flag_rate_limit.map(|rate| {
+            rate_limit::parse_rate(&rate)
+                .map(|r| Arc::new(RateLimiter::new(r)))
+                .unwrap_or_else(|err| quit_msg!("Invalid --rate-limit: {}", err))
+        });
+
+        let max_output_bytes = args.flag_max_output_bytes.map(|size| {
+            data::parse_size(&size)
+                .unwrap_or_else(|err| quit_msg!("Invalid --max-output-bytes: {}", err))
+        });
+
+        let retry_if = match (args.flag_retry_app_errors, &args.flag_retry_if) {
+            (0, None) => None,
+            (0, Some(_)) => quit_msg!("--retry-if requires --retry-app-errors"),
+            (_, None) => quit_msg!("--retry-app-errors requires --retry-if"),
+            (_, Some(pattern)) => Some(
+                Regex::new(pattern).unwrap_or_else(|err| quit_msg!("Invalid --retry-if: {}", err)),
+            ),
+        };
+
+        let format = OutputFormat::resolve(
+            args.flag_format.as_deref(),
+            self.output_format.as_deref(),
+            &[OutputFormat::Text, OutputFormat::Env, OutputFormat::Json],
+        );
+        // A profile's output_format = "json" is meant for ls, so run only prints its combined
+        //   JSON document with an explicit --format json
+        let format = match format {
+            OutputFormat::Json if args.flag_format.is_none() => OutputFormat::Text,
+            format => format,
+        };
+
+        if args.flag_only_result_body {
+            if streaming {
+                quit_msg!("--only-result-body is not supported with {}", stream_flag);
+            }
+            if args.flag_response || args.flag_response_body {
+                quit_msg!("--only-result-body cannot be used with --response or --response-body");
+            }
+            if args.flag_output_encoding.is_some() {
+                quit_msg!("--only-result-body cannot be used with --output-encoding");
+            }
+            if args.flag_format.is_some() && format != OutputFormat::Text {
+                quit_msg!("--only-result-body cannot be used with --format");
+            }
+        }
+        // A profile's output_format doesn't apply to --only-result-body
+        let format = if args.flag_only_result_body {
+            OutputFormat::Text
+        } else {
+            format
+        };
+
+        let result_jsonpath = args.flag_result_jsonpath.as_ref().map(|path| {
+            JsonPath::parse(path)
+                .unwrap_or_else(|err| quit_msg!("Invalid --result-jsonpath: {}", err))
+        });
+        if result_jsonpath.is_some() {
+            if streaming || args.flag_only_result_body || args.flag_output_data.is_some() {
+                quit_msg!(
+                    "--result-jsonpath cannot be used with {}, --only-result-body, \
+                     or --output-data",
+                    stream_flag
+                );
+            }
+            if args.flag_response || args.flag_response_body {
+                quit_msg!("--result-jsonpath cannot be used with --response or --response-body");
+            }
+            if args.flag_format.is_some() && format != OutputFormat::Text {
+                quit_msg!("--result-jsonpath cannot be used with --format");
+            }
+        } else if args.flag_require_match {
+            quit_msg!("--require-match requires --result-jsonpath");
         }
+        // A profile's output_format doesn't apply to --result-jsonpath
+        let format = if result_jsonpath.is_some() {
+            OutputFormat::Text
+        } else {
+            format
+        };
 
-        // Run the algorithm
-        let response = self.run_algorithm(&*args.arg_algorithm, input_args.remove(0), opts);
+        if streaming {
+            // A profile's output_format doesn't apply to --stream
+            if args.flag_format.is_some() && format != OutputFormat::Text {
+                quit_msg!("--format is not supported with {}", stream_flag);
+            }
+            if args.flag_schema.is_some() {
+                quit_msg!("--schema is not supported with {}", stream_flag);
+            }
+            if args.flag_output_data.is_some() {
+                quit_msg!("--output-data is not supported with {}", stream_flag);
+            }
+            if args.flag_show_ttfb || args.flag_show_metadata {
+                quit_msg!(
+                    "--show-ttfb and --show-metadata are not supported with {}",
+                    stream_flag
+                );
+            }
+            if retry_if.is_some() {
+                quit_msg!("--retry-app-errors is not supported with {}", stream_flag);
+            }
+            if args.flag_dry_run {
+                quit_msg!("--dry-run is not supported with {}", stream_flag);
+            }
+            if args.flag_stdin_json_lines && args.flag_input_encoding.is_some() {
+                quit_msg!("--input-encoding is not supported with --stdin-json-lines");
+            }
+            // Binary results are always base64-encoded JSON strings with --stdin-json-lines
+            if args.flag_stdin_json_lines && args.flag_output_encoding.is_some() {
+                quit_msg!("--output-encoding is not supported with --stdin-json-lines");
+            }
+            let config = StreamConfig {
+                concurrency: args.flag_c.max(1),
+                limiter,
+                input_base64: args.flag_input_encoding.is_some(),
+                json_lines: args.flag_stdin_json_lines,
+                output: args.flag_output,
+                output_encoding: args.flag_output_encoding,
+                max_output_bytes,
+                deadline: Deadline::start(args.flag_deadline),
+                resume_token: args.flag_resume_token,
+            };
+            return stream::run_stream(&self.client, &algorithm, &opts, config);
+        }
+
+        if args.flag_deadline.is_some() {
+            quit_msg!(
+                "--deadline requires --stream or --stdin-json-lines \
+                 (use --timeout to limit a single call)"
+            );
+        }
+        if args.flag_resume_token.is_some() {
+            quit_msg!("--resume-token requires --stream or --stdin-json-lines");
+        }
 
-        let config = ResponseConfig {
+        if args.flag_output_data.is_some() {
+            if args.flag_output.is_some() {
+                quit_msg!("--output and --output-data cannot be combined");
+            }
+            if args.flag_response || args.flag_response_body {
+                quit_msg!("--output-data cannot be used with --response or --response-body");
+            }
+            if args.flag_output_encoding.is_some() {
+                quit_msg!("--output-encoding is not supported with --output-data");
+            }
+        }
+        let base_path = self.base_path.as_deref();
+        let output_data = args
+            .flag_output_data
+            .map(|path| self.client.file(&data::resolve_path(base_path, &path)));
+
+        // The input is uploaded last so that invalid options don't leave a file behind
+        //   (and a dry run only names the file it would upload)
+        let dry_run = args.flag_dry_run;
+        let uploaded = args.flag_upload_input.as_ref().map(|path| {
+            if dry_run {
+                self.upload_target(path)
+            } else {
+                self.upload_input(path, verbosity)
+            }
+        });
+        let mut input = match uploaded {
+            Some(ref file) => {
+                InputData::from_template(&args.flag_input_template, &file.to_data_uri())
+            }
+            None => input_args.remove(0),
+        };
+        if args.flag_input_encoding.is_some() {
+            input = input.decode_base64();
+        }
+        if let Some(ref schema_file) = args.flag_schema {
+            validate_input(&input, schema_file);
+        }
+        if args.flag_dry_run {
+            let upload = args.flag_upload_input.as_deref().zip(uploaded.as_ref());
+            return self.dry_run(&algorithm, &input, &opts, upload);
+        }
+
+        let mut config = ResponseConfig {
             flag_response_body: args.flag_response_body,
             flag_response: args.flag_response,
-            flag_silence: args.flag_silence,
+            verbosity,
             flag_debug: debug,
             flag_output: args.flag_output,
+            output_data,
+            flag_response_meta: args.flag_response_meta,
+            flag_output_encoding: args.flag_output_encoding,
+            only_result_body: args.flag_only_result_body,
+            max_output_bytes,
+            format,
+            request_started: Instant::now(),
+            show_ttfb: args.flag_show_ttfb,
+            input_size: if args.flag_show_metadata {
+                Some(input.size())
+            } else {
+                None
+            },
+            result_jsonpath,
+            require_match: args.flag_require_match,
+        };
+
+        // Run the algorithm, retrying errors it raises that match --retry-if
+        //   The input is only copied while another attempt may need it
+        let mut input = Some(input);
+        let mut retries_left = args.flag_retry_app_errors;
+        let mut backoff = Duration::from_secs(1);
+        let (response, body) = loop {
+            if let Some(ref limiter) = limiter {
+                limiter.acquire();
+            }
+            config.request_started = Instant::now();
+            let attempt_input = match retries_left {
+                0 => input.take(),
+                _ => input.clone(),
+            };
+            let opts = copy_options(&opts);
+            let response = call_algorithm(&self.client, &algorithm, attempt_input.unwrap(), opts);
+            match (response, &retry_if) {
+                (Ok(mut response), Some(retry_if)) => {
+                    let body = read_response(&mut response, &config);
+                    match retry_error(&body.0, retry_if) {
+                        Some(err) if retries_left > 0 => {
+                            if verbosity == Verbosity::Normal {
+                                notice!(
+                                    Warn,
+                                    "Algorithm error (retrying in {}s): {}",
+                                    backoff.as_secs(),
+                                    err
+                                );
+                            }
+                            retries_left -= 1;
+                            thread::sleep(backoff);
+                            backoff *= 2;
+                        }
+                        _ => break (Ok(response), Some(body)),
+                    }
+                }
+                (response, _) => break (response, None),
+            }
         };
+        // Clean up before the response is displayed, since displaying it may exit
+        if let (true, Some(file)) = (args.flag_cleanup, uploaded) {
+            if let Err(err) = file.delete() {
+                eprintln_red!("Error deleting {}: {}", file.to_data_uri(), err);
+            }
+        }
+        let response =
+            response.unwrap_or_else(|err| quit_err!("Error calling algorithm: {} {}", 1, err));
 
-        display_response(response, config);
+        match body {
+            Some(body) => display_body(response, body, config),
+            None => display_response(response, config),
+        }
     }
 }
 
@@ -115,22 +588,161 @@ impl Run {
     pub fn new(profile: Profile) -> Self {
         Run {
             client: profile.client(),
+            base_path: profile.base_data_path().map(String::from),
+            output_format: profile.output_format().map(String::from),
         }
     }
 
-    fn run_algorithm(&self, algo: &str, input_data: InputData, opts: AlgoOptions) -> Response {
-        let mut algorithm = self.client.algo(algo);
-        let algorithm = algorithm.set_options(opts);
+    // Calls an algorithm, returning its result (with an API error as an error)
+    //   Unlike the command, the whole response is read and nothing is printed
+    pub fn run(
+        &self,
+        algo: &str,
+        input_data: InputData,
+        opts: AlgoOptions,
+    ) -> Result<RunResult, String> {
+        let algo = algorithm_id(algo)?;
+        let mut response = call_algorithm(&self.client, &algo, input_data, opts)
+            .map_err(|err| format!("Error calling algorithm: {}", err))?;
+        let mut body = String::new();
+        response
+            .read_to_string(&mut body)
+            .map_err(|err| format!("Error reading response: {}", err))?;
+        body.parse::<AlgoResponse>()
+            .map(RunResult::from)
+            .map_err(|err| match err.api_error() {
+                Some(api_err) => format!("API error: {}", api_err.message),
+                None => format!("Failed to parse algorithm response: {}", err),
+            })
+    }
 
-        let result = match input_data {
-            InputData::Text(text) => algorithm.pipe_as(text, mime::TEXT_PLAIN),
-            InputData::Json(json) => algorithm.pipe_as(json, mime::APPLICATION_JSON),
-            InputData::Binary(bytes) => algorithm.pipe_as(bytes, mime::APPLICATION_OCTET_STREAM),
+    // The uniquely named file in UPLOAD_DIR that a local file for --upload-input is uploaded to
+    fn upload_target(&self, path: &str) -> DataFile {
+        let name = match Path::new(path).file_name() {
+            Some(name) => name.to_string_lossy().into_owned(),
+            None => quit_msg!("--upload-input requires a file, not {}", path),
         };
+        let stamp = Utc::now().format("%Y%m%d%H%M%S%3f");
+        self.client
+            .dir(UPLOAD_DIR)
+            .child::<DataFile>(&format!("{}-{}", stamp, name))
+    }
+
+    // Downloads a data file to use as the input, auto-detecting its type
+    fn input_from_data(&self, path: &str) -> InputData {
+        let path = data::resolve_path(self.base_path.as_deref(), path);
+        let data_file = self.client.file(&path);
+        match data::get_file(&data_file, false) {
+            Ok(mut download) => InputData::auto(&mut download),
+            Err(err) => quit_msg!("{}", err),
+        }
+    }
+
+    // Uploads a local file for --upload-input to a uniquely named file in UPLOAD_DIR
+    fn upload_input(&self, path: &str, verbosity: Verbosity) -> DataFile {
+        let data_file = self.upload_target(path);
+        let file =
+            File::open(path).unwrap_or_else(|err| quit_err!("Error opening {}: {}", path, err));
+
+        let dir = self.client.dir(UPLOAD_DIR);
+        match dir.exists() {
+            Ok(true) => (),
+            Ok(false) => {
+                if let Err(err) = dir.create(DataAcl::default()) {
+                    quit_err!("Error creating directory {}: {}", UPLOAD_DIR, err);
+                }
+            }
+            Err(err) => quit_err!("Error checking {}: {}", UPLOAD_DIR, err),
+        }
+
+        if let Err(err) = data_file.put(file) {
+            quit_err!("Error uploading {}: {}", path, err);
+        }
+        if verbosity == Verbosity::Normal {
+            notice!(Info, "Uploaded {} to {}", path, data_file.to_data_uri());
+        }
+        data_file
+    }
+
+    // Prints the request that would call the algorithm (for --dry-run), after checking that
+    //   the API key is accepted with a request to the Data API, which doesn't run anything
+    fn dry_run(
+        &self,
+        algo: &str,
+        input: &InputData,
+        opts: &AlgoOptions,
+        upload: Option<(&str, &DataFile)>,
+    ) {
+        let mut url = self
+            .client
+            .algo(algo)
+            .to_url()
+            .unwrap_or_else(|err| quit_err!("Invalid algorithm: {}", err));
+        for (k, v) in opts.iter() {
+            url.query_pairs_mut().append_pair(k, v);
+        }
+
+        let my_data = self.client.dir("data://.my");
+        let response = my_data
+            .to_url()
+            .map_err(|err| err.to_string())
+            .and_then(|url| {
+                let request = my_data.client().head(url);
+                request.send().map_err(|err| err.to_string())
+            })
+            .unwrap_or_else(|err| quit_msg!("Error checking API key: {}", err));
+        match response.status() {
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => {
+                quit_msg!("API key was rejected ({})", response.status())
+            }
+            status if !status.is_success() => {
+                quit_msg!("Error checking API key: {}", status)
+            }
+            _ => (),
+        }
+
+        if let Some((path, _)) = upload {
+            if let Err(err) = File::open(path) {
+                quit_err!("Error opening {}: {}", path, err);
+            }
+        }
+
+        println!("Dry run (the algorithm was not called)");
+        println!("API key:       accepted");
+        if let Some((path, file)) = upload {
+            println!("Upload:        {} to {}", path, file.to_data_uri());
+        }
+        println!("Request:       POST {}", url);
+        println!("Content-Type:  {}", input.content_type());
+        println!("Input size:    {}B", data::size_with_suffix(input.size()));
+        println!("Input:         {}", input.preview(DRY_RUN_PREVIEW_CHARS));
+    }
+}
+
+// Checks JSON input against the JSON Schema in `schema_file`, exiting with the errors if invalid
+fn validate_input(input: &InputData, schema_file: &str) {
+    let json = match input {
+        InputData::Json(json) => json,
+        _ => quit_msg!("--schema requires JSON input"),
+    };
+    let value = Json::from_str(json).unwrap_or_else(|err| quit_err!("Invalid JSON input: {}", err));
+
+    let mut schema_json = String::new();
+    if let Err(err) = File::open(schema_file).and_then(|mut f| f.read_to_string(&mut schema_json)) {
+        quit_err!("Error reading {}: {}", schema_file, err);
+    }
+    let schema = Json::from_str(&schema_json)
+        .unwrap_or_else(|err| quit_err!("Invalid JSON in schema {}: {}", schema_file, err));
 
-        match result {
-            Ok(response) => response,
-            Err(err) => quit_err!("Error calling algorithm: {} {}", 1, err),
+    let errors = schema::validate(&schema, &value);
+    if !errors.is_empty() {
+        for error in &errors {
+            eprintln_red!("{}", error);
         }
+        quit_msg!(
+            "Input does not match {} ({} error(s))",
+            schema_file,
+            errors.len()
+        );
     }
 }
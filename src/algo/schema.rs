@@ -0,0 +1,227 @@
+use rustc_serialize::json::Json;
+
+// Validates JSON against a JSON Schema, returning a message for each violation
+//
+// Supports the commonly used validation keywords: type, enum, const, properties, required,
+//   additionalProperties, items, minItems, maxItems, minLength, maxLength, minimum, maximum,
+//   anyOf, oneOf, and allOf. Unsupported keywords (e.g. $ref, pattern, format) are ignored.
+pub fn validate(schema: &Json, value: &Json) -> Vec<String> {
+    let mut errors = Vec::new();
+    validate_at(schema, value, "$", &mut errors);
+    errors
+}
+
+fn validate_at(schema: &Json, value: &Json, path: &str, errors: &mut Vec<String>) {
+    let schema = match schema {
+        Json::Object(schema) => schema,
+        // `true` accepts anything, `false` accepts nothing
+        Json::Boolean(false) => return errors.push(format!("{}: no value is allowed", path)),
+        _ => return,
+    };
+    let mut fail = |msg: String| errors.push(format!("{}: {}", path, msg));
+
+    if let Some(expected) = schema.get("type") {
+        let matches = |t: &Json| t.as_string().map_or(false, |t| has_type(value, t));
+        let ok = match expected {
+            Json::Array(types) => types.iter().any(matches),
+            t => matches(t),
+        };
+        if !ok {
+            return fail(format!(
+                "expected type {}, found {}",
+                expected,
+                type_name(value)
+            ));
+        }
+    }
+    if let Some(Json::Array(allowed)) = schema.get("enum") {
+        if !allowed.contains(value) {
+            fail(format!(
+                "{} is not one of {}",
+                value,
+                Json::Array(allowed.clone())
+            ));
+        }
+    }
+    if let Some(expected) = schema.get("const") {
+        if expected != value {
+            fail(format!("expected {}, found {}", expected, value));
+        }
+    }
+
+    match value {
+        Json::Object(object) => {
+            if let Some(Json::Array(required)) = schema.get("required") {
+                for key in required.iter().filter_map(Json::as_string) {
+                    if !object.contains_key(key) {
+                        fail(format!("missing required property '{}'", key));
+                    }
+                }
+            }
+            let properties = schema.get("properties").and_then(Json::as_object);
+            for (key, child) in object {
+                match properties.and_then(|p| p.get(key)) {
+                    Some(child_schema) => {
+                        let child_path = format!("{}.{}", path, key);
+                        validate_at(child_schema, child, &child_path, errors);
+                    }
+                    None => {
+                        if let Some(additional) = schema.get("additionalProperties") {
+                            let child_path = format!("{}.{}", path, key);
+                            if *additional == Json::Boolean(false) {
+                                errors.push(format!("{}: property is not allowed", child_path));
+                            } else {
+                                validate_at(additional, child, &child_path, errors);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        Json::Array(items) => {
+            check_bounds(
+                schema,
+                "minItems",
+                "maxItems",
+                items.len(),
+                "items",
+                &mut fail,
+            );
+            if let Some(item_schema) = schema.get("items") {
+                for (i, item) in items.iter().enumerate() {
+                    validate_at(item_schema, item, &format!("{}[{}]", path, i), errors);
+                }
+            }
+        }
+        Json::String(s) => {
+            let len = s.chars().count();
+            check_bounds(
+                schema,
+                "minLength",
+                "maxLength",
+                len,
+                "characters",
+                &mut fail,
+            );
+        }
+        _ => {
+            if let Some(n) = value.as_f64() {
+                if let Some(min) = schema.get("minimum").and_then(Json::as_f64) {
+                    if n < min {
+                        fail(format!("{} is less than the minimum of {}", value, min));
+                    }
+                }
+                if let Some(max) = schema.get("maximum").and_then(Json::as_f64) {
+                    if n > max {
+                        fail(format!("{} is greater than the maximum of {}", value, max));
+                    }
+                }
+            }
+        }
+    }
+
+    if let Some(Json::Array(all)) = schema.get("allOf") {
+        for sub in all {
+            validate_at(sub, value, path, errors);
+        }
+    }
+    let matching = |subs: &[Json]| {
+        subs.iter()
+            .filter(|sub| validate(sub, value).is_empty())
+            .count()
+    };
+    if let Some(Json::Array(any)) = schema.get("anyOf") {
+        if matching(any) == 0 {
+            errors.push(format!("{}: does not match any schema in anyOf", path));
+        }
+    }
+    if let Some(Json::Array(one)) = schema.get("oneOf") {
+        let count = matching(one);
+        if count != 1 {
+            let msg = format!("matches {} schemas in oneOf (expected exactly 1)", count);
+            errors.push(format!("{}: {}", path, msg));
+        }
+    }
+}
+
+fn check_bounds<F: FnMut(String)>(
+    schema: &::std::collections::BTreeMap<String, Json>,
+    min_key: &str,
+    max_key: &str,
+    len: usize,
+    unit: &str,
+    fail: &mut F,
+) {
+    if let Some(min) = schema.get(min_key).and_then(Json::as_u64) {
+        if (len as u64) < min {
+            fail(format!("has {} {} (minimum is {})", len, unit, min));
+        }
+    }
+    if let Some(max) = schema.get(max_key).and_then(Json::as_u64) {
+        if (len as u64) > max {
+            fail(format!("has {} {} (maximum is {})", len, unit, max));
+        }
+    }
+}
+
+fn has_type(value: &Json, t: &str) -> bool {
+    match t {
+        "integer" => value.is_i64() || value.is_u64(),
+        "number" => value.is_number(),
+        other => type_name(value) == other,
+    }
+}
+
+fn type_name(value: &Json) -> &'static str {
+    match value {
+        Json::Object(_) => "object",
+        Json::Array(_) => "array",
+        Json::String(_) => "string",
+        Json::Boolean(_) => "boolean",
+        Json::Null => "null",
+        Json::I64(_) | Json::U64(_) => "integer",
+        Json::F64(_) => "number",
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn check(schema: &str, value: &str) -> Vec<String> {
+        validate(
+            &Json::from_str(schema).unwrap(),
+            &Json::from_str(value).unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_validate() {
+        let schema = r#"{
+            "type": "object",
+            "required": ["name", "size"],
+            "properties": {
+                "name": {"type": "string", "minLength": 1},
+                "size": {"type": "integer", "minimum": 1},
+                "tags": {"type": "array", "items": {"enum": ["a", "b"]}}
+            },
+            "additionalProperties": false
+        }"#;
+        assert!(check(schema, r#"{"name": "x", "size": 3, "tags": ["a"]}"#).is_empty());
+        assert_eq!(
+            check(schema, r#"{"name": "", "tags": ["c"], "extra": 1}"#),
+            vec![
+                "$: missing required property 'size'",
+                "$.extra: property is not allowed",
+                "$.name: has 0 characters (minimum is 1)",
+                r#"$.tags[0]: "c" is not one of ["a","b"]"#,
+            ]
+        );
+        assert_eq!(
+            check(schema, "[1]"),
+            vec!["$: expected type \"object\", found array"]
+        );
+        assert!(check(r#"{"type": "number"}"#, "1").is_empty());
+        assert_eq!(check(r#"{"oneOf": [{}, {}]}"#, "1").len(), 1);
+    }
+}
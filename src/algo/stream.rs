@@ -0,0 +1,256 @@
+use super::rate_limit::RateLimiter;
+use super::resume;
+use super::{
+    call_algorithm, copy_options, read_body, render_result, truncation_notice, InputData,
+    OutputDevice,
+};
+use crate::deadline::Deadline;
+use algorithmia::algo::{AlgoIo, AlgoOptions, AlgoResponse};
+use algorithmia::Algorithmia;
+use rustc_serialize::base64::{self, ToBase64};
+use rustc_serialize::json::Json;
+use std::collections::BTreeMap;
+use std::io::{self, BufRead};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+
+pub struct StreamConfig {
+    pub concurrency: usize,
+    pub limiter: Option<Arc<RateLimiter>>,
+    pub input_base64: bool,
+    // For --stdin-json-lines, each line must be JSON and each result is printed as a JSON line
+    pub json_lines: bool,
+    // TODO: --output-template (e.g. '{stem}.result.json', with the input's stem, an index,
+    //   and a timestamp) to name a file per result, once there is an --output-dir to write
+    //   them to (every result currently goes to this one output, and STDIN lines have no stem)
+    pub output: Option<String>,
+    pub output_encoding: Option<String>,
+    pub max_output_bytes: Option<u64>,
+    pub deadline: Deadline,
+    // The state file of --resume-token
+    pub resume_token: Option<String>,
+}
+
+// Calls the algorithm once per line of STDIN, printing the results in input order
+//
+// Lines are read only while fewer than `2 * concurrency` results are pending output,
+//   so a slow algorithm applies backpressure rather than buffering all of STDIN
+pub fn run_stream(client: &Algorithmia, algo: &str, opts: &AlgoOptions, config: StreamConfig) {
+    let window = config.concurrency * 2;
+    let (window_tx, window_rx) = chan::sync(window);
+    let (line_tx, line_rx) = chan::sync::<(usize, usize, String)>(config.concurrency);
+    // Results are tagged with their line's input hash for the resume log. A result of None
+    //   means the line was skipped because the deadline expired
+    let (result_tx, result_rx) =
+        chan::r#async::<(usize, usize, String, Option<Result<Vec<u8>, String>>)>();
+
+    let (completed, mut resume_log) = match config.resume_token {
+        Some(ref path) => {
+            let (completed, log) = resume::open(path).unwrap_or_else(|err| quit_msg!("{}", err));
+            (Some(completed), Some(log))
+        }
+        None => (None, None),
+    };
+
+    // One Producer thread queuing up lines from STDIN, returning how many it skipped as
+    //   already completed
+    let producer_deadline = config.deadline.clone();
+    let producer = thread::spawn(move || {
+        let stdin = io::stdin();
+        let mut seq = 0;
+        let mut resumed = 0;
+        for (line_index, line) in stdin.lock().lines().enumerate() {
+            let line = line.unwrap_or_else(|err| quit_err!("Error reading STDIN: {}", err));
+            // Blank lines are skipped rather than sent to the algorithm
+            if line.trim().is_empty() {
+                continue;
+            }
+            if let Some(ref completed) = completed {
+                if completed.contains(line_index + 1, &line) {
+                    resumed += 1;
+                    continue;
+                }
+            }
+            if producer_deadline.should_stop() {
+                break;
+            }
+            window_tx.send(());
+            line_tx.send((seq, line_index + 1, line));
+            seq += 1;
+        }
+        resumed
+    });
+
+    // Spin up threads to concurrently call the algorithm for lines received on line_rx
+    //   Cloned clients share the same underlying HTTP client, so workers reuse one pool of
+    //   keep-alive connections (at most `concurrency` in use at once) rather than reconnecting
+    // TODO: --max-connections to cap the pool itself, once the algorithmia client exposes
+    //   its reqwest ClientBuilder (pool size isn't configurable through it today)
+    for _ in 0..config.concurrency {
+        let thread_rx = line_rx.clone();
+        let thread_tx = result_tx.clone();
+        let thread_client = client.clone();
+        let thread_algo = algo.to_string();
+        let thread_opts = copy_options(opts);
+        let thread_limiter = config.limiter.clone();
+        let input_base64 = config.input_base64;
+        let json_lines = config.json_lines;
+        let output_encoding = config.output_encoding.clone();
+        let max_output_bytes = config.max_output_bytes;
+        let deadline = config.deadline.clone();
+
+        thread::spawn(move || {
+            for (seq, line_no, line) in thread_rx {
+                let input_hash = resume::input_hash(&line);
+                if deadline.should_stop() {
+                    thread_tx.send((seq, line_no, input_hash, None));
+                    continue;
+                }
+                let input = if json_lines {
+                    match Json::from_str(&line) {
+                        Ok(_) => InputData::Json(line),
+                        Err(err) => {
+                            let outcome = Err(format!("Invalid JSON: {}", err));
+                            thread_tx.send((seq, line_no, input_hash, Some(outcome)));
+                            continue;
+                        }
+                    }
+                } else if input_base64 {
+                    InputData::auto(&mut line.as_bytes()).decode_base64()
+                } else {
+                    InputData::auto(&mut line.as_bytes())
+                };
+                // Only once the input is valid, so that an invalid line doesn't use up a call
+                if let Some(ref limiter) = thread_limiter {
+                    limiter.acquire();
+                }
+                let opts = copy_options(&thread_opts);
+                let outcome = call_algorithm(&thread_client, &thread_algo, input, opts)
+                    .map_err(|err| err.to_string())
+                    .and_then(|mut response| {
+                        let (body, truncated) = read_body(&mut response, max_output_bytes)
+                            .map_err(|err| format!("Error reading response: {}", err))?;
+                        if truncated {
+                            return Err(truncation_notice(max_output_bytes));
+                        }
+                        String::from_utf8_lossy(&body)
+                            .parse::<AlgoResponse>()
+                            .map_err(|err| match err.api_error() {
+                                Some(api_err) => api_err.message.clone(),
+                                None => err.to_string(),
+                            })
+                    })
+                    .map(|response| {
+                        if json_lines {
+                            render_json_line(&response.result)
+                        } else {
+                            render_result(&response.result, output_encoding.as_deref())
+                        }
+                    });
+                thread_tx.send((seq, line_no, input_hash, Some(outcome)));
+            }
+        });
+    }
+    drop(result_tx);
+
+    // Reorder results, writing each as soon as all earlier lines have been written
+    let mut output = OutputDevice::new(&config.output);
+    let mut pending = BTreeMap::new();
+    let mut next_seq = 0;
+    let mut failed = 0;
+    let mut skipped = 0;
+    // Lines completed and failed so far, for the summary if the deadline cuts the run off
+    //   (e.g. while the producer waits on STDIN that is still open)
+    let tally = Arc::new((AtomicUsize::new(0), AtomicUsize::new(0)));
+    let overrun_tally = tally.clone();
+    let deadline_secs = config.deadline.secs();
+    config.deadline.exit_after(move || {
+        let failed = overrun_tally.1.load(Ordering::SeqCst);
+        let mut reason = format!(
+            "the deadline of {}s was reached, and input still being read or called {}s later \
+             was cut off ({} line(s) completed",
+            deadline_secs,
+            crate::deadline::GRACE_SECS,
+            overrun_tally.0.load(Ordering::SeqCst)
+        );
+        if failed > 0 {
+            reason = format!("{}, {} failed, see above", reason, failed);
+        }
+        quit_code!(1, "{})", reason);
+    });
+    for (seq, line_no, input_hash, outcome) in result_rx {
+        pending.insert(seq, (line_no, input_hash, outcome));
+        while let Some((line_no, input_hash, outcome)) = pending.remove(&next_seq) {
+            // The line's error, if any (or None if it was skipped)
+            let status = match outcome {
+                Some(Ok(bytes)) => {
+                    output.write(&bytes);
+                    tally.0.fetch_add(1, Ordering::SeqCst);
+                    Some(None)
+                }
+                Some(Err(err)) => {
+                    eprintln_red!("Error on input line {}: {}", line_no, err);
+                    failed += 1;
+                    tally.1.fetch_add(1, Ordering::SeqCst);
+                    Some(Some(err))
+                }
+                None => {
+                    skipped += 1;
+                    None
+                }
+            };
+            output.flush();
+            // Recorded once the result is written, so a crash can't lose a line marked ok
+            if let (Some(log), Some(error)) = (resume_log.as_mut(), status) {
+                log.record(line_no, input_hash, error.as_deref());
+            }
+            next_seq += 1;
+            window_rx.recv();
+        }
+    }
+
+    let resumed = producer.join().unwrap_or(0);
+    if resumed > 0 {
+        notice!(
+            Info,
+            "Skipped {} input line(s) already completed in {} (their results were printed by \
+             the earlier run)",
+            resumed,
+            config.resume_token.as_deref().unwrap_or_default()
+        );
+    }
+    let completed = next_seq - skipped - failed;
+    let deadline = format!(
+        "the deadline of {}s was reached before all input was processed",
+        config.deadline.secs()
+    );
+    if failed > 0 {
+        let code = if completed > 0 {
+            crate::PARTIAL_FAILURE_CODE
+        } else {
+            1
+        };
+        let mut reason = format!(
+            "{} of {} input line(s) failed (see above)",
+            failed,
+            next_seq - skipped
+        );
+        if config.deadline.stopped_early() {
+            reason = format!("{}, and {}", reason, deadline);
+        }
+        quit_code!(code, "{}", reason);
+    } else if config.deadline.stopped_early() {
+        quit_code!(1, "{} ({} line(s) completed)", deadline, completed);
+    }
+}
+
+// A result as one line of JSON: JSON compacted, text as a JSON string, and binary as a
+//   base64-encoded JSON string
+fn render_json_line(result: &AlgoIo) -> Vec<u8> {
+    let json = result.to_json().unwrap_or_else(|| {
+        let encoded = result.as_bytes().unwrap().to_base64(base64::STANDARD);
+        Json::String(encoded).to_string()
+    });
+    format!("{}\n", json).into_bytes()
+}
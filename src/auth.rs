@@ -21,6 +21,9 @@ Usage:
 
     [profiles.default]
     sim_key = "sim1234567890abcdef"
+
+  A profile may also set 'base_data_path' (e.g. "data://.my/projectx") so that data paths
+  without a 'data://' prefix are resolved relative to it.
 "##;
 
 #[derive(RustcDecodable, Debug)]
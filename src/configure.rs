@@ -0,0 +1,195 @@
+use crate::config::{self, PROFILE_FIELDS};
+use crate::CmdRunner;
+use docopt::Docopt;
+use std::vec::IntoIter;
+use toml::value::{Table, Value};
+
+static USAGE: &str = r##"Usage:
+  mia config get <key>
+  mia config set <key> <value>
+
+  Read or write a single setting in the config file, keeping the other settings in it
+
+  <key> is a dotted path into the config file (e.g. 'profiles.default.api_server'). A profile
+  setting can be given by name alone, applying to the profile selected with --profile
+  (or 'default'):
+    api_server        The API server (e.g. https://api.algorithmia.com)
+    git_server        The git server, if not derived from api_server
+    api_key           The API key
+    base_data_path    Resolve data paths without a 'data://' prefix relative to this path
+    output_format     The --format of commands that support it, unless given (e.g. json)
+    color             Color output: auto, always, or never (unless --color is given)
+
+  output_format and color can also be set for every profile in the [defaults] table
+  (e.g. 'defaults.output_format'), which a profile's own settings override.
+
+  'get' prints the value (or exits with an error if it isn't set). 'set' keeps the type of an
+  existing value, and otherwise stores integers and true/false as such and anything else as a
+  string. Profile settings are always strings. Comments in the config file are not preserved.
+
+  Examples:
+    mia config get api_server                        Print the default profile's API server
+    mia --profile ci config set api_key "$KEY"       Set the API key of the 'ci' profile
+    mia config set defaults.concurrency 16           Set any other setting
+"##;
+
+#[derive(RustcDecodable, Debug)]
+struct Args {
+    cmd_get: bool,
+    cmd_set: bool,
+    arg_key: String,
+    arg_value: Option<String>,
+}
+
+pub struct Configure {
+    profile: String,
+}
+
+impl CmdRunner for Configure {
+    fn get_usage() -> &'static str {
+        USAGE
+    }
+
+    fn cmd_main(&self, argv: IntoIter<String>) {
+        let args: Args = Docopt::new(USAGE)
+            .and_then(|d| d.argv(argv).decode())
+            .unwrap_or_else(|e| e.exit());
+
+        let path = self.resolve_key(&args.arg_key);
+        let mut table = config::read_config_table();
+        if args.cmd_get {
+            match get_value(&table, &path) {
+                Some(Value::String(s)) => println!("{}", s),
+                Some(Value::Table(t)) => print!("{}", toml::to_string(t).unwrap_or_default()),
+                Some(value) => println!("{}", value),
+                None => quit_msg!("{} is not set", path.join(".")),
+            }
+        } else if args.cmd_set {
+            let value = args.arg_value.unwrap_or_default();
+            if let Err(err) = set_value(&mut table, &path, &value) {
+                quit_msg!("Unable to set {}: {}", path.join("."), err);
+            }
+            if let Err(err) = config::write_config_table(&table) {
+                quit_msg!("Unable to set {}: {}", path.join("."), err);
+            }
+        }
+    }
+}
+
+impl Configure {
+    pub fn new(profile: &str) -> Self {
+        Configure {
+            profile: profile.to_owned(),
+        }
+    }
+
+    // Splits a key into its path, expanding a bare profile setting to the selected profile's
+    fn resolve_key(&self, key: &str) -> Vec<String> {
+        if PROFILE_FIELDS.contains(&key) {
+            return vec![
+                "profiles".to_string(),
+                self.profile.clone(),
+                key.to_string(),
+            ];
+        }
+        let path: Vec<String> = key.split('.').map(String::from).collect();
+        if path.iter().any(String::is_empty) {
+            quit_msg!("Invalid key '{}'", key);
+        }
+        path
+    }
+}
+
+fn get_value<'a>(table: &'a Table, path: &[String]) -> Option<&'a Value> {
+    let (last, parents) = path.split_last()?;
+    let mut table = table;
+    for key in parents {
+        table = table.get(key)?.as_table()?;
+    }
+    table.get(last)
+}
+
+// Sets the value at `path`, creating tables along the way
+fn set_value(table: &mut Table, path: &[String], value: &str) -> Result<(), String> {
+    let (last, parents) = path.split_last().ok_or("empty key")?;
+    let is_profile_field = parents.len() == 2 && parents[0] == "profiles";
+    if is_profile_field && !PROFILE_FIELDS.contains(&last.as_str()) {
+        return Err(format!(
+            "unknown profile setting '{}'. Supported: {}",
+            last,
+            PROFILE_FIELDS.join(", ")
+        ));
+    }
+
+    let mut table = table;
+    for key in parents {
+        let entry = table
+            .entry(key.clone())
+            .or_insert_with(|| Value::Table(Table::new()));
+        table = match entry {
+            Value::Table(t) => t,
+            _ => return Err(format!("'{}' is not a table", key)),
+        };
+    }
+    let value = match (table.get(last), is_profile_field) {
+        (_, true) | (Some(Value::String(_)), _) => Value::String(value.to_string()),
+        (Some(Value::Integer(_)), _) => value
+            .parse()
+            .map(Value::Integer)
+            .map_err(|_| format!("expected an integer, found '{}'", value))?,
+        (Some(Value::Boolean(_)), _) => value
+            .parse()
+            .map(Value::Boolean)
+            .map_err(|_| format!("expected true or false, found '{}'", value))?,
+        (Some(Value::Table(_)), _) => return Err("cannot replace a table".to_string()),
+        _ => infer_value(value),
+    };
+    table.insert(last.clone(), value);
+    Ok(())
+}
+
+fn infer_value(value: &str) -> Value {
+    if let Ok(n) = value.parse() {
+        Value::Integer(n)
+    } else if let Ok(b) = value.parse() {
+        Value::Boolean(b)
+    } else {
+        Value::String(value.to_string())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn path(key: &str) -> Vec<String> {
+        key.split('.').map(String::from).collect()
+    }
+
+    #[test]
+    fn test_set_value() {
+        let mut table: Table = toml::from_str(
+            "[profiles.default]\napi_key = \"sim123\"\n[defaults]\nconcurrency = 8\n",
+        )
+        .unwrap();
+        set_value(&mut table, &path("profiles.default.api_key"), "42").unwrap();
+        set_value(&mut table, &path("defaults.concurrency"), "16").unwrap();
+        set_value(&mut table, &path("defaults.verbose"), "true").unwrap();
+        set_value(&mut table, &path("other.name"), "x").unwrap();
+        assert_eq!(
+            get_value(&table, &path("profiles.default.api_key")),
+            Some(&Value::String("42".to_string()))
+        );
+        assert_eq!(
+            get_value(&table, &path("defaults.concurrency")),
+            Some(&Value::Integer(16))
+        );
+        assert_eq!(
+            get_value(&table, &path("defaults.verbose")),
+            Some(&Value::Boolean(true))
+        );
+        assert!(set_value(&mut table, &path("defaults.concurrency"), "many").is_err());
+        assert!(set_value(&mut table, &path("profiles.default.api_kee"), "x").is_err());
+        assert!(set_value(&mut table, &path("other.name.deeper"), "x").is_err());
+    }
+}
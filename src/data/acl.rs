@@ -0,0 +1,282 @@
+use crate::config::Profile;
+use crate::{data, CmdRunner};
+use algorithmia::data::{DataAcl, DataDir, HasDataPath, ReadAcl};
+use algorithmia::Algorithmia;
+use docopt::Docopt;
+use once_cell::sync::OnceCell;
+use reqwest::blocking::{Client, Response};
+use reqwest::Method;
+use rustc_serialize::json::{self, Json};
+use std::collections::HashMap;
+use std::vec::IntoIter;
+
+static USAGE: &str = r##"Usage:
+  mia acl <data-dir> [<acl>]
+
+  Show or set who can read an Algorithmia data directory
+
+  <data-dir>    Specifies the Algorithmia Data URI
+                The 'data://' prefix is optional (paths without it are relative
+                  to the profile's base_data_path, if set)
+  <acl>         If given, the ACL to set:
+                  private         Readable only by you
+                  my-algorithms   Readable by your algorithms, regardless of caller (the default
+                                    for new directories)
+                  public          Readable by any user
+
+  ACLs apply to a directory and the files in it (files do not have their own ACL)
+
+  Examples:
+    mia acl data://.my/foo                Show the ACL of your 'foo' data directory
+    mia acl data://.my/foo public         Make your 'foo' data directory publicly readable
+"##;
+
+#[derive(RustcDecodable, Debug)]
+struct Args {
+    arg_data_dir: String,
+    arg_acl: Option<String>,
+}
+
+pub struct Acl {
+    client: Algorithmia,
+    base_path: Option<String>,
+}
+
+impl CmdRunner for Acl {
+    fn get_usage() -> &'static str {
+        USAGE
+    }
+
+    fn cmd_main(&self, argv: IntoIter<String>) {
+        let args: Args = Docopt::new(USAGE)
+            .and_then(|d| d.argv(argv).decode())
+            .unwrap_or_else(|e| e.exit());
+
+        let path = data::resolve_path(self.base_path.as_deref(), &args.arg_data_dir);
+        let dir = self.client.dir(&path);
+        if let Some(ref acl) = args.arg_acl {
+            let access = ReadAccess::parse(acl).unwrap_or_else(|err| quit_msg!("{}", err));
+            if let Err(err) = set_acl(&dir, access) {
+                quit_msg!("Error setting ACL of {}: {}", dir.to_data_uri(), err);
+            }
+        }
+        match get_acl(&dir) {
+            Ok(read) => println!("{}: {}", dir.to_data_uri(), describe(&read)),
+            Err(err) => quit_msg!("Error getting ACL of {}: {}", dir.to_data_uri(), err),
+        }
+    }
+}
+
+impl Acl {
+    pub fn new(profile: Profile) -> Self {
+        Acl {
+            client: profile.client(),
+            base_path: profile.base_data_path().map(String::from),
+        }
+    }
+}
+
+// The read permissions that can be set on a data directory
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ReadAccess {
+    Private,
+    MyAlgorithms,
+    Public,
+}
+
+impl ReadAccess {
+    pub fn parse(name: &str) -> Result<ReadAccess, String> {
+        match name {
+            "private" => Ok(ReadAccess::Private),
+            "my-algorithms" => Ok(ReadAccess::MyAlgorithms),
+            "public" => Ok(ReadAccess::Public),
+            _ => Err(format!(
+                "Unsupported ACL '{}'. Supported: private, my-algorithms, public",
+                name
+            )),
+        }
+    }
+
+    fn to_data_acl(self) -> DataAcl {
+        match self {
+            ReadAccess::Private => ReadAcl::Private.into(),
+            ReadAccess::MyAlgorithms => ReadAcl::MyAlgorithms.into(),
+            ReadAccess::Public => ReadAcl::Public.into(),
+        }
+    }
+}
+
+// Names a directory's read ACL, falling back to the raw entries if it isn't a standard one
+pub fn describe(read: &[String]) -> String {
+    let matches = |access: ReadAccess| access.to_data_acl().read == read;
+    if matches(ReadAccess::Private) {
+        "private".to_string()
+    } else if matches(ReadAccess::MyAlgorithms) {
+        "my-algorithms".to_string()
+    } else if matches(ReadAccess::Public) {
+        "public".to_string()
+    } else {
+        read.join(", ")
+    }
+}
+
+// Fetches the read ACL of a data directory
+pub fn get_acl(dir: &DataDir) -> Result<Vec<String>, String> {
+    let listing = get_listing(dir, None)?;
+    // Directories created without an ACL are private
+    Ok(read_acl(listing.find("acl")).unwrap_or_default())
+}
+
+// The read ACLs of a data directory and of each of its subdirectories (by name)
+pub struct DirAcls {
+    pub acl: Vec<String>,
+    pub folders: HashMap<String, Vec<String>>,
+}
+
+// Fetches the read ACLs of a data directory and its subdirectories
+//   The listing includes subdirectory ACLs, so only subdirectories missing from it cost
+//   an extra request
+pub fn get_dir_acls(dir: &DataDir) -> Result<DirAcls, String> {
+    let mut acls = DirAcls {
+        acl: Vec::new(),
+        folders: HashMap::new(),
+    };
+    let mut missing = Vec::new();
+    let mut marker = None;
+    loop {
+        let listing = get_listing(dir, marker.as_deref())?;
+        if let Some(read) = read_acl(listing.find("acl")) {
+            acls.acl = read;
+        }
+        let folders = listing.find("folders").and_then(Json::as_array);
+        for folder in folders.into_iter().flatten() {
+            let name = match folder.find("name").and_then(Json::as_string) {
+                Some(name) => name.to_string(),
+                None => continue,
+            };
+            match read_acl(folder.find("acl")) {
+                Some(read) => {
+                    acls.folders.insert(name, read);
+                }
+                None => missing.push(name),
+            }
+        }
+        marker = listing
+            .find("marker")
+            .and_then(Json::as_string)
+            .map(String::from);
+        if marker.is_none() {
+            break;
+        }
+    }
+
+    for name in missing {
+        let read = get_acl(&dir.child::<DataDir>(&name))?;
+        acls.folders.insert(name, read);
+    }
+    Ok(acls)
+}
+
+// Fetches a page of a directory listing, including ACLs
+fn get_listing(dir: &DataDir, marker: Option<&str>) -> Result<Json, String> {
+    let mut url = dir.to_url().map_err(|err| err.to_string())?;
+    url.query_pairs_mut().append_pair("acl", "true");
+    if let Some(marker) = marker {
+        url.query_pairs_mut().append_pair("marker", marker);
+    }
+    let response = dir
+        .client()
+        .get(url)
+        .send()
+        .map_err(|err| err.to_string())?;
+    let body = read_response(response)?;
+    Json::from_str(&body).map_err(|err| format!("Invalid response: {}", err))
+}
+
+// Reads the entries of an ACL object like {"read": ["user://*"]}
+fn read_acl(acl: Option<&Json>) -> Option<Vec<String>> {
+    let read = acl?.find("read")?.as_array()?;
+    Some(
+        read.iter()
+            .filter_map(Json::as_string)
+            .map(String::from)
+            .collect(),
+    )
+}
+
+// Replaces the read ACL of a data directory
+pub fn set_acl(dir: &DataDir, access: ReadAccess) -> Result<(), String> {
+    #[derive(RustcEncodable)]
+    struct AclUpdate {
+        acl: AclRead,
+    }
+    #[derive(RustcEncodable)]
+    struct AclRead {
+        read: Vec<String>,
+    }
+    let update = AclUpdate {
+        acl: AclRead {
+            read: access.to_data_acl().read,
+        },
+    };
+    let body = json::encode(&update).map_err(|err| err.to_string())?;
+
+    // The algorithmia client has no PATCH helper, so build the request with its credentials
+    //   and switch the method before sending it
+    let url = dir.to_url().map_err(|err| err.to_string())?;
+    let mut request = dir
+        .client()
+        .put(url)
+        .header("Content-Type", "application/json")
+        .body(body)
+        .build()
+        .map_err(|err| err.to_string())?;
+    *request.method_mut() = Method::PATCH;
+    let response = patch_client()?
+        .execute(request)
+        .map_err(|err| err.to_string())?;
+    read_response(response).map(|_| ())
+}
+
+// The client that PATCH requests are sent with, configured like the algorithmia client's own
+//   (which it doesn't expose), and shared so that its connections are reused
+fn patch_client() -> Result<&'static Client, String> {
+    static CLIENT: OnceCell<Client> = OnceCell::new();
+    CLIENT.get_or_try_init(|| {
+        let builder = Client::builder();
+        #[cfg(feature = "rust-tls")]
+        let builder = builder.use_rustls_tls();
+        builder.build().map_err(|err| err.to_string())
+    })
+}
+
+// Reads a Data API response body, turning an error status into its error message
+fn read_response(response: Response) -> Result<String, String> {
+    let status = response.status();
+    let body = response.text().map_err(|err| err.to_string())?;
+    if status.is_success() {
+        return Ok(body);
+    }
+    let message = Json::from_str(&body).ok().and_then(|err| {
+        err.find_path(&["error", "message"])
+            .and_then(Json::as_string)
+            .map(String::from)
+    });
+    Err(message.unwrap_or_else(|| status.to_string()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_describe() {
+        assert_eq!(describe(&[]), "private");
+        assert_eq!(describe(&["algo://.my/*".to_string()]), "my-algorithms");
+        assert_eq!(describe(&["user://*".to_string()]), "public");
+        assert_eq!(
+            describe(&["user://*".to_string(), "algo://.my/*".to_string()]),
+            "user://*, algo://.my/*"
+        );
+    }
+}
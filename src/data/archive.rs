@@ -0,0 +1,950 @@
+// Archives a local directory for `mia cp --archive`, as a tar, gzipped tar, or zip file, and
+//   extracts a downloaded one for `mia cp --unarchive`
+//
+// The archive is written on its own thread as it is uploaded (see stream), so no temp file is
+//   needed. Compression uses miniz_oxide, as decoding downloads does. Zip entries are written
+//   with data descriptors (sizes and CRCs after the data) so that they can be streamed too,
+//   and likewise they are extracted as they are downloaded, from their local headers.
+
+use super::download;
+use super::ignore::PathFilter;
+use chrono::{DateTime, Datelike, Local, Timelike};
+use miniz_oxide::deflate::core::CompressorOxide;
+use miniz_oxide::deflate::stream::deflate;
+use miniz_oxide::{DataFormat, MZFlush, MZStatus};
+use std::fs::{self, File, Metadata};
+use std::io::{self, BufRead, BufReader, Cursor, Read, Write};
+use std::path::{Component, Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::thread;
+use std::time::UNIX_EPOCH;
+
+// Archive data is handed to the upload in chunks of this size
+const CHUNK_SIZE: usize = 64 * 1024;
+// Chunks written ahead of the upload
+const CHUNKS_AHEAD: usize = 4;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ArchiveFormat {
+    Tar,
+    TarGz,
+    Zip,
+}
+
+impl ArchiveFormat {
+    pub fn parse(name: &str) -> Result<ArchiveFormat, String> {
+        match name {
+            "tar" => Ok(ArchiveFormat::Tar),
+            "tar.gz" | "tgz" => Ok(ArchiveFormat::TarGz),
+            "zip" => Ok(ArchiveFormat::Zip),
+            _ => Err(format!(
+                "Unsupported archive format '{}'. Supported: tar, tar.gz, zip",
+                name
+            )),
+        }
+    }
+
+    // The format of an archive named with its extension (e.g. bundle.tar.gz)
+    fn from_name(name: &str) -> Option<ArchiveFormat> {
+        let name = name.to_lowercase();
+        if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Some(ArchiveFormat::TarGz)
+        } else if name.ends_with(".tar") {
+            Some(ArchiveFormat::Tar)
+        } else if name.ends_with(".zip") {
+            Some(ArchiveFormat::Zip)
+        } else {
+            None
+        }
+    }
+
+    // The format of an archive starting with `head` (up to its first 512 bytes)
+    fn from_magic(head: &[u8]) -> Option<ArchiveFormat> {
+        if head.starts_with(b"PK\x03\x04") || head.starts_with(b"PK\x05\x06") {
+            Some(ArchiveFormat::Zip)
+        } else if head.starts_with(&[0x1f, 0x8b]) {
+            Some(ArchiveFormat::TarGz)
+        } else if head.len() >= 262 && &head[257..262] == b"ustar" {
+            Some(ArchiveFormat::Tar)
+        } else {
+            None
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            ArchiveFormat::Tar => "tar",
+            ArchiveFormat::TarGz => "tar.gz",
+            ArchiveFormat::Zip => "zip",
+        }
+    }
+}
+
+// A file or directory to archive, named by its '/'-separated path within the archive
+pub struct Entry {
+    path: PathBuf,
+    name: String,
+    is_dir: bool,
+}
+
+// Lists the files and directories under `root` that the filter doesn't skip, in name order
+//   (with each directory before its contents)
+pub fn list_entries(root: &Path, filter: &mut PathFilter) -> Result<Vec<Entry>, String> {
+    let mut entries = Vec::new();
+    add_entries(root, "", filter, &mut entries)?;
+    Ok(entries)
+}
+
+fn add_entries(
+    dir: &Path,
+    rel_dir: &str,
+    filter: &mut PathFilter,
+    entries: &mut Vec<Entry>,
+) -> Result<(), String> {
+    let mark = filter.add_ignore_file(dir, rel_dir)?;
+    let mut children = fs::read_dir(dir)
+        .and_then(|children| children.collect::<Result<Vec<_>, _>>())
+        .map_err(|err| format!("Error reading {}: {}", dir.display(), err))?;
+    children.sort_by_key(|child| child.file_name());
+
+    for child in children {
+        let name = child.file_name().to_string_lossy().into_owned();
+        let rel_path = match rel_dir {
+            "" => name,
+            _ => format!("{}/{}", rel_dir, name),
+        };
+        let path = child.path();
+        let is_dir = path.is_dir();
+        if filter.is_skipped(&rel_path, is_dir) {
+            continue;
+        }
+        entries.push(Entry {
+            path: path.clone(),
+            name: rel_path.clone(),
+            is_dir,
+        });
+        if is_dir {
+            add_entries(&path, &rel_path, filter, entries)?;
+        }
+    }
+    filter.remove_ignore_file(mark);
+    Ok(())
+}
+
+// Writes the archive on another thread, returning a reader of it for the upload
+//   An error writing the archive (e.g. reading a file) is returned by the reader
+pub fn stream(format: ArchiveFormat, entries: Vec<Entry>) -> ArchiveReader {
+    let (tx, rx) = mpsc::sync_channel(CHUNKS_AHEAD);
+    thread::spawn(move || {
+        let mut writer = ChunkWriter {
+            tx: tx.clone(),
+            buf: Vec::with_capacity(CHUNK_SIZE),
+        };
+        let written = write_archive(format, &entries, &mut writer).and_then(|_| writer.flush());
+        // An empty chunk marks the end, so that a writer that died isn't taken for one that finished
+        let _ = tx.send(written.map(|_| Vec::new()));
+    });
+    ArchiveReader {
+        rx,
+        chunk: Vec::new(),
+        pos: 0,
+        done: false,
+    }
+}
+
+pub fn write_archive<W: Write>(
+    format: ArchiveFormat,
+    entries: &[Entry],
+    out: &mut W,
+) -> io::Result<()> {
+    match format {
+        ArchiveFormat::Tar => write_tar(entries, out),
+        ArchiveFormat::TarGz => {
+            let mut gzip = GzipWriter::new(out)?;
+            write_tar(entries, &mut gzip)?;
+            gzip.finish()
+        }
+        ArchiveFormat::Zip => write_zip(entries, out),
+    }
+}
+
+pub struct ArchiveReader {
+    rx: Receiver<io::Result<Vec<u8>>>,
+    chunk: Vec<u8>,
+    pos: usize,
+    done: bool,
+}
+
+impl Read for ArchiveReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.chunk.len() {
+            if self.done {
+                return Ok(0);
+            }
+            self.chunk = match self.rx.recv() {
+                Ok(chunk) => chunk?,
+                Err(_) => return Err(io::Error::new(io::ErrorKind::Other, "archiving stopped")),
+            };
+            self.pos = 0;
+            self.done = self.chunk.is_empty();
+        }
+        let n = (self.chunk.len() - self.pos).min(buf.len());
+        buf[..n].copy_from_slice(&self.chunk[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+// Sends what is written to an ArchiveReader in chunks
+struct ChunkWriter {
+    tx: SyncSender<io::Result<Vec<u8>>>,
+    buf: Vec<u8>,
+}
+
+impl Write for ChunkWriter {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.buf.extend_from_slice(data);
+        if self.buf.len() >= CHUNK_SIZE {
+            self.flush()?;
+        }
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.buf.is_empty() {
+            return Ok(());
+        }
+        let chunk = std::mem::replace(&mut self.buf, Vec::with_capacity(CHUNK_SIZE));
+        self.tx
+            .send(Ok(chunk))
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "upload stopped"))
+    }
+}
+
+// Extracts an archive into `dest` as it is read, returning the number of files extracted
+//   The format is detected from the archive's first bytes, or else from its name. Entries
+//   that would be extracted outside of `dest` (e.g. "../x" or "/x") are an error, and links
+//   are skipped.
+pub fn extract<R: Read>(name: &str, mut reader: R, dest: &Path) -> io::Result<u64> {
+    let mut head = Vec::with_capacity(512);
+    (&mut reader).take(512).read_to_end(&mut head)?;
+    let format = ArchiveFormat::from_magic(&head)
+        .or_else(|| ArchiveFormat::from_name(name))
+        .ok_or_else(|| invalid_data("not a tar, tar.gz, or zip archive".to_string()))?;
+
+    let mut reader = BufReader::with_capacity(CHUNK_SIZE, Cursor::new(head).chain(reader));
+    match format {
+        ArchiveFormat::Tar => extract_tar(&mut reader, dest),
+        ArchiveFormat::TarGz => extract_tar(&mut download::gunzip(reader), dest),
+        ArchiveFormat::Zip => extract_zip(&mut reader, dest),
+    }
+}
+
+fn extract_tar<R: Read>(reader: &mut R, dest: &Path) -> io::Result<u64> {
+    let mut files = 0;
+    // The name for the next entry, from a GNU long name entry or a pax header before it
+    let mut next_name = None;
+    loop {
+        let mut header = [0u8; 512];
+        reader.read_exact(&mut header)?;
+        // An empty block ends the archive
+        if header.iter().all(|&b| b == 0) {
+            return Ok(files);
+        }
+        let checksum = tar_number(&header[148..156])?;
+        let mut unchecked = header;
+        unchecked[148..156].copy_from_slice(b"        ");
+        if unchecked.iter().map(|&b| u64::from(b)).sum::<u64>() != checksum {
+            return Err(invalid_data("invalid tar header".to_string()));
+        }
+
+        let size = tar_number(&header[124..136])?;
+        let padding = (512 - size % 512) % 512;
+        let name = next_name.take().unwrap_or_else(|| tar_name(&header));
+        match header[156] {
+            b'L' => next_name = Some(read_name(reader, size)?),
+            b'x' => next_name = pax_path(&read_name(reader, size)?),
+            b'0' | b'7' | 0 => {
+                let path = entry_path(dest, &name)?;
+                write_entry(&mut reader.take(size), &path, size)?;
+                set_mode(&path, tar_number(&header[100..108])? as u32)?;
+                files += 1;
+            }
+            b'5' => fs::create_dir_all(entry_path(dest, &name)?)?,
+            other => {
+                if other == b'1' || other == b'2' {
+                    notice!(Warn, "Skipping link {} in the archive", name);
+                }
+                skip(reader, size)?;
+            }
+        }
+        skip(reader, padding)?;
+    }
+}
+
+// A tar entry's name, joined to its prefix (in the POSIX format)
+fn tar_name(header: &[u8; 512]) -> String {
+    let field = |bytes: &[u8]| {
+        let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+        String::from_utf8_lossy(&bytes[..end]).into_owned()
+    };
+    let name = field(&header[..100]);
+    match &header[257..263] {
+        b"ustar\0" if header[345] != 0 => format!("{}/{}", field(&header[345..500]), name),
+        _ => name,
+    }
+}
+
+// A numeric field of a tar header, in octal or (for large values) GNU's base-256
+fn tar_number(field: &[u8]) -> io::Result<u64> {
+    if field[0] & 0x80 != 0 {
+        let value = field[1..]
+            .iter()
+            .fold(u64::from(field[0] & 0x7f), |n, &b| n << 8 | u64::from(b));
+        return Ok(value);
+    }
+    let digits = String::from_utf8_lossy(field);
+    let digits = digits.trim_matches(|c: char| c == '\0' || c == ' ');
+    if digits.is_empty() {
+        return Ok(0);
+    }
+    u64::from_str_radix(digits, 8)
+        .map_err(|_| invalid_data(format!("invalid number '{}' in tar header", digits)))
+}
+
+fn read_name<R: Read>(reader: &mut R, size: u64) -> io::Result<String> {
+    let mut name = Vec::new();
+    reader.take(size).read_to_end(&mut name)?;
+    let end = name.iter().position(|&b| b == 0).unwrap_or(name.len());
+    Ok(String::from_utf8_lossy(&name[..end]).into_owned())
+}
+
+// The path in a pax header's "<length> <key>=<value>" records, if it has one
+fn pax_path(records: &str) -> Option<String> {
+    records.lines().find_map(|record| {
+        let (_, field) = record.split_at(record.find(' ')? + 1);
+        field.strip_prefix("path=").map(String::from)
+    })
+}
+
+fn extract_zip<R: BufRead>(reader: &mut R, dest: &Path) -> io::Result<u64> {
+    let mut files = 0;
+    loop {
+        match read_u32(reader)? {
+            0x0403_4b50 => (),
+            // The central directory only repeats what the local headers had
+            0x0201_4b50 | 0x0605_4b50 => return Ok(files),
+            _ => return Err(invalid_data("invalid zip entry header".to_string())),
+        }
+        let mut header = [0u8; 26];
+        reader.read_exact(&mut header)?;
+        let u16_at = |i: usize| u16::from_le_bytes([header[i], header[i + 1]]);
+        let u32_at =
+            |i: usize| u32::from_le_bytes([header[i], header[i + 1], header[i + 2], header[i + 3]]);
+        let (flags, method) = (u16_at(2), u16_at(4));
+        let (crc, compressed, size) = (u32_at(10), u32_at(14), u32_at(18));
+        let mut name = vec![0; usize::from(u16_at(22))];
+        reader.read_exact(&mut name)?;
+        let name = String::from_utf8_lossy(&name).into_owned();
+        skip(reader, u64::from(u16_at(24)))?;
+
+        if flags & 0x0001 != 0 {
+            return Err(invalid_data(format!("{} is encrypted", name)));
+        }
+        let path = entry_path(dest, &name)?;
+        if name.ends_with('/') {
+            fs::create_dir_all(&path)?;
+            skip(reader, u64::from(compressed))?;
+            continue;
+        }
+
+        // Sizes and CRCs may instead follow the data, in a data descriptor
+        let has_descriptor = flags & 0x0008 != 0;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut out = CrcWriter {
+            inner: File::create(&path)?,
+            crc: 0,
+            size: 0,
+        };
+        match method {
+            // The end of stored data is only known from its size
+            0 if has_descriptor => {
+                return Err(invalid_data(format!(
+                    "{} is stored with its size after its data, which can't be extracted as it \
+                     is downloaded",
+                    name
+                )))
+            }
+            0 => copy_exact(
+                &mut reader.take(u64::from(compressed)),
+                &mut out,
+                u64::from(size),
+            )?,
+            8 => {
+                io::copy(&mut download::inflate_raw(&mut *reader), &mut out)?;
+            }
+            _ => {
+                return Err(invalid_data(format!(
+                    "{} uses an unsupported compression method ({})",
+                    name, method
+                )))
+            }
+        }
+        let (crc, size) = if has_descriptor {
+            // The descriptor's signature is optional
+            let mut descriptor = read_u32(reader)?;
+            if descriptor == 0x0807_4b50 {
+                descriptor = read_u32(reader)?;
+            }
+            let _compressed = read_u32(reader)?;
+            (descriptor, read_u32(reader)?)
+        } else {
+            (crc, size)
+        };
+        if out.crc != crc || out.size != u64::from(size) {
+            // Don't leave the corrupt file behind as if it were extracted
+            drop(out);
+            let _ = fs::remove_file(&path);
+            return Err(invalid_data(format!(
+                "{} is corrupt (its CRC or size doesn't match)",
+                name
+            )));
+        }
+        files += 1;
+    }
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+// The path to extract an entry to, refusing names that would put it outside of `dest`
+fn entry_path(dest: &Path, name: &str) -> io::Result<PathBuf> {
+    let mut path = dest.to_path_buf();
+    for component in Path::new(name).components() {
+        match component {
+            Component::Normal(part) => path.push(part),
+            Component::CurDir => (),
+            _ => {
+                return Err(invalid_data(format!(
+                    "refusing to extract {} outside of {}",
+                    name,
+                    dest.display()
+                )))
+            }
+        }
+    }
+    Ok(path)
+}
+
+// Writes a file of `size` bytes from `reader`, creating its directory if needed
+fn write_entry<R: Read>(reader: &mut R, path: &Path, size: u64) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    copy_exact(reader, &mut File::create(path)?, size)
+}
+
+fn copy_exact<R: Read, W: Write>(reader: &mut R, out: &mut W, size: u64) -> io::Result<()> {
+    if io::copy(reader, out)? < size {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "archive ended early",
+        ));
+    }
+    Ok(())
+}
+
+fn skip<R: Read>(reader: &mut R, size: u64) -> io::Result<()> {
+    copy_exact(&mut reader.take(size), &mut io::sink(), size)
+}
+
+#[cfg(unix)]
+fn set_mode(path: &Path, mode: u32) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(path, fs::Permissions::from_mode(mode & 0o777))
+}
+
+#[cfg(not(unix))]
+fn set_mode(_path: &Path, _mode: u32) -> io::Result<()> {
+    Ok(())
+}
+
+fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+// Writes a tar archive in the GNU format, using its long name entries for paths over 100 bytes
+fn write_tar<W: Write>(entries: &[Entry], out: &mut W) -> io::Result<()> {
+    for entry in entries {
+        let meta = fs::metadata(&entry.path)?;
+        let size = if entry.is_dir { 0 } else { meta.len() };
+        // Files of 8GB and over would need GNU's base-256 sizes
+        if size >= 1 << 33 {
+            return Err(too_large(entry, "tar"));
+        }
+        let name = if entry.is_dir {
+            format!("{}/", entry.name)
+        } else {
+            entry.name.clone()
+        };
+        if name.len() > 100 {
+            let mut long_name = name.clone().into_bytes();
+            long_name.push(0);
+            let header = tar_header("././@LongLink", b'L', 0, long_name.len() as u64, 0);
+            out.write_all(&header)?;
+            out.write_all(&long_name)?;
+            write_tar_padding(long_name.len() as u64, out)?;
+        }
+        let type_flag = if entry.is_dir { b'5' } else { b'0' };
+        let header = tar_header(&name, type_flag, mode(&meta), size, mtime(&meta));
+        out.write_all(&header)?;
+        if !entry.is_dir {
+            let copied = io::copy(&mut File::open(&entry.path)?.take(size), out)?;
+            if copied < size {
+                return Err(changed(entry));
+            }
+            write_tar_padding(size, out)?;
+        }
+    }
+    // Two empty blocks end the archive
+    out.write_all(&[0; 1024])
+}
+
+fn tar_header(name: &str, type_flag: u8, mode: u32, size: u64, mtime: u64) -> [u8; 512] {
+    let mut header = [0u8; 512];
+    let mut set = |offset: usize, value: &[u8]| {
+        header[offset..offset + value.len()].copy_from_slice(value);
+    };
+    // Longer names were written in a long name entry first
+    let name = name.as_bytes();
+    set(0, &name[..name.len().min(100)]);
+    set(100, format!("{:07o}\0", mode).as_bytes());
+    set(108, b"0000000\0");
+    set(116, b"0000000\0");
+    set(124, format!("{:011o}\0", size).as_bytes());
+    set(136, format!("{:011o}\0", mtime).as_bytes());
+    set(156, &[type_flag]);
+    set(257, b"ustar  \0");
+
+    // The checksum is of the header with the checksum field as spaces
+    header[148..156].copy_from_slice(b"        ");
+    let checksum: u32 = header.iter().map(|&b| u32::from(b)).sum();
+    header[148..156].copy_from_slice(format!("{:06o}\0 ", checksum).as_bytes());
+    header
+}
+
+fn write_tar_padding<W: Write>(size: u64, out: &mut W) -> io::Result<()> {
+    let padding = (512 - size % 512) % 512;
+    out.write_all(&[0; 512][..padding as usize])
+}
+
+// Writes a zip archive, deflating files
+//   Without zip64 extensions, which few algorithms would need, the archive is limited to 4GB
+//   and 65535 entries
+fn write_zip<W: Write>(entries: &[Entry], out: &mut W) -> io::Result<()> {
+    if entries.len() > 0xffff {
+        return Err(io::Error::new(
+            io::ErrorKind::Other,
+            "too many files for a zip archive (use tar)",
+        ));
+    }
+    let mut out = CountingWriter {
+        inner: out,
+        count: 0,
+    };
+    let mut central_dir = Vec::new();
+    for entry in entries {
+        let meta = fs::metadata(&entry.path)?;
+        let (time, date) = dos_time(&meta);
+        let name = if entry.is_dir {
+            format!("{}/", entry.name)
+        } else {
+            entry.name.clone()
+        };
+        // UTF-8 names, and for files, sizes and CRCs in a data descriptor after the data
+        let (flags, method) = if entry.is_dir {
+            (0x0800u16, 0u16)
+        } else {
+            (0x0808, 8)
+        };
+        let offset = zip_u32(out.count, entry)?;
+
+        let mut local = Vec::new();
+        put_u32(&mut local, 0x0403_4b50);
+        put_u16(&mut local, 20);
+        put_u16(&mut local, flags);
+        put_u16(&mut local, method);
+        put_u16(&mut local, time);
+        put_u16(&mut local, date);
+        local.extend_from_slice(&[0; 12]);
+        put_u16(&mut local, name.len() as u16);
+        put_u16(&mut local, 0);
+        local.extend_from_slice(name.as_bytes());
+        out.write_all(&local)?;
+
+        let (crc, compressed, size) = if entry.is_dir {
+            (0, 0, 0)
+        } else {
+            let start = out.count;
+            let mut deflater = DeflateWriter::new(&mut out);
+            let mut file = File::open(&entry.path)?;
+            let (mut crc, mut size) = (0, 0u64);
+            let mut buf = vec![0; CHUNK_SIZE];
+            loop {
+                let n = file.read(&mut buf)?;
+                if n == 0 {
+                    break;
+                }
+                crc = crc32(crc, &buf[..n]);
+                size += n as u64;
+                deflater.write_all(&buf[..n])?;
+            }
+            deflater.finish()?;
+            let compressed = zip_u32(out.count - start, entry)?;
+            let size = zip_u32(size, entry)?;
+
+            let mut descriptor = Vec::new();
+            put_u32(&mut descriptor, 0x0807_4b50);
+            put_u32(&mut descriptor, crc);
+            put_u32(&mut descriptor, compressed);
+            put_u32(&mut descriptor, size);
+            out.write_all(&descriptor)?;
+            (crc, compressed, size)
+        };
+
+        put_u32(&mut central_dir, 0x0201_4b50);
+        // Made by Unix (3), so that extractors apply the mode in the external attributes
+        put_u16(&mut central_dir, 0x0314);
+        put_u16(&mut central_dir, 20);
+        put_u16(&mut central_dir, flags);
+        put_u16(&mut central_dir, method);
+        put_u16(&mut central_dir, time);
+        put_u16(&mut central_dir, date);
+        put_u32(&mut central_dir, crc);
+        put_u32(&mut central_dir, compressed);
+        put_u32(&mut central_dir, size);
+        put_u16(&mut central_dir, name.len() as u16);
+        central_dir.extend_from_slice(&[0; 8]);
+        let file_type = if entry.is_dir { 0o040_000 } else { 0o100_000 };
+        let dos_dir = if entry.is_dir { 0x10 } else { 0 };
+        put_u32(&mut central_dir, (file_type | mode(&meta)) << 16 | dos_dir);
+        put_u32(&mut central_dir, offset);
+        central_dir.extend_from_slice(name.as_bytes());
+    }
+
+    let central_dir_offset = out.count;
+    out.write_all(&central_dir)?;
+    if out.count > u64::from(u32::MAX) {
+        return Err(io::Error::new(
+            io::ErrorKind::Other,
+            "archive is too large for zip (use tar)",
+        ));
+    }
+    let mut end = Vec::new();
+    put_u32(&mut end, 0x0605_4b50);
+    put_u32(&mut end, 0);
+    put_u16(&mut end, entries.len() as u16);
+    put_u16(&mut end, entries.len() as u16);
+    put_u32(&mut end, central_dir.len() as u32);
+    put_u32(&mut end, central_dir_offset as u32);
+    put_u16(&mut end, 0);
+    out.write_all(&end)
+}
+
+fn zip_u32(value: u64, entry: &Entry) -> io::Result<u32> {
+    if value > u64::from(u32::MAX) {
+        return Err(too_large(entry, "zip"));
+    }
+    Ok(value as u32)
+}
+
+fn put_u16(buf: &mut Vec<u8>, value: u16) {
+    buf.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put_u32(buf: &mut Vec<u8>, value: u32) {
+    buf.extend_from_slice(&value.to_le_bytes());
+}
+
+// The modification time as an MS-DOS time and date (in local time, as zip tools expect)
+fn dos_time(meta: &Metadata) -> (u16, u16) {
+    let modified = match meta.modified() {
+        Ok(modified) => DateTime::<Local>::from(modified),
+        Err(_) => return (0, 0x21),
+    };
+    // DOS dates start at 1980
+    let year = (modified.year().max(1980) - 1980).min(127) as u16;
+    let time = (modified.hour() << 11 | modified.minute() << 5 | (modified.second() / 2)) as u16;
+    let date = year << 9 | (modified.month() << 5 | modified.day()) as u16;
+    (time, date)
+}
+
+fn mtime(meta: &Metadata) -> u64 {
+    meta.modified()
+        .ok()
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |since| since.as_secs())
+}
+
+#[cfg(unix)]
+fn mode(meta: &Metadata) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+    meta.permissions().mode() & 0o7777
+}
+
+#[cfg(not(unix))]
+fn mode(meta: &Metadata) -> u32 {
+    if meta.is_dir() {
+        0o755
+    } else {
+        0o644
+    }
+}
+
+fn too_large(entry: &Entry, format: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Other,
+        format!("{} is too large for {}", entry.path.display(), format),
+    )
+}
+
+fn changed(entry: &Entry) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Other,
+        format!("{} changed while it was archived", entry.path.display()),
+    )
+}
+
+// Compresses what is written to it as a gzip (RFC 1952) stream
+struct GzipWriter<W: Write> {
+    deflater: DeflateWriter<W>,
+    crc: u32,
+    // Bytes written (mod 2^32), for the trailer
+    size: u32,
+}
+
+impl<W: Write> GzipWriter<W> {
+    fn new(mut inner: W) -> io::Result<GzipWriter<W>> {
+        // Deflate, with no flags or modification time, from an unknown OS
+        inner.write_all(&[0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 0xff])?;
+        Ok(GzipWriter {
+            deflater: DeflateWriter::new(inner),
+            crc: 0,
+            size: 0,
+        })
+    }
+
+    fn finish(self) -> io::Result<()> {
+        let mut inner = self.deflater.finish()?;
+        inner.write_all(&self.crc.to_le_bytes())?;
+        inner.write_all(&self.size.to_le_bytes())
+    }
+}
+
+impl<W: Write> Write for GzipWriter<W> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.deflater.write_all(data)?;
+        self.crc = crc32(self.crc, data);
+        self.size = self.size.wrapping_add(data.len() as u32);
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.deflater.flush()
+    }
+}
+
+// Compresses what is written to it as a raw deflate stream
+struct DeflateWriter<W: Write> {
+    inner: W,
+    compressor: Box<CompressorOxide>,
+    buf: Vec<u8>,
+}
+
+impl<W: Write> DeflateWriter<W> {
+    fn new(inner: W) -> DeflateWriter<W> {
+        let mut compressor = Box::new(CompressorOxide::default());
+        compressor.set_format_and_level(DataFormat::Raw, 6);
+        DeflateWriter {
+            inner,
+            compressor,
+            buf: vec![0; CHUNK_SIZE],
+        }
+    }
+
+    // Deflates `input`, returning the number of bytes consumed and whether the stream ended
+    fn deflate(&mut self, input: &[u8], flush: MZFlush) -> io::Result<(usize, bool)> {
+        let result = deflate(&mut self.compressor, input, &mut self.buf, flush);
+        let status = result
+            .status
+            .map_err(|err| io::Error::new(io::ErrorKind::Other, format!("{:?}", err)))?;
+        self.inner.write_all(&self.buf[..result.bytes_written])?;
+        Ok((result.bytes_consumed, status == MZStatus::StreamEnd))
+    }
+
+    // Ends the stream, returning the writer it was written to
+    fn finish(mut self) -> io::Result<W> {
+        while !self.deflate(&[], MZFlush::Finish)?.1 {}
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for DeflateWriter<W> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        let mut consumed = 0;
+        while consumed < data.len() {
+            consumed += self.deflate(&data[consumed..], MZFlush::None)?.0;
+        }
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+// Tallies the CRC-32 and size of what is written through it (e.g. an extracted zip entry)
+struct CrcWriter<W> {
+    inner: W,
+    crc: u32,
+    size: u64,
+}
+
+impl<W: Write> Write for CrcWriter<W> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(data)?;
+        self.crc = crc32(self.crc, &data[..n]);
+        self.size += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+struct CountingWriter<W> {
+    inner: W,
+    count: u64,
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(data)?;
+        self.count += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+// The CRC-32 (as used by gzip and zip) of `data`, continuing from the CRC of what preceded it
+pub fn crc32(crc: u32, data: &[u8]) -> u32 {
+    let mut crc = !crc;
+    for &byte in data {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                crc >> 1 ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use miniz_oxide::inflate::decompress_to_vec;
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(0, b"123456789"), 0xcbf4_3926);
+        assert_eq!(crc32(crc32(0, b"1234"), b"56789"), 0xcbf4_3926);
+    }
+
+    #[test]
+    fn test_gzip_writer() {
+        let mut out = Vec::new();
+        let mut gzip = GzipWriter::new(&mut out).unwrap();
+        gzip.write_all(b"hello ").unwrap();
+        gzip.write_all(b"hello\n").unwrap();
+        gzip.finish().unwrap();
+
+        assert_eq!(&out[..3], &[0x1f, 0x8b, 8]);
+        let trailer = &out[out.len() - 8..];
+        assert_eq!(trailer[..4], crc32(0, b"hello hello\n").to_le_bytes());
+        assert_eq!(trailer[4..], 12u32.to_le_bytes());
+        let inflated = decompress_to_vec(&out[10..out.len() - 8]).unwrap();
+        assert_eq!(inflated, b"hello hello\n");
+    }
+
+    #[test]
+    fn test_tar_header() {
+        let header = tar_header("dir/a.txt", b'0', 0o644, 5, 0);
+        assert_eq!(&header[..9], b"dir/a.txt");
+        assert_eq!(&header[124..136], b"00000000005\0");
+        let mut unchecked = header;
+        unchecked[148..156].copy_from_slice(b"        ");
+        let sum: u32 = unchecked.iter().map(|&b| u32::from(b)).sum();
+        assert_eq!(&header[148..156], format!("{:06o}\0 ", sum).as_bytes());
+    }
+
+    #[test]
+    fn test_extract_round_trip() {
+        let root = std::env::temp_dir().join(format!("mia-archive-{}", std::process::id()));
+        let src = root.join("src");
+        fs::create_dir_all(src.join("sub/empty")).unwrap();
+        fs::write(src.join("a.txt"), "alpha").unwrap();
+        let long_name = "x".repeat(120);
+        fs::write(src.join("sub").join(&long_name), vec![7; 70_000]).unwrap();
+
+        for &format in &[ArchiveFormat::Tar, ArchiveFormat::TarGz, ArchiveFormat::Zip] {
+            let entries = list_entries(&src, &mut PathFilter::new(&[], &[])).unwrap();
+            let mut archive = Vec::new();
+            write_archive(format, &entries, &mut archive).unwrap();
+
+            // Detected by its contents, since the name has no extension
+            let out = root.join(format.extension());
+            assert_eq!(extract("bundle", &archive[..], &out).unwrap(), 2);
+            assert_eq!(fs::read(out.join("a.txt")).unwrap(), b"alpha");
+            assert_eq!(
+                fs::read(out.join("sub").join(&long_name)).unwrap().len(),
+                70_000
+            );
+            assert!(out.join("sub/empty").is_dir());
+        }
+
+        // Detected by its contents despite a misleading name
+        let entries = list_entries(&src, &mut PathFilter::new(&[], &[])).unwrap();
+        let mut zip = Vec::new();
+        write_archive(ArchiveFormat::Zip, &entries, &mut zip).unwrap();
+        let out = root.join("misnamed");
+        assert_eq!(extract("bundle.tar", &zip[..], &out).unwrap(), 2);
+
+        // A corrupt entry isn't left behind
+        let descriptor = zip.windows(4).position(|w| w == b"PK\x07\x08").unwrap();
+        zip[descriptor + 4] ^= 1;
+        let out = root.join("corrupt");
+        assert!(extract("bundle.zip", &zip[..], &out).is_err());
+        assert!(!out.join("a.txt").exists());
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_entry_path() {
+        let dest = Path::new("out");
+        assert_eq!(entry_path(dest, "./a/b.txt").unwrap(), dest.join("a/b.txt"));
+        assert!(entry_path(dest, "a/../../b.txt").is_err());
+        assert!(entry_path(dest, "/etc/passwd").is_err());
+    }
+}
@@ -1,24 +1,146 @@
+use super::charset::{self, Transcoder};
+use super::download;
+use super::Copied;
 use crate::config::Profile;
-use crate::CmdRunner;
+use crate::{data, CmdRunner};
 use algorithmia::data::{DataFile, HasDataPath};
 use algorithmia::Algorithmia;
 use docopt::Docopt;
+use encoding_rs::{Encoding, UTF_8};
+use regex::bytes::{Regex, RegexBuilder};
+use reqwest::blocking::Response;
+use reqwest::StatusCode;
 
-use std::io;
+use std::io::{self, BufRead, Cursor, Read, Write};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
 use std::vec::IntoIter;
 
-static USAGE: &'static str = r##"Usage: mia cat <data-file>...
+static USAGE: &'static str = r##"Usage: mia cat [options] <data-file>...
 
   Concatenate file(s) from the Algorithmia Data API and print on standard output
+
+  If <data-file> is '-', newline-separated data URIs are read from STDIN
+  Paths without a 'data://' prefix are relative to the profile's base_data_path, if set
+
+  Options:
+    --head-bytes <n>    Only print the first <n> bytes of each file
+    --tail-bytes <n>    Only print the last <n> bytes of each file (using a ranged read)
+    --line-range <range>
+                        Only print lines in <range> of each file, like 100-200 (1-indexed and
+                          inclusive) or 100- (to the end). Reading stops after the last line.
+                          With --from-charset or --to-charset, lines are counted after
+                          transcoding
+    --grep <regex>      Only print lines matching the regular expression. Lines are matched
+                          as they stream in, so files aren't buffered whole
+    -i                  With --grep, match case-insensitively
+    -v                  With --grep, only print lines that don't match
+    --hex               Print a canonical hex dump (offset, hex bytes, and ASCII)
+    --allow-short       Warn instead of failing if fewer bytes are received than the
+                          file's reported size
+    --raw               Print bytes as stored, without decoding a compressed Content-Encoding
+                          (gzip or deflate) that a file was stored with. Ranged reads of
+                          such files (--tail-bytes, --follow) require --raw
+    --from-charset <enc>
+                        Transcode from this character encoding (e.g. latin1, utf-16le,
+                          windows-1252). A byte order mark overrides it, so UTF-16 with a
+                          BOM is detected automatically (default: UTF-8)
+    --to-charset <enc>  Transcode to this character encoding (default: UTF-8)
+                        Without either option, bytes are printed unchanged
+    -f, --follow        After printing the file, keep polling it for appended bytes and print
+                          them as they appear, like `tail -f` (exit with Ctrl-C)
+    --poll-interval <seconds>
+                        How often --follow checks for new bytes [Default: 1]
+    --with-filenames    Print a '==> <data-file> <==' header before each file, like `tail`
+    --separator <text>  Print <text> (and a newline) between files
+                        These two only apply when more than one file is printed
+    --continue-on-error
+                        Print an error for a file that can't be printed and continue with
+                          the next one, exiting with an error at the end if any failed
+    --parallel <n>      Download up to <n> files at once, printing each whole as soon as it
+                          finishes (so in no particular order, and with each file held in
+                          memory until printed). Use --with-filenames to tell them apart
+
+  Examples:
+    mia cat data://.my/foo/file1.txt                  Print file1.txt
+    mia cat - < uris.txt                              Print each file listed in uris.txt
+    mia cat --continue-on-error - < uris.txt          Same as above, skipping files that fail
+    mia cat --with-filenames .my/foo/a.txt .my/foo/b.txt
+                                                      Print a.txt and b.txt, each under a header
+    mia cat --parallel 8 --with-filenames - < uris.txt
+                                                      Print the listed files as they download
+    mia cat --from-charset latin1 .my/foo/old.csv     Print old.csv converted to UTF-8
+    mia cat --head-bytes 16 --hex .my/foo/img.png     Show the magic number of img.png
+    mia cat --line-range 100-200 .my/foo/log.txt      Print lines 100 through 200 of log.txt
+    mia cat --grep -i 'error|warn' .my/foo/log.txt    Print the errors and warnings in log.txt
+    mia cat --follow --tail-bytes 1024 .my/foo/log.txt
+                                                      Print the end of log.txt and watch it grow
 "##;
 
 #[derive(RustcDecodable, Debug)]
 struct Args {
     arg_data_file: Vec<String>,
+    flag_head_bytes: Option<u64>,
+    flag_tail_bytes: Option<u64>,
+    flag_line_range: Option<String>,
+    flag_grep: Option<String>,
+    flag_i: bool,
+    flag_v: bool,
+    flag_hex: bool,
+    flag_allow_short: bool,
+    flag_raw: bool,
+    flag_from_charset: Option<String>,
+    flag_to_charset: Option<String>,
+    flag_follow: bool,
+    flag_poll_interval: u64,
+    flag_continue_on_error: bool,
+    flag_with_filenames: bool,
+    flag_separator: Option<String>,
+    flag_parallel: Option<usize>,
+}
+
+// How Cat::run prints a file, defaulting to printing it whole and unchanged
+#[derive(Default)]
+pub struct CatOptions {
+    pub span: Span,
+    // Print a canonical hex dump instead of the bytes themselves
+    pub hex: bool,
+    // Warn instead of failing on a short read
+    pub allow_short: bool,
+    // Print files as stored rather than decoding their Content-Encoding
+    pub raw: bool,
+    // Source and target encodings, if transcoding
+    pub charsets: Option<(&'static Encoding, &'static Encoding)>,
+    // Only print the lines a Grep selects (after the span and transcoding)
+    pub grep: Option<Grep>,
 }
 
+// Selects lines by whether they match a regex, or with `invert`, whether they don't
+pub struct Grep {
+    pub regex: Regex,
+    pub invert: bool,
+}
+
+// The part of each file to print
+pub enum Span {
+    All,
+    Head(u64),
+    Tail(u64),
+    // Lines from the first to the last (if any), 1-indexed and inclusive
+    Lines(u64, Option<u64>),
+}
+
+impl Default for Span {
+    fn default() -> Span {
+        Span::All
+    }
+}
+
+#[derive(Clone)]
 pub struct Cat {
     client: Algorithmia,
+    base_path: Option<String>,
 }
 impl CmdRunner for Cat {
     fn get_usage() -> &'static str {
@@ -30,8 +152,175 @@ impl CmdRunner for Cat {
             .and_then(|d| d.argv(argv).decode())
             .unwrap_or_else(|e| e.exit());
 
-        for f in args.arg_data_file {
-            cat_file(&self.client.file(&f))
+        let line_range = args.flag_line_range.as_ref().map(|range| {
+            parse_line_range(range).unwrap_or_else(|err| quit_msg!("Invalid --line-range: {}", err))
+        });
+        let span = match (args.flag_head_bytes, args.flag_tail_bytes, line_range) {
+            (Some(n), None, None) => Span::Head(n),
+            (None, Some(n), None) => Span::Tail(n),
+            (None, None, Some((first, last))) => Span::Lines(first, last),
+            (None, None, None) => Span::All,
+            _ => quit_msg!("Only one of --head-bytes, --tail-bytes, and --line-range can be given"),
+        };
+        if args.flag_hex && line_range.is_some() {
+            quit_msg!("--hex cannot be combined with --line-range");
+        }
+        let grep = match args.flag_grep {
+            Some(_) if args.flag_hex => quit_msg!("--hex cannot be combined with --grep"),
+            Some(ref pattern) => {
+                let regex = RegexBuilder::new(pattern)
+                    .case_insensitive(args.flag_i)
+                    .build()
+                    .unwrap_or_else(|err| quit_msg!("Invalid --grep: {}", err));
+                Some(Grep {
+                    regex,
+                    invert: args.flag_v,
+                })
+            }
+            None if args.flag_i || args.flag_v => quit_msg!("-i and -v require --grep"),
+            None => None,
+        };
+        let charsets = match (&args.flag_from_charset, &args.flag_to_charset) {
+            (None, None) => None,
+            (_, _) if args.flag_hex => {
+                quit_msg!("--hex cannot be combined with --from-charset or --to-charset")
+            }
+            (from, to) => {
+                let lookup = |label: &Option<String>| match label {
+                    Some(label) => {
+                        charset::lookup(label).unwrap_or_else(|err| quit_msg!("{}", err))
+                    }
+                    None => UTF_8,
+                };
+                let (from, to) = (lookup(from), lookup(to));
+                // Check the target is supported before downloading anything
+                if let Err(err) = Transcoder::new(io::sink(), from, to) {
+                    quit_msg!("{}", err);
+                }
+                Some((from, to))
+            }
+        };
+        let opts = CatOptions {
+            span,
+            hex: args.flag_hex,
+            allow_short: args.flag_allow_short,
+            raw: args.flag_raw,
+            charsets,
+            grep,
+        };
+
+        if args.flag_parallel == Some(0) {
+            quit_msg!("--parallel must be at least 1");
+        }
+
+        if args.flag_follow {
+            if args.flag_parallel.is_some() {
+                quit_msg!("--follow cannot be combined with --parallel");
+            }
+            if args.arg_data_file.len() != 1 || args.arg_data_file[0] == "-" {
+                quit_msg!("--follow requires a single <data-file> (not '-')");
+            }
+            let from_end = match opts.span {
+                Span::All => true,
+                Span::Tail(n) => n > 0,
+                _ => false,
+            };
+            if !from_end || opts.hex || opts.charsets.is_some() {
+                quit_msg!(
+                    "--follow can only be combined with --tail-bytes (of at least 1) \
+                     and --allow-short"
+                );
+            }
+            let path = data::resolve_path(self.base_path.as_deref(), &args.arg_data_file[0]);
+            let data_file = self.client.file(&path);
+            let interval = Duration::from_secs(args.flag_poll_interval.max(1));
+            // Appended bytes may finish a line that was started before, so one filter is used
+            let mut stdout = io::stdout();
+            let mut grep_lines;
+            let out: &mut dyn Write = match opts.grep {
+                Some(ref grep) => {
+                    grep_lines = GrepLines::new(&mut stdout, grep);
+                    &mut grep_lines
+                }
+                None => &mut stdout,
+            };
+            let result = cat_file(&data_file, &opts, out)
+                .and_then(|end| follow(&data_file, end, interval, opts.raw, out));
+            if let Err(err) = result {
+                quit_msg!("{}", err);
+            }
+            return;
+        }
+
+        // Headers and separators only apply to several files (which '-' may list)
+        let multiple = args.arg_data_file.len() > 1 || args.arg_data_file.iter().any(|f| f == "-");
+        let mut printed = 0;
+        let mut failed = 0;
+        let mut header = |path: &str| {
+            if multiple {
+                let mut stdout = io::stdout();
+                let mut written = Ok(());
+                if let (Some(separator), true) = (&args.flag_separator, printed > 0) {
+                    written = written.and_then(|_| writeln!(stdout, "{}", separator));
+                }
+                if args.flag_with_filenames {
+                    // Like `tail`, headers after the first are preceded by a blank line
+                    //   (unless there is a separator instead)
+                    let blank = match (&args.flag_separator, printed) {
+                        (None, n) if n > 0 => "\n",
+                        _ => "",
+                    };
+                    written = written.and_then(|_| writeln!(stdout, "{}==> {} <==", blank, path));
+                }
+                if let Err(err) = written {
+                    quit_err!("Error writing output: {}", err);
+                }
+            }
+            printed += 1;
+        };
+        let mut fail = |err: String| {
+            if !args.flag_continue_on_error {
+                quit_msg!("{}", err);
+            }
+            eprintln_red!("{}", err);
+            failed += 1;
+        };
+
+        match args.flag_parallel {
+            // Files are printed whole in the order they finish downloading
+            Some(workers) => {
+                let files = args.arg_data_file.clone();
+                for (path, result) in self.cat_parallel(files, opts, workers) {
+                    header(&path);
+                    let written = result.and_then(|bytes| {
+                        io::stdout()
+                            .write_all(&bytes)
+                            .map_err(|err| format!("Error copying data: {}", err))
+                    });
+                    if let Err(err) = written {
+                        fail(err);
+                    }
+                }
+            }
+            None => each_path(&args.arg_data_file, |path| {
+                header(path);
+                if let Err(err) = self.run(path, &opts, &mut io::stdout()) {
+                    fail(err);
+                }
+            }),
+        }
+        if failed > 0 {
+            let code = if printed > failed {
+                crate::PARTIAL_FAILURE_CODE
+            } else {
+                1
+            };
+            quit_code!(
+                code,
+                "{} of {} file(s) failed to print (see above)",
+                failed,
+                printed
+            );
         }
     }
 }
@@ -40,21 +329,535 @@ impl Cat {
     pub fn new(profile: Profile) -> Self {
         Cat {
             client: profile.client(),
+            base_path: profile.base_data_path().map(String::from),
+        }
+    }
+
+    // Prints a data file (resolved against the base data path) to `out`
+    pub fn run(&self, path: &str, opts: &CatOptions, out: &mut dyn Write) -> Result<(), String> {
+        let path = data::resolve_path(self.base_path.as_deref(), path);
+        let data_file = self.client.file(&path);
+        match opts.grep {
+            Some(ref grep) => {
+                let mut grep_lines = GrepLines::new(out, grep);
+                cat_file(&data_file, opts, &mut grep_lines)?;
+                grep_lines
+                    .finish()
+                    .map_err(|err| format!("Error copying data: {}", err))
+            }
+            None => cat_file(&data_file, opts, out).map(|_| ()),
+        }
+    }
+
+    // Downloads files on `workers` threads, yielding each path with its printed bytes
+    //   (or error) as it completes. Each file is held in memory until it is received.
+    fn cat_parallel(
+        &self,
+        files: Vec<String>,
+        opts: CatOptions,
+        workers: usize,
+    ) -> chan::Receiver<(String, Result<Vec<u8>, String>)> {
+        let (path_tx, path_rx) = chan::sync(workers);
+        let (done_tx, done_rx) = chan::sync(workers);
+
+        // One Producer thread queuing up paths (which may be read from STDIN as they arrive)
+        thread::spawn(move || each_path(&files, |path| path_tx.send(path.to_string())));
+
+        let opts = Arc::new(opts);
+        for _ in 0..workers {
+            let thread_rx = path_rx.clone();
+            let thread_tx = done_tx.clone();
+            let thread_cat = self.clone();
+            let thread_opts = opts.clone();
+            thread::spawn(move || {
+                for path in thread_rx {
+                    let mut bytes = Vec::new();
+                    let result = thread_cat.run(&path, &thread_opts, &mut bytes);
+                    thread_tx.send((path, result.map(|_| bytes)));
+                }
+            });
         }
+        done_rx
     }
 }
 
-fn cat_file(data_file: &DataFile) {
-    match data_file.get() {
-        Ok(mut response) => {
-            let mut stdout = io::stdout();
+// Calls `f` with each <data-file>, reading a path per line from STDIN in place of '-'
+fn each_path<F: FnMut(&str)>(files: &[String], mut f: F) {
+    for file in files {
+        if file == "-" {
+            let stdin = io::stdin();
+            for line in stdin.lock().lines() {
+                let line = line.unwrap_or_else(|err| quit_err!("Error reading STDIN: {}", err));
+                let path = line.trim();
+                if !path.is_empty() {
+                    f(path)
+                }
+            }
+        } else {
+            f(file)
+        }
+    }
+}
+
+// Prints a data file, returning the offset in the file just past the bytes that were read
+fn cat_file(data_file: &DataFile, opts: &CatOptions, out: &mut dyn Write) -> Result<u64, String> {
+    // The expected length is only checked when printing whole files
+    let (mut reader, offset, expected) = match opts.span {
+        Span::Head(0) | Span::Tail(0) => return Ok(0),
+        Span::All => {
+            let download = download::get_file(data_file, opts.raw)?;
+            let size = download.size;
+            (Box::new(download) as Box<dyn Read>, 0, Some(size))
+        }
+        Span::Lines(..) => {
+            let download = download::get_file(data_file, opts.raw)?;
+            (Box::new(download) as Box<dyn Read>, 0, None)
+        }
+        Span::Head(n) => (get_head(data_file, n, opts.raw)?, 0, None),
+        Span::Tail(n) => {
+            let (reader, offset) = get_tail(data_file, n, opts.raw)?;
+            (reader, offset, None)
+        }
+    };
+
+    let mut bytes = Vec::new();
+    let expected = expected.unwrap_or(0);
+    let copied = if opts.hex {
+        data::copy_download(&mut reader, &mut bytes, expected)
+    } else if let Span::Lines(first, last) = opts.span {
+        let lines = LineRange::new(&mut *out, first, last);
+        copy_lines(&mut reader, lines, opts.charsets).map(Copied::Complete)
+    } else if let Some((from, to)) = opts.charsets {
+        // The expected length applies to the bytes downloaded, not the transcoded output
+        Transcoder::new(&mut *out, from, to)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))
+            .and_then(|mut transcoder| {
+                let copied = data::copy_download(&mut reader, &mut transcoder, expected)?;
+                transcoder.finish()?;
+                Ok(copied)
+            })
+    } else {
+        // Copy downloaded data to the output
+        data::copy_download(&mut reader, out, expected)
+    };
+    let copied = copied.map_err(|err| format!("Error copying data: {}", err))?;
+    if opts.hex {
+        let _ = out.write_all(hex_dump(&bytes, offset).as_bytes());
+    }
+
+    if let Copied::Short(_, ref msg) = copied {
+        let _ = out.flush();
+        if !opts.allow_short {
+            return Err(format!(
+                "Short read of {}: {}",
+                data_file.to_data_uri(),
+                msg
+            ));
+        }
+        notice!(
+            Warn,
+            "Warning: short read of {}: {}",
+            data_file.to_data_uri(),
+            msg
+        );
+    }
+    match copied {
+        Copied::Complete(n) | Copied::Short(n, _) => Ok(offset + n),
+    }
+}
+
+// Copies the lines a LineRange passes through, transcoding them first if requested
+//   Reading stops once the range ends, so the rest of the file isn't downloaded
+fn copy_lines<W: Write>(
+    reader: &mut dyn Read,
+    mut lines: LineRange<W>,
+    charsets: Option<(&'static Encoding, &'static Encoding)>,
+) -> io::Result<u64> {
+    match charsets {
+        Some((from, to)) => {
+            let mut transcoder = Transcoder::new(lines, from, to)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+            let copied = copy_until(reader, &mut transcoder, |t| t.get_ref().is_done())?;
+            transcoder.finish()?;
+            Ok(copied)
+        }
+        None => copy_until(reader, &mut lines, LineRange::is_done),
+    }
+}
+
+// Copies from reader to writer until the reader is exhausted or `done` returns true
+fn copy_until<W: Write, F: Fn(&W) -> bool>(
+    reader: &mut dyn Read,
+    writer: &mut W,
+    done: F,
+) -> io::Result<u64> {
+    let mut buf = [0; 64 * 1024];
+    let mut copied = 0;
+    while !done(writer) {
+        let n = match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        };
+        writer.write_all(&buf[..n])?;
+        copied += n as u64;
+    }
+    Ok(copied)
+}
+
+// A writer that only passes through the lines from `first` to `last` (1-indexed, inclusive)
+struct LineRange<W: Write> {
+    inner: W,
+    first: u64,
+    last: Option<u64>,
+    // The line currently being written
+    line: u64,
+}
+
+impl<W: Write> LineRange<W> {
+    fn new(inner: W, first: u64, last: Option<u64>) -> Self {
+        LineRange {
+            inner,
+            first,
+            last,
+            line: 1,
+        }
+    }
+
+    fn is_done(&self) -> bool {
+        self.last.map_or(false, |last| self.line > last)
+    }
+}
+
+impl<W: Write> Write for LineRange<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut rest = buf;
+        while !rest.is_empty() && !self.is_done() {
+            let (line, ended) = match rest.iter().position(|&b| b == b'\n') {
+                Some(i) => (&rest[..=i], true),
+                None => (rest, false),
+            };
+            if self.line >= self.first {
+                self.inner.write_all(line)?;
+            }
+            if ended {
+                self.line += 1;
+            }
+            rest = &rest[line.len()..];
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
 
-            // Copy downloaded data to stdout
-            match io::copy(&mut response, &mut stdout) {
-                Ok(_) => (),
-                Err(err) => quit_err!("Error copying data: {}", err),
+// A writer that only passes through the lines a Grep selects
+//   A line is buffered until its end is written (or finish is called for a last line
+//   without a newline), so only one line is held in memory at a time
+struct GrepLines<'a, W: Write + ?Sized> {
+    inner: &'a mut W,
+    grep: &'a Grep,
+    line: Vec<u8>,
+}
+
+impl<'a, W: Write + ?Sized> GrepLines<'a, W> {
+    fn new(inner: &'a mut W, grep: &'a Grep) -> Self {
+        GrepLines {
+            inner,
+            grep,
+            line: Vec::new(),
+        }
+    }
+
+    fn write_line(&mut self) -> io::Result<()> {
+        let mut text = &self.line[..];
+        for ending in &[b"\n", b"\r"] {
+            if text.ends_with(*ending) {
+                text = &text[..text.len() - 1];
             }
         }
-        Err(e) => quit_err!("Error downloading {}: {}", data_file.to_data_uri(), e),
+        if self.grep.regex.is_match(text) != self.grep.invert {
+            self.inner.write_all(&self.line)?;
+        }
+        self.line.clear();
+        Ok(())
+    }
+
+    // Writes the last line if it has no newline
+    fn finish(&mut self) -> io::Result<()> {
+        if !self.line.is_empty() {
+            self.write_line()?;
+        }
+        self.inner.flush()
+    }
+}
+
+impl<'a, W: Write + ?Sized> Write for GrepLines<'a, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut rest = buf;
+        while let Some(i) = rest.iter().position(|&b| b == b'\n') {
+            self.line.extend_from_slice(&rest[..=i]);
+            self.write_line()?;
+            rest = &rest[i + 1..];
+        }
+        self.line.extend_from_slice(rest);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+// Parses a line range like "100-200" or "100-" (open-ended)
+fn parse_line_range(range: &str) -> Result<(u64, Option<u64>), String> {
+    let invalid = || format!("'{}' (expected <first>-<last> or <first>-)", range);
+    let mut split = range.splitn(2, '-');
+    let first = split.next().and_then(|n| n.parse::<u64>().ok());
+    let first = first.filter(|&n| n > 0).ok_or_else(invalid)?;
+    let last = match split.next() {
+        Some("") => None,
+        Some(n) => Some(n.parse::<u64>().map_err(|_| invalid())?),
+        None => return Err(invalid()),
     };
+    if last.map_or(false, |last| last < first) {
+        return Err(format!("'{}' ends before it starts", range));
+    }
+    Ok((first, last))
+}
+
+// Issues a ranged GET (e.g. "bytes=0-255"), returning the body and the offset it starts at
+//   The offset is None if the server ignored the Range header and sent the whole file
+//   Unless `raw`, files stored with a compressed Content-Encoding are an error, since the
+//   range would be of the encoded bytes
+fn get_range(
+    data_file: &DataFile,
+    range: String,
+    raw: bool,
+) -> Result<(Box<dyn Read>, Option<u64>), String> {
+    let response = send_range(data_file, range)?;
+    if !response.status().is_success() {
+        return Err(download_err(data_file, &response.status()));
+    }
+    if !raw {
+        check_unencoded(data_file, &response)?;
+    }
+    let start = content_range(&response).and_then(|(start, _)| start.parse::<u64>().ok());
+    Ok((Box::new(response), start))
+}
+
+fn send_range(data_file: &DataFile, range: String) -> Result<Response, String> {
+    let url = data_file
+        .to_url()
+        .map_err(|err| download_err(data_file, &err))?;
+    data_file
+        .client()
+        .get(url)
+        .header("Range", range)
+        .send()
+        .map_err(|err| download_err(data_file, &err))
+}
+
+fn check_unencoded(data_file: &DataFile, response: &Response) -> Result<(), String> {
+    match download::content_encoding(response) {
+        Some(encoding) => Err(format!(
+            "{} is stored with Content-Encoding '{}', so only its encoded bytes can be read \
+             by range (use --raw to print them)",
+            data_file.to_data_uri(),
+            encoding
+        )),
+        None => Ok(()),
+    }
+}
+
+fn download_err(data_file: &DataFile, err: &dyn std::fmt::Display) -> String {
+    format!("Error downloading {}: {}", data_file.to_data_uri(), err)
+}
+
+// Splits a Content-Range header like "bytes 100-199/200" (or "bytes */200") into the range
+//   and the file size
+fn content_range(response: &Response) -> Option<(&str, &str)> {
+    let value = response.headers().get("Content-Range")?.to_str().ok()?;
+    let mut split = value.trim_start_matches("bytes ").splitn(2, '/');
+    let range = split.next()?;
+    Some((range.split('-').next()?, split.next()?))
+}
+
+// Polls a growing file every `interval` for bytes past `offset` (with ranged reads),
+//   printing them as they appear until interrupted. If the file shrinks, it was probably
+//   truncated or replaced, so it's followed again from the start.
+fn follow(
+    data_file: &DataFile,
+    mut offset: u64,
+    interval: Duration,
+    raw: bool,
+    out: &mut dyn Write,
+) -> Result<(), String> {
+    let copy_err = |err: io::Error| format!("Error copying data: {}", err);
+    loop {
+        out.flush().map_err(copy_err)?;
+        thread::sleep(interval);
+
+        let mut response = send_range(data_file, format!("bytes={}-", offset))?;
+        let size = content_range(&response).and_then(|(_, size)| size.parse::<u64>().ok());
+        if size.map_or(false, |size| size < offset) {
+            notice!(Warn, "{}: file truncated", data_file.to_data_uri());
+            offset = 0;
+            continue;
+        }
+        if response.status() == StatusCode::RANGE_NOT_SATISFIABLE {
+            // Nothing past the end yet
+            continue;
+        }
+        if !response.status().is_success() {
+            return Err(download_err(data_file, &response.status()));
+        }
+        if !raw {
+            check_unencoded(data_file, &response)?;
+        }
+
+        let start = content_range(&response).and_then(|(start, _)| start.parse::<u64>().ok());
+        let mut bytes = Vec::new();
+        response.read_to_end(&mut bytes).map_err(copy_err)?;
+        // A server that ignores the Range header sends the whole file
+        let new_bytes = match start {
+            Some(_) => &bytes[..],
+            None if (bytes.len() as u64) < offset => {
+                notice!(Warn, "{}: file truncated", data_file.to_data_uri());
+                offset = 0;
+                &bytes[..]
+            }
+            None => &bytes[offset as usize..],
+        };
+        out.write_all(new_bytes).map_err(copy_err)?;
+        offset += new_bytes.len() as u64;
+    }
+}
+
+// Fetches the first `n` bytes of a file
+//   A range of a file stored compressed would be of its encoded bytes, so unless `raw`,
+//   such a file is decoded from the start instead (reading only as much as needed)
+fn get_head(data_file: &DataFile, n: u64, raw: bool) -> Result<Box<dyn Read>, String> {
+    let response = send_range(data_file, format!("bytes=0-{}", n - 1))?;
+    if !response.status().is_success() {
+        return Err(download_err(data_file, &response.status()));
+    }
+    if !raw && download::content_encoding(&response).is_some() {
+        let download = download::get_file(data_file, false)?;
+        return Ok(Box::new(download.take(n)));
+    }
+    Ok(Box::new(response.take(n)))
+}
+
+// Fetches the last `n` bytes of a file, returning them along with their offset in the file
+fn get_tail(data_file: &DataFile, n: u64, raw: bool) -> Result<(Box<dyn Read>, u64), String> {
+    let (mut reader, start) = get_range(data_file, format!("bytes=-{}", n), raw)?;
+    let mut bytes = Vec::new();
+    if let Err(err) = reader.read_to_end(&mut bytes) {
+        return Err(format!("Error copying data: {}", err));
+    }
+    match start {
+        Some(start) => Ok((Box::new(Cursor::new(bytes)), start)),
+        // The whole file was sent, so only keep its end
+        None => {
+            let start = bytes.len().saturating_sub(n as usize);
+            let tail = bytes.split_off(start);
+            Ok((Box::new(Cursor::new(tail)), start as u64))
+        }
+    }
+}
+
+// Formats bytes as a canonical hex dump (like `hexdump -C`) with offsets starting at `offset`
+fn hex_dump(bytes: &[u8], offset: u64) -> String {
+    let mut out = String::new();
+    for (i, chunk) in bytes.chunks(16).enumerate() {
+        let mut hex = String::new();
+        for (j, byte) in chunk.iter().enumerate() {
+            if j == 8 {
+                hex.push(' ');
+            }
+            hex.push_str(&format!("{:02x} ", byte));
+        }
+        let ascii: String = chunk
+            .iter()
+            .map(|&b| match b {
+                0x20..=0x7e => b as char,
+                _ => '.',
+            })
+            .collect();
+        let line_offset = offset + (i * 16) as u64;
+        out.push_str(&format!("{:08x}  {:<49} |{}|\n", line_offset, hex, ascii));
+    }
+    out.push_str(&format!("{:08x}\n", offset + bytes.len() as u64));
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_hex_dump() {
+        let dump = hex_dump(b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR!", 0);
+        assert_eq!(
+            dump,
+            "00000000  89 50 4e 47 0d 0a 1a 0a  00 00 00 0d 49 48 44 52  |.PNG........IHDR|\n\
+             00000010  21                                                |!|\n\
+             00000011\n"
+        );
+        assert_eq!(hex_dump(b"", 32), "00000020\n");
+    }
+
+    #[test]
+    fn test_line_range() {
+        let lines = |input: &[u8], first, last| {
+            let mut range = LineRange::new(Vec::new(), first, last);
+            // Split the input to exercise lines spanning writes
+            for chunk in input.chunks(3) {
+                range.write_all(chunk).unwrap();
+            }
+            (
+                String::from_utf8(range.inner.clone()).unwrap(),
+                range.is_done(),
+            )
+        };
+        let text = b"one\ntwo\nthree\nfour";
+        assert_eq!(lines(text, 2, Some(3)), ("two\nthree\n".to_string(), true));
+        assert_eq!(lines(text, 3, None), ("three\nfour".to_string(), false));
+        assert_eq!(lines(text, 9, Some(10)), ("".to_string(), false));
+
+        assert_eq!(parse_line_range("100-200"), Ok((100, Some(200))));
+        assert_eq!(parse_line_range("100-"), Ok((100, None)));
+        assert!(parse_line_range("0-5").is_err());
+        assert!(parse_line_range("5-4").is_err());
+        assert!(parse_line_range("5").is_err());
+    }
+
+    #[test]
+    fn test_grep_lines() {
+        let grep = |pattern: &str, invert, input: &[u8]| {
+            let grep = Grep {
+                regex: RegexBuilder::new(pattern)
+                    .case_insensitive(true)
+                    .build()
+                    .unwrap(),
+                invert,
+            };
+            let mut out = Vec::new();
+            let mut lines = GrepLines::new(&mut out, &grep);
+            // Split the input to exercise lines spanning writes
+            for chunk in input.chunks(3) {
+                lines.write_all(chunk).unwrap();
+            }
+            lines.finish().unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        let log = b"INFO start\r\nerror: disk\nwarn: slow\nerror: net";
+        assert_eq!(grep("^error", false, log), "error: disk\nerror: net");
+        assert_eq!(grep("error", true, log), "INFO start\r\nwarn: slow\n");
+        assert_eq!(grep("start$", false, log), "INFO start\r\n");
+    }
 }
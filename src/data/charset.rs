@@ -0,0 +1,120 @@
+use encoding_rs::{CoderResult, Decoder, Encoder, Encoding, UTF_8};
+use std::io::{self, Write};
+
+const BUFFER_SIZE: usize = 8 * 1024;
+
+// Looks up a character encoding by label (e.g. "latin1", "utf-16le", "windows-1252")
+pub fn lookup(label: &str) -> Result<&'static Encoding, String> {
+    Encoding::for_label(label.as_bytes()).ok_or_else(|| format!("Unknown charset '{}'", label))
+}
+
+// A writer that transcodes the bytes written to it from one character encoding to another
+//
+// A byte order mark at the start of the input overrides the source encoding, so UTF-16
+//   input with a BOM is detected automatically. Invalid sequences become U+FFFD (or a
+//   numeric character reference when encoding to a legacy charset that lacks a character).
+//   Call `finish` once all input is written to flush any incomplete trailing sequence.
+pub struct Transcoder<W: Write> {
+    inner: W,
+    decoder: Decoder,
+    // None when encoding to UTF-8, which the decoder already produces
+    encoder: Option<Encoder>,
+    decoded: String,
+    encoded: Vec<u8>,
+}
+
+impl<W: Write> Transcoder<W> {
+    pub fn new(inner: W, from: &'static Encoding, to: &'static Encoding) -> Result<Self, String> {
+        // encoding_rs only encodes to ASCII-compatible encodings (e.g. not to UTF-16)
+        if to.output_encoding() != to {
+            return Err(format!("Converting to {} is not supported", to.name()));
+        }
+        Ok(Transcoder {
+            inner,
+            decoder: from.new_decoder(),
+            encoder: match to {
+                to if to == UTF_8 => None,
+                to => Some(to.new_encoder()),
+            },
+            decoded: String::with_capacity(BUFFER_SIZE),
+            encoded: Vec::with_capacity(BUFFER_SIZE),
+        })
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        self.transcode(&[], true)?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+
+    fn transcode(&mut self, mut src: &[u8], last: bool) -> io::Result<()> {
+        loop {
+            self.decoded.clear();
+            let (result, read, _) = self.decoder.decode_to_string(src, &mut self.decoded, last);
+            src = &src[read..];
+            let input_empty = result == CoderResult::InputEmpty;
+            self.write_decoded(last && input_empty)?;
+            if input_empty {
+                return Ok(());
+            }
+        }
+    }
+
+    fn write_decoded(&mut self, last: bool) -> io::Result<()> {
+        let encoder = match self.encoder {
+            Some(ref mut encoder) => encoder,
+            None => return self.inner.write_all(self.decoded.as_bytes()),
+        };
+        let mut src = &self.decoded[..];
+        loop {
+            self.encoded.clear();
+            let (result, read, _) = encoder.encode_from_utf8_to_vec(src, &mut self.encoded, last);
+            src = &src[read..];
+            self.inner.write_all(&self.encoded)?;
+            if result == CoderResult::InputEmpty {
+                return Ok(());
+            }
+        }
+    }
+}
+
+impl<W: Write> Write for Transcoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.transcode(buf, false)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn transcode(bytes: &[u8], from: &str, to: &str) -> Vec<u8> {
+        let from = lookup(from).unwrap();
+        let to = lookup(to).unwrap();
+        let mut transcoder = Transcoder::new(Vec::new(), from, to).unwrap();
+        // Split the input to exercise sequences spanning writes
+        for chunk in bytes.chunks(3) {
+            transcoder.write_all(chunk).unwrap();
+        }
+        transcoder.finish().unwrap()
+    }
+
+    #[test]
+    fn test_transcode() {
+        assert_eq!(transcode(b"caf\xe9", "latin1", "utf-8"), "café".as_bytes());
+        assert_eq!(transcode("café".as_bytes(), "utf-8", "latin1"), b"caf\xe9");
+        // A UTF-16 BOM overrides the source charset
+        let utf16 = b"\xff\xfeh\x00\xe9\x00";
+        assert_eq!(transcode(utf16, "utf-8", "utf-8"), "hé".as_bytes());
+        assert!(Transcoder::new(Vec::new(), UTF_8, lookup("utf-16le").unwrap()).is_err());
+    }
+}
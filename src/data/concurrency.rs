@@ -0,0 +1,291 @@
+// Limits how many transfers run at once across the worker threads
+//   A fixed limit lets every worker transfer, while an adaptive limit starts low and is
+//   raised (up to the number of workers) while aggregate throughput improves, and halved
+//   when a transfer fails (e.g. a 429 from the Data API)
+//
+// A HostQueue additionally limits the transfers to each host (e.g. each storage connector),
+//   handing workers the next file whose host has a free slot
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+// The limit an adaptive limit starts at
+const ADAPTIVE_START: usize = 2;
+// The shortest period whose throughput is compared to the last
+const MIN_WINDOW: Duration = Duration::from_millis(500);
+// How much throughput must improve over the last window to raise the limit
+const IMPROVEMENT: f64 = 1.1;
+
+pub struct ConcurrencyLimit {
+    state: Mutex<State>,
+    available: Condvar,
+    verbose: bool,
+}
+
+impl ConcurrencyLimit {
+    pub fn fixed(workers: usize) -> ConcurrencyLimit {
+        ConcurrencyLimit::new(State::new(workers, workers, false), false)
+    }
+
+    pub fn adaptive(workers: usize, verbose: bool) -> ConcurrencyLimit {
+        let start = ADAPTIVE_START.min(workers);
+        ConcurrencyLimit::new(State::new(start, workers, true), verbose)
+    }
+
+    fn new(state: State, verbose: bool) -> ConcurrencyLimit {
+        ConcurrencyLimit {
+            state: Mutex::new(state),
+            available: Condvar::new(),
+            verbose,
+        }
+    }
+
+    // Waits until another transfer may start
+    pub fn acquire(&self) {
+        let mut state = self.state.lock().unwrap();
+        while state.active >= state.limit {
+            state = self.available.wait(state).unwrap();
+        }
+        state.active += 1;
+    }
+
+    // Ends a transfer started with acquire, given the bytes it transferred (None if it failed)
+    pub fn release(&self, bytes: Option<u64>) {
+        let mut state = self.state.lock().unwrap();
+        state.active -= 1;
+        let before = state.limit;
+        state.record(bytes, Instant::now());
+        if self.verbose && state.limit != before {
+            let reason = if bytes.is_none() {
+                "after a failed transfer"
+            } else {
+                "as throughput improved"
+            };
+            notice!(
+                Info,
+                "Adjusted concurrency from {} to {} {}",
+                before,
+                state.limit,
+                reason
+            );
+        }
+        self.available.notify_all();
+    }
+}
+
+struct State {
+    limit: usize,
+    max: usize,
+    adaptive: bool,
+    active: usize,
+    window_start: Instant,
+    window_bytes: u64,
+    window_transfers: usize,
+    // Bytes per second during the last complete window
+    last_rate: Option<f64>,
+}
+
+impl State {
+    fn new(limit: usize, max: usize, adaptive: bool) -> State {
+        State {
+            limit: limit.max(1),
+            max: max.max(1),
+            adaptive,
+            active: 0,
+            window_start: Instant::now(),
+            window_bytes: 0,
+            window_transfers: 0,
+            last_rate: None,
+        }
+    }
+
+    fn record(&mut self, bytes: Option<u64>, now: Instant) {
+        if !self.adaptive {
+            return;
+        }
+        let bytes = match bytes {
+            Some(bytes) => bytes,
+            None => {
+                self.limit = (self.limit / 2).max(1);
+                self.last_rate = None;
+                self.reset_window(now);
+                return;
+            }
+        };
+
+        self.window_bytes += bytes;
+        self.window_transfers += 1;
+        // A window spans at least one transfer per slot, so each limit is measured fairly
+        let elapsed = now.duration_since(self.window_start);
+        if self.window_transfers < self.limit || elapsed < MIN_WINDOW {
+            return;
+        }
+        let rate = self.window_bytes as f64 / elapsed.as_secs_f64();
+        let improved = match self.last_rate {
+            Some(last_rate) => rate > last_rate * IMPROVEMENT,
+            None => true,
+        };
+        if improved && self.limit < self.max {
+            self.limit += 1;
+        }
+        self.last_rate = Some(rate);
+        self.reset_window(now);
+    }
+
+    fn reset_window(&mut self, now: Instant) {
+        self.window_start = now;
+        self.window_bytes = 0;
+        self.window_transfers = 0;
+    }
+}
+
+// The host of a data URI, as its connector and authority (e.g. "s3://bucket" or "data://.my")
+pub fn uri_host(uri: &str) -> &str {
+    let start = uri.find("://").map_or(0, |i| i + 3);
+    match uri[start..].find('/') {
+        Some(end) => &uri[..start + end],
+        None => uri,
+    }
+}
+
+// A queue of transfers that allows at most `per_host` of them to run at once for each host,
+//   without a host at its limit holding up the files queued behind it for other hosts
+pub struct HostQueue<T> {
+    per_host: usize,
+    state: Mutex<QueueState<T>>,
+    available: Condvar,
+}
+
+struct QueueState<T> {
+    pending: VecDeque<(String, T)>,
+    active: HashMap<String, usize>,
+    closed: bool,
+}
+
+impl<T> HostQueue<T> {
+    // A queue without a per-host limit hands out files in order, like a channel
+    pub fn new(per_host: Option<usize>) -> HostQueue<T> {
+        HostQueue {
+            per_host: per_host.unwrap_or(usize::MAX).max(1),
+            state: Mutex::new(QueueState {
+                pending: VecDeque::new(),
+                active: HashMap::new(),
+                closed: false,
+            }),
+            available: Condvar::new(),
+        }
+    }
+
+    pub fn push(&self, host: &str, item: T) {
+        let mut state = self.state.lock().unwrap();
+        state.pending.push_back((host.to_string(), item));
+        self.available.notify_all();
+    }
+
+    // Marks that nothing more will be pushed, so that pop returns None once the queue is empty
+    pub fn close(&self) {
+        self.state.lock().unwrap().closed = true;
+        self.available.notify_all();
+    }
+
+    // Takes the first queued file whose host is below its limit, waiting while there is none
+    //   Returns its host, to release once it is transferred, or None when the queue is done
+    pub fn pop(&self) -> Option<(String, T)> {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            let per_host = self.per_host;
+            let next = {
+                let active = &state.active;
+                state
+                    .pending
+                    .iter()
+                    .position(|(host, _)| active.get(host).map_or(0, |&n| n) < per_host)
+            };
+            if let Some(index) = next {
+                let (host, item) = state.pending.remove(index).unwrap();
+                *state.active.entry(host.clone()).or_insert(0) += 1;
+                return Some((host, item));
+            }
+            if state.closed && state.pending.is_empty() {
+                return None;
+            }
+            state = self.available.wait(state).unwrap();
+        }
+    }
+
+    pub fn release(&self, host: &str) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(active) = state.active.get_mut(host) {
+            *active -= 1;
+        }
+        self.available.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_adaptive_limit() {
+        let start = Instant::now();
+        let mut state = State::new(2, 4, true);
+        state.window_start = start;
+        let at = |ms| start + Duration::from_millis(ms);
+
+        // A window needs a transfer per slot and MIN_WINDOW to pass
+        state.record(Some(1000), at(100));
+        state.record(Some(1000), at(200));
+        assert_eq!(state.limit, 2);
+        state.record(Some(1000), at(1000));
+        assert_eq!(state.limit, 3);
+
+        // Throughput improving raises the limit, but not past the max
+        for (i, limit) in [4, 4].iter().enumerate() {
+            let end = 2000 + 1000 * i as u64;
+            for _ in 0..state.limit {
+                state.record(Some(10_000 * (i as u64 + 2)), at(end));
+            }
+            assert_eq!(state.limit, *limit);
+        }
+
+        // Throughput not improving holds the limit
+        for _ in 0..4 {
+            state.record(Some(1000), at(5000));
+        }
+        assert_eq!(state.limit, 4);
+
+        // A failure halves it
+        state.record(None, at(5100));
+        assert_eq!(state.limit, 2);
+        state.record(None, at(5200));
+        state.record(None, at(5300));
+        assert_eq!(state.limit, 1);
+    }
+
+    #[test]
+    fn test_host_queue() {
+        assert_eq!(uri_host("s3://bucket/a/b.txt"), "s3://bucket");
+        assert_eq!(uri_host("data://.my/a.txt"), "data://.my");
+
+        let queue = HostQueue::new(Some(1));
+        for (host, item) in &[("a", 1), ("a", 2), ("b", 3)] {
+            queue.push(host, *item);
+        }
+        queue.close();
+        // "a" is at its limit, so its second file waits while "b"'s goes ahead
+        assert_eq!(queue.pop(), Some(("a".to_string(), 1)));
+        assert_eq!(queue.pop(), Some(("b".to_string(), 3)));
+        queue.release("a");
+        assert_eq!(queue.pop(), Some(("a".to_string(), 2)));
+        assert_eq!(queue.pop(), None);
+    }
+
+    #[test]
+    fn test_fixed_limit() {
+        let mut state = State::new(3, 3, false);
+        state.record(None, Instant::now());
+        assert_eq!(state.limit, 3);
+    }
+}
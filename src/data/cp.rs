@@ -1,45 +1,307 @@
-use super::size_with_suffix;
+use super::acl::{self, ReadAccess};
+use super::archive::{self, ArchiveFormat};
+use super::concurrency::{self, ConcurrencyLimit, HostQueue};
+use super::download;
+use super::ignore::PathFilter;
+use super::progress::ProgressFd;
+use super::{size_with_suffix, Copied};
 use crate::config::Profile;
-use crate::CmdRunner;
-use algorithmia::data::{DataFile, DataItem, HasDataPath};
-use algorithmia::Algorithmia;
+use crate::deadline::{self, Deadline};
+use crate::{data, CmdRunner};
+use algorithmia::data::{DataAcl, DataDir, DataFile, DataItem, HasDataPath};
+use algorithmia::{Algorithmia, Body};
 use chan;
+use chrono::{DateTime, Utc};
 use docopt::Docopt;
-use std::fs::File;
-use std::path::Path;
+use rustc_serialize::json;
+use std::collections::{HashMap, HashSet};
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
+use std::time::Instant;
 use std::vec::IntoIter;
 use std::{clone, cmp, fs, io, thread};
 
 static USAGE: &'static str = r##"Usage:
-  mia cp [options] <source>... <dest>
-  mia copy [options] <source>... <dest>
+  mia cp [options] [--include <glob> | --exclude <glob>]... <source>... <dest>
+  mia cp [options] <source>
+  mia copy [options] [--include <glob> | --exclude <glob>]... <source>... <dest>
+  mia copy [options] <source>
 
   Copy files to or from the Algorithmia Data API
 
   An Algorithmia Data URL must be prefixed with data:// in order to avoid potential path ambiguity
+  (so a profile's base_data_path does not apply to cp)
+
+  If <dest> is omitted when downloading a single file, it is saved to the working directory.
+  When uploading several files to a <dest> that doesn't exist, it is created as a directory.
+
+  A <source> of '-' uploads STDIN to <dest>. To upload STDIN into a data directory, name the
+  file to create with --name. A <dest> of '-' downloads a single data file to STDOUT (with
+  the lines cp normally prints going to STDERR instead).
+
+  With -r, each local directory source is uploaded with its contents, skipping paths
+  matched by --exclude, not matched by --include (if given), or listed in a .algoignore
+  file. A .algoignore file lists one glob pattern per line and applies to the directory
+  containing it. --exclude takes precedence over --include, which takes precedence over
+  .algoignore, and skipped directories are not descended into.
 
   Options:
-    -c <CONCURRENCY>    Number of threads for uploading in parallel [Default: 8]
+    -c <CONCURRENCY>      Number of threads for uploading in parallel [Default: 8]
+    --adaptive-concurrency
+                          Start with 2 threads and add more (up to -c) while throughput
+                            improves, halving them when a transfer fails (e.g. when rate
+                            limited), for endpoints where the best -c isn't known
+    --concurrency-per-host <n>
+                          Transfer at most <n> files at once from each host (a connector and
+                            its bucket or account, e.g. s3://bucket), so that downloads from
+                            several backends share -c fairly (uploads go to one host, so
+                            there it only lowers -c)
+    --log-file <path>     Append a JSON line per transferred file to <path>
+    --progress-fd <n>     Write progress as a JSON line per file (with running totals) and a
+                            final line of totals (or of the error, if cp exits early) to
+                            the open file descriptor <n>, for programs wrapping mia (Unix
+                            only)
+    -r, --recursive       Upload directories recursively
+    --archive <format>    Upload a local directory source as a single archive file instead,
+                            written as it is uploaded: tar, tar.gz, or zip. It is named
+                            <directory>.<format> if <dest> is a data directory. Paths are
+                            filtered by --include, --exclude, and .algoignore files as with -r
+    --include <glob>      With -r, only upload files matching the pattern (repeatable)
+    --exclude <glob>      With -r, skip paths matching the pattern (repeatable)
+    --create-empty-dirs   With -r, create data directories for local directories with nothing
+                            to upload (by default they are skipped with a notice)
+    --rename-dupes        When downloading several files with the same name, save later ones
+                            as name-1.ext, name-2.ext, ... (by default this is an error)
+    --allow-short         Keep a download that received fewer bytes than the file's reported
+                            size (by default it is deleted and counted as failed)
+    --min-free-space <size>
+                          Fail each download that would leave less than <size> (e.g. 10G) free
+                            on the destination's filesystem, checked before each file (Unix only)
+    --unarchive           Extract a downloaded tar, tar.gz, or zip file into the <dest>
+                            directory as it is downloaded (detected by its contents, or else
+                            its extension), refusing entries that would land outside of <dest>
+    --raw                 Save downloads as stored, without decoding a compressed
+                            Content-Encoding (gzip or deflate) that a file was stored with
+    --deadline <seconds>  Stop starting new transfers after <seconds>, then summarize what
+                            completed and exit with an error. Transfers in progress get 5 more
+                            seconds to finish before they are cut off
+    --summary-by-source   Report completed, failed, and bytes for each source argument
+    --newer-than <when>   Only transfer files modified after <when>: a duration ago (e.g. 30m,
+                            24h, 7d) or a timestamp (e.g. 2024-05-01 or 2024-05-01T12:00:00Z).
+                            Uses local modification times for uploads and the Data API's
+                            last-modified times for downloads
+    --ignore-errors       Exit successfully even if some files failed to transfer (failures
+                            are still reported on STDERR)
+    --name <filename>     With a '-' source, upload STDIN as <filename> in the <dest> directory
+    --acl <acl>           Set the ACL of each data directory uploaded into: private,
+                            my-algorithms, or public (see 'mia acl --help')
+    --no-create-dirs      Fail instead of creating missing parent data directories of <dest>
+                            (like `mkdir -p`, which is done by default)
 
   Examples:
     mia cp file1.jpg file2.jpg data://.my/foo          Upload 2 files to your 'foo' data directory
     mia cp data://.my/foo/file1.jpg .                  Download file1.jpg to the workig directory
+    mia cp data://.my/foo/file1.jpg                    Same as above (destination inferred)
+    mia cp -r --exclude '*.log' proj data://.my/foo    Upload 'proj' and its contents except logs
+    mia cp --acl public out.csv data://.my/shared      Upload out.csv and make 'shared' public
+    mia cp --archive zip imgs data://.my/foo/imgs.zip  Upload 'imgs' zipped as imgs.zip
+    cat foo | mia cp - data://.my/foo/ --name out.txt  Upload STDIN as out.txt in 'foo'
+    mia cp data://.my/foo/a.tar - | tar x              Extract a.tar without saving it
+    mia cp --unarchive data://.my/foo/out.zip out      Extract out.zip into 'out'
 "##;
 
 // TODO:
-// -r                   Recursive copy if the source is a directory
+// -r                   Recursive download if the source is a data directory
+//   --flatten          With it, save every file into <dest> by its name, without the remote
+//                        directories (names that collide handled like --rename-dupes, as
+//                        downloads of several files already are)
+// --multipart          Upload large files in parallel chunks, retrying only failed chunks
+//                        (blocked on the Data API exposing a multipart upload endpoint)
+//                        -c would then also bound the chunk workers, so that one large file
+//                        uses up to -c threads rather than one (likewise for downloads, if
+//                        they are split into ranged reads)
+// --hash-threads <N>  Hash files for --verify in a pool separate from the transfer workers,
+//                        sized min(num_cpus, -c) by default so hashing doesn't oversubscribe
+//                        cores (blocked on --verify, as there is no checksum support yet)
+// --verify-against <manifest>
+//                      Hash each file listed in a manifest of expected hashes (local or in
+//                        <dest>) and report matches, mismatches, and missing files without
+//                        transferring anything (blocked on checksum support and a manifest
+//                        format, neither of which exist yet)
+// mia sync <src> <dest> [--delete]
+//                      Diff a local tree and a data directory (either way) by name, size, and
+//                        hash, transferring only the differences with this transfer engine and
+//                        with --delete removing dest files missing from <src> (blocked on -r
+//                        downloads and checksum support)
+// --idempotent         Send an idempotency key (from the content hash) with each upload, so a
+//                        retried upload is applied once (blocked on the Data API honoring such
+//                        keys, and only useful with upload retries; for now each upload is a PUT
+//                        to a fixed path, so repeating one replaces the file rather than adding
+//                        a duplicate)
+// --atomic-dir         With -r, upload into a temporary data directory and only swap it into
+//                        place (removing the old directory) if every file uploads, otherwise
+//                        removing it and leaving <dest> untouched (blocked on the Data API
+//                        supporting moving or renaming a directory, as there is no `mv`)
 
 #[derive(RustcDecodable, Debug)]
 struct Args {
     arg_source: Vec<String>,
-    arg_dest: String,
+    arg_dest: Option<String>,
     flag_c: u32,
+    flag_adaptive_concurrency: bool,
+    flag_concurrency_per_host: Option<usize>,
+    flag_log_file: Option<String>,
+    flag_progress_fd: Option<i32>,
+    flag_recursive: bool,
+    flag_archive: Option<String>,
+    flag_include: Vec<String>,
+    flag_exclude: Vec<String>,
+    flag_summary_by_source: bool,
+    flag_acl: Option<String>,
+    flag_name: Option<String>,
+    flag_allow_short: bool,
+    flag_raw: bool,
+    flag_unarchive: bool,
+    flag_min_free_space: Option<String>,
+    flag_deadline: Option<u64>,
+    flag_ignore_errors: bool,
+    flag_newer_than: Option<String>,
+    flag_rename_dupes: bool,
+    flag_no_create_dirs: bool,
+    flag_create_empty_dirs: bool,
 }
 
+// The source argument for uploading STDIN
+const STDIN_SOURCE: &str = "-";
+// The dest argument for downloading a file to STDOUT
+const STDOUT_DEST: &str = "-";
+
 pub struct Cp {
     client: Algorithmia,
 }
+
+// Options for Cp::run, defaulting to the same as the command line's
+//   (except `verbose` and `on_event`, which the command uses to print its progress)
+pub struct CpOptions {
+    // Number of files transferred in parallel
+    pub concurrency: u32,
+    // Adjust the number of transfers in parallel (up to `concurrency`) to throughput
+    pub adaptive_concurrency: bool,
+    // Limit the files transferred in parallel from (or to) each host
+    pub concurrency_per_host: Option<usize>,
+    // Print notices, like directories skipped without `recursive` and ACLs set
+    pub verbose: bool,
+    // Called (from the transfer threads) as each file is transferred, skipped, or fails
+    pub on_event: Option<TransferCallback>,
+    // Append a JSON line per transferred file to this path
+    pub log_file: Option<String>,
+    // Upload directories recursively, filtered by glob patterns and .algoignore files
+    pub recursive: bool,
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+    // Upload a local directory as a single archive, filtered like `recursive`
+    pub archive: Option<ArchiveFormat>,
+    // Set the ACL of each data directory uploaded into
+    pub acl: Option<ReadAccess>,
+    // With a '-' (STDIN) source, the name of the file to create in the dest directory
+    pub stdin_name: Option<String>,
+    pub allow_short: bool,
+    // Download files as stored rather than decoding their Content-Encoding
+    pub raw: bool,
+    // Extract a downloaded archive into dest
+    pub unarchive: bool,
+    // Fail downloads that would leave fewer bytes than this free on the local filesystem
+    pub min_free_space: Option<u64>,
+    // Seconds after which no new transfers are started
+    pub deadline: Option<u64>,
+    pub rename_dupes: bool,
+    // Only transfer files modified after this
+    pub newer_than: Option<DateTime<Utc>>,
+    // Create missing parent data directories of dest when uploading
+    pub create_dirs: bool,
+    // With `recursive`, create data directories for local directories with nothing to upload
+    pub create_empty_dirs: bool,
+}
+
+impl Default for CpOptions {
+    fn default() -> CpOptions {
+        CpOptions {
+            concurrency: 8,
+            adaptive_concurrency: false,
+            concurrency_per_host: None,
+            verbose: false,
+            on_event: None,
+            log_file: None,
+            recursive: false,
+            include: Vec::new(),
+            exclude: Vec::new(),
+            archive: None,
+            acl: None,
+            stdin_name: None,
+            allow_short: false,
+            raw: false,
+            unarchive: false,
+            min_free_space: None,
+            deadline: None,
+            rename_dupes: false,
+            newer_than: None,
+            create_dirs: true,
+            create_empty_dirs: false,
+        }
+    }
+}
+
+pub type TransferCallback = Arc<dyn Fn(&TransferEvent) + Send + Sync>;
+
+// A file that Cp::run transferred, skipped, or failed to transfer
+#[derive(Debug, PartialEq)]
+pub struct TransferEvent {
+    // The data URI uploaded to or downloaded from (or the local path of a skipped
+    //   or failed upload)
+    pub path: String,
+    pub bytes: u64,
+    pub status: TransferStatus,
+    // The ETag of an uploaded file, if the Data API sent one
+    pub etag: Option<String>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum TransferStatus {
+    Uploaded,
+    Downloaded,
+    // Not modified after `newer_than`
+    Skipped,
+    Failed(String),
+}
+
+// What Cp::run transferred
+#[derive(Debug, Default, PartialEq)]
+pub struct CpSummary {
+    pub uploaded: u64,
+    pub downloaded: u64,
+    // Files not modified after `newer_than`
+    pub skipped: u64,
+    pub failed: u64,
+    // Bytes transferred
+    pub bytes: u64,
+    // Whether the deadline stopped new transfers from starting
+    pub stopped_early: bool,
+    // Tallies for each source argument, in order
+    pub by_source: Vec<SourceSummary>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct SourceSummary {
+    pub source: String,
+    pub completed: u64,
+    pub failed: u64,
+    pub bytes: u64,
+}
+
 impl CmdRunner for Cp {
     fn get_usage() -> &'static str {
         USAGE
@@ -50,15 +312,172 @@ impl CmdRunner for Cp {
             .and_then(|d| d.argv(argv).decode())
             .unwrap_or_else(|e| e.exit());
 
-        let cp_client = CpClient::new(self.client.clone(), args.flag_c, &args.arg_dest);
+        let dest = match args.arg_dest {
+            Some(dest) => dest,
+            // A lone remote source downloads into the working directory using its basename
+            None if args.arg_source.len() == 1 && args.arg_source[0].contains("://") => {
+                ".".to_string()
+            }
+            None => quit_msg!(
+                "Must specify a destination unless downloading a single file\n\n{}",
+                USAGE
+            ),
+        };
 
-        // Download if the dest is a local path or prefixed with file://_
-        //   otherwise, assume upload
-        let dest_parts: Vec<_> = args.arg_dest.splitn(2, "://").collect();
-        if dest_parts.len() < 2 || dest_parts[0] == "file" {
-            cp_client.download(args.arg_source);
-        } else {
-            cp_client.upload(args.arg_source);
+        let acl = args
+            .flag_acl
+            .map(|name| ReadAccess::parse(&name).unwrap_or_else(|err| quit_msg!("{}", err)));
+        let newer_than = args.flag_newer_than.map(|when| {
+            data::parse_time_threshold(&when, Utc::now())
+                .unwrap_or_else(|err| quit_msg!("Invalid --newer-than: {}", err))
+        });
+        let progress = args
+            .flag_progress_fd
+            .map(|fd| Arc::new(ProgressFd::open(fd).unwrap_or_else(|err| quit_msg!("{}", err))));
+        let min_free_space = args.flag_min_free_space.map(|size| {
+            data::parse_size(&size)
+                .unwrap_or_else(|err| quit_msg!("Invalid --min-free-space: {}", err))
+        });
+        let archive = args
+            .flag_archive
+            .map(|name| ArchiveFormat::parse(&name).unwrap_or_else(|err| quit_msg!("{}", err)));
+        let download = is_local(&dest);
+        // Downloading to STDOUT leaves it to the file, so cp's own lines go to STDERR
+        let to_stdout = dest == STDOUT_DEST;
+        let event_progress = progress.clone();
+        let deadline_progress = progress.clone();
+        // Files completed and failed so far, for the summary if the deadline cuts cp off
+        let tally = Arc::new((AtomicUsize::new(0), AtomicUsize::new(0)));
+        let event_tally = tally.clone();
+        let opts = CpOptions {
+            concurrency: args.flag_c,
+            adaptive_concurrency: args.flag_adaptive_concurrency,
+            concurrency_per_host: args.flag_concurrency_per_host,
+            verbose: true,
+            on_event: Some(Arc::new(move |event| {
+                print_event(event, download, to_stdout);
+                match event.status {
+                    TransferStatus::Uploaded | TransferStatus::Downloaded => {
+                        event_tally.0.fetch_add(1, Ordering::SeqCst);
+                    }
+                    TransferStatus::Failed(_) => {
+                        event_tally.1.fetch_add(1, Ordering::SeqCst);
+                    }
+                    TransferStatus::Skipped => (),
+                }
+                if let Some(ref progress) = event_progress {
+                    progress.file(event);
+                }
+            })),
+            log_file: args.flag_log_file,
+            recursive: args.flag_recursive,
+            include: args.flag_include,
+            exclude: args.flag_exclude,
+            archive,
+            acl,
+            stdin_name: args.flag_name,
+            allow_short: args.flag_allow_short,
+            raw: args.flag_raw,
+            unarchive: args.flag_unarchive,
+            min_free_space,
+            deadline: args.flag_deadline,
+            rename_dupes: args.flag_rename_dupes,
+            newer_than,
+            create_dirs: !args.flag_no_create_dirs,
+            create_empty_dirs: args.flag_create_empty_dirs,
+        };
+
+        let verb = if download { "download" } else { "upload" };
+        let deadline_secs = args.flag_deadline.unwrap_or(0);
+        deadline::exit_after(args.flag_deadline, move || {
+            let completed = tally.0.load(Ordering::SeqCst);
+            let failed = tally.1.load(Ordering::SeqCst);
+            print_line(
+                &format!("Finished {}ing {} file(s)", verb, completed),
+                to_stdout,
+            );
+            let mut reason = format!(
+                "the deadline of {}s was reached, and transfers still in progress {}s later \
+                 were cut off",
+                deadline_secs,
+                deadline::GRACE_SECS
+            );
+            if failed > 0 {
+                reason = format!("{} ({} other file(s) failed, see above)", reason, failed);
+            }
+            if let Some(ref progress) = deadline_progress {
+                progress.error(&reason);
+            }
+            quit_code!(1, "{}", reason);
+        });
+        let summary = self
+            .run(args.arg_source, &dest, opts)
+            .unwrap_or_else(|err| {
+                if let Some(ref progress) = progress {
+                    progress.error(&err);
+                }
+                quit_msg!("{}", err)
+            });
+        if let Some(ref progress) = progress {
+            progress.done(&summary);
+        }
+
+        let completed = summary.uploaded + summary.downloaded;
+        print_line(
+            &format!("Finished {}ing {} file(s)", verb, completed),
+            to_stdout,
+        );
+        if let Some(threshold) = newer_than {
+            if summary.skipped > 0 {
+                let skipped = format!(
+                    "Skipped {} file(s) not modified since {}",
+                    summary.skipped,
+                    threshold.to_rfc3339()
+                );
+                print_line(&skipped, to_stdout);
+            }
+        }
+        if args.flag_summary_by_source {
+            for source in &summary.by_source {
+                let line = format!(
+                    "  {}: {} completed, {} failed ({}B)",
+                    source.source,
+                    source.completed,
+                    source.failed,
+                    size_with_suffix(source.bytes)
+                );
+                print_line(&line, to_stdout);
+            }
+        }
+        let deadline = format!(
+            "the deadline of {}s was reached before all files were {}ed",
+            deadline_secs, verb
+        );
+        if summary.failed > 0 && !args.flag_ignore_errors {
+            let code = if completed > 0 {
+                crate::PARTIAL_FAILURE_CODE
+            } else {
+                1
+            };
+            let mut reason = format!(
+                "{} of {} file(s) failed to {} (see above)",
+                summary.failed,
+                completed + summary.failed,
+                verb
+            );
+            if summary.stopped_early {
+                reason = format!("{}, and {}", reason, deadline);
+            }
+            quit_code!(code, "{}", reason);
+        } else if summary.failed > 0 {
+            eprintln_red!(
+                "Failed to {} {} file(s) (ignored with --ignore-errors)",
+                verb,
+                summary.failed
+            );
+        }
+        if summary.stopped_early {
+            quit_code!(1, "{}", deadline);
         }
     }
 }
@@ -69,12 +488,140 @@ impl Cp {
             client: profile.client(),
         }
     }
+
+    // Copies the sources to dest, downloading if dest is a local path (or prefixed with
+    //   file://) and otherwise uploading. A dest of '-' downloads a single file to STDOUT.
+    //   Files that fail to transfer are counted in the summary, while an error means the
+    //   copy couldn't be carried out (or was cut short)
+    pub fn run(
+        &self,
+        sources: Vec<String>,
+        dest: &str,
+        opts: CpOptions,
+    ) -> Result<CpSummary, String> {
+        let log = match opts.log_file {
+            Some(ref path) => Some(TransferLog::open(path)?),
+            None => None,
+        };
+        let mut cp_client = CpClient::new(self.client.clone(), opts.concurrency, dest, log);
+        cp_client.verbose = opts.verbose;
+        cp_client.adaptive = opts.adaptive_concurrency;
+        if opts.concurrency_per_host == Some(0) {
+            return Err("--concurrency-per-host must be at least 1".to_string());
+        }
+        cp_client.per_host = opts.concurrency_per_host;
+        cp_client.on_event = opts.on_event;
+        let verbose = opts.verbose;
+        cp_client.acl = opts
+            .acl
+            .map(|access| Arc::new(AclUpdater::new(access, verbose)));
+
+        let from_stdin = sources.iter().any(|s| s == STDIN_SOURCE);
+        if from_stdin && sources.len() > 1 {
+            return Err(format!(
+                "'{}' (STDIN) must be the only source",
+                STDIN_SOURCE
+            ));
+        }
+        if opts.stdin_name.is_some() && !from_stdin {
+            return Err(format!(
+                "--name only applies when uploading STDIN ('{}')",
+                STDIN_SOURCE
+            ));
+        }
+        cp_client.stdin_name = opts.stdin_name;
+        cp_client.allow_short = opts.allow_short;
+        cp_client.raw = opts.raw;
+        cp_client.min_free_space = opts.min_free_space;
+        cp_client.deadline = Deadline::start(opts.deadline);
+        cp_client.rename_dupes = opts.rename_dupes;
+        cp_client.newer_than = opts.newer_than;
+        cp_client.create_dirs = opts.create_dirs;
+        cp_client.create_empty_dirs = opts.create_empty_dirs;
+
+        if is_local(dest) {
+            if opts.acl.is_some() {
+                return Err("--acl only applies when uploading".to_string());
+            }
+            if from_stdin {
+                return Err(format!("'{}' (STDIN) can only be uploaded", STDIN_SOURCE));
+            }
+            if opts.archive.is_some() {
+                return Err("--archive only applies when uploading".to_string());
+            }
+            if opts.min_free_space.is_some() && !cfg!(unix) {
+                return Err("--min-free-space is only supported on Unix".to_string());
+            }
+            if dest == STDOUT_DEST {
+                if sources.len() != 1 {
+                    return Err(format!(
+                        "Only a single data file can be downloaded to '{}' (STDOUT)",
+                        STDOUT_DEST
+                    ));
+                }
+                cp_client.max_concurrency = 1;
+            }
+            if opts.unarchive {
+                if sources.len() != 1 || dest == STDOUT_DEST {
+                    return Err(
+                        "--unarchive requires a single data file and a directory to extract it to"
+                            .to_string(),
+                    );
+                }
+                if opts.newer_than.is_some() || opts.min_free_space.is_some() {
+                    return Err(
+                        "--unarchive cannot be combined with --newer-than or --min-free-space"
+                            .to_string(),
+                    );
+                }
+                return cp_client.download_archive(&sources[0]);
+            }
+            cp_client.download(sources)
+        } else if opts.min_free_space.is_some() {
+            Err("--min-free-space only applies when downloading".to_string())
+        } else if opts.unarchive {
+            Err("--unarchive only applies when downloading".to_string())
+        } else if let Some(format) = opts.archive {
+            if sources.len() != 1 || from_stdin {
+                return Err("--archive requires a single local directory source".to_string());
+            }
+            if opts.newer_than.is_some() {
+                return Err("--archive cannot be combined with --newer-than".to_string());
+            }
+            let filter = PathFilter::new(&opts.include, &opts.exclude);
+            cp_client.upload_archive(&sources[0], format, filter)
+        } else if opts.recursive {
+            let filter = PathFilter::new(&opts.include, &opts.exclude);
+            cp_client.upload(sources, Some(filter))
+        } else {
+            cp_client.upload(sources, None)
+        }
+    }
 }
 
 struct CpClient {
     client: Algorithmia,
     max_concurrency: u32,
+    adaptive: bool,
+    per_host: Option<usize>,
     dest: Arc<String>,
+    log: Option<Arc<TransferLog>>,
+    verbose: bool,
+    on_event: Option<TransferCallback>,
+    acl: Option<Arc<AclUpdater>>,
+    stdin_name: Option<String>,
+    allow_short: bool,
+    raw: bool,
+    min_free_space: Option<u64>,
+    deadline: Deadline,
+    rename_dupes: bool,
+    // What dest is, looked up once before uploading (see resolve_dest)
+    dest_kind: DestKind,
+    // Files last modified at or before this are skipped (and counted)
+    newer_than: Option<DateTime<Utc>>,
+    skipped_old: Arc<AtomicU64>,
+    create_dirs: bool,
+    create_empty_dirs: bool,
 }
 
 impl clone::Clone for CpClient {
@@ -82,36 +629,186 @@ impl clone::Clone for CpClient {
         CpClient {
             client: self.client.clone(),
             max_concurrency: self.max_concurrency,
+            adaptive: self.adaptive,
+            per_host: self.per_host,
             dest: self.dest.clone(),
+            log: self.log.clone(),
+            verbose: self.verbose,
+            on_event: self.on_event.clone(),
+            acl: self.acl.clone(),
+            stdin_name: self.stdin_name.clone(),
+            allow_short: self.allow_short,
+            raw: self.raw,
+            min_free_space: self.min_free_space,
+            deadline: self.deadline.clone(),
+            rename_dupes: self.rename_dupes,
+            dest_kind: self.dest_kind,
+            newer_than: self.newer_than,
+            skipped_old: self.skipped_old.clone(),
+            create_dirs: self.create_dirs,
+            create_empty_dirs: self.create_empty_dirs,
         }
     }
 }
 
 impl CpClient {
-    fn new(client: Algorithmia, max_concurrency: u32, dest: &str) -> CpClient {
+    fn new(
+        client: Algorithmia,
+        max_concurrency: u32,
+        dest: &str,
+        log: Option<TransferLog>,
+    ) -> CpClient {
         CpClient {
             client: client,
             max_concurrency: max_concurrency,
+            adaptive: false,
+            per_host: None,
             dest: Arc::new(dest.to_string()),
+            log: log.map(Arc::new),
+            verbose: false,
+            on_event: None,
+            acl: None,
+            stdin_name: None,
+            allow_short: false,
+            raw: false,
+            min_free_space: None,
+            deadline: Deadline::start(None),
+            rename_dupes: false,
+            dest_kind: DestKind::New,
+            newer_than: None,
+            skipped_old: Arc::new(AtomicU64::new(0)),
+            create_dirs: false,
+            create_empty_dirs: false,
+        }
+    }
+
+    fn report(&self, path: &str, bytes: u64, status: TransferStatus) {
+        self.report_event(TransferEvent {
+            path: path.to_string(),
+            bytes,
+            status,
+            etag: None,
+        });
+    }
+
+    fn report_upload(&self, uri: &str, bytes: u64, etag: Option<String>) {
+        self.report_event(TransferEvent {
+            path: uri.to_string(),
+            bytes,
+            status: TransferStatus::Uploaded,
+            etag,
+        });
+    }
+
+    fn report_event(&self, event: TransferEvent) {
+        if let Some(ref on_event) = self.on_event {
+            on_event(&event);
+        }
+    }
+
+    fn log_transfer(
+        &self,
+        source: &str,
+        destination: &str,
+        bytes: u64,
+        started: Instant,
+        error: Option<String>,
+    ) {
+        self.log_upload(source, destination, bytes, started, error, None)
+    }
+
+    // Logs a transfer, with the ETag of an uploaded file if the Data API sent one
+    fn log_upload(
+        &self,
+        source: &str,
+        destination: &str,
+        bytes: u64,
+        started: Instant,
+        error: Option<String>,
+        etag: Option<&str>,
+    ) {
+        if let Some(ref log) = self.log {
+            log.record(&TransferLogEntry {
+                timestamp: chrono::Utc::now().to_rfc3339(),
+                source,
+                destination,
+                bytes,
+                status: if error.is_none() { "ok" } else { "failed" },
+                error,
+                etag,
+                duration: started.elapsed().as_secs_f64(),
+            });
         }
     }
 
-    fn upload(&self, sources: Vec<String>) {
+    // Uploads the sources, recursing into directories if given a filter for recursion
+    fn upload(
+        &self,
+        sources: Vec<String>,
+        recursive: Option<PathFilter>,
+    ) -> Result<CpSummary, String> {
+        // The same local file may be listed more than once (e.g. overlapping globs)
+        let sources = dedup_sources(sources, |path| match fs::canonicalize(path) {
+            Ok(canonical) => canonical.display().to_string(),
+            Err(_) => path.to_string(),
+        });
+        let mut conn = self.clone();
+        conn.dest_kind = self.resolve_dest(sources.len())?;
+
         // As long as we aren't recursing, we can be more aggressive in limiting threads we spin up
-        // TODO: when supporting dir recursion, fall-back to max_concurrency
-        let concurrency = cmp::min(sources.len(), self.max_concurrency as usize);
+        let concurrency = match recursive {
+            Some(_) => self.max_concurrency as usize,
+            None => cmp::min(sources.len(), self.max_concurrency as usize),
+        };
+        // Every file goes to dest's host
+        let concurrency = cmp::min(concurrency, self.per_host.unwrap_or(usize::MAX));
 
         let (tx, rx) = chan::sync(self.max_concurrency as usize);
         let wg = chan::WaitGroup::new();
-        let completed = Arc::new(Mutex::new(0));
+        let stats = Arc::new(TransferStats::new(&sources));
+        let limit = Arc::new(self.concurrency_limit(concurrency));
 
-        // One Producer thread queuing up file paths to upload
-        thread::spawn(move || {
-            for path in sources {
-                // TODO: if recursing and is_dir: recurse_and_send(&tx, path)
-                tx.send(path);
+        // One Producer thread queuing up file paths (and the data dir for recursive uploads)
+        //   An error (e.g. creating a data dir) stops queuing, but queued files are still uploaded
+        let producer_conn = conn.clone();
+        let producer = thread::spawn(move || -> Result<(), String> {
+            for (source, path) in sources.into_iter().enumerate() {
+                if producer_conn.deadline.should_stop() {
+                    break;
+                }
+                match recursive {
+                    Some(ref filter) if Path::new(&path).is_dir() => {
+                        let remote_dir = producer_conn.recursive_upload_root(&path)?;
+                        let root = Path::new(&path);
+                        // Each source starts from just --include and --exclude, so that one
+                        //   source's .algoignore files don't apply to another
+                        let mut filter = filter.clone();
+                        let mut walk = TreeWalk {
+                            tx: &tx,
+                            source,
+                            filter: &mut filter,
+                            uncreated: Vec::new(),
+                        };
+                        producer_conn.send_tree(&mut walk, root, "", &remote_dir)?;
+                    }
+                    None if Path::new(&path).is_dir() => {
+                        if producer_conn.verbose {
+                            notice!(
+                                Info,
+                                "Skipping directory {} (use -r to upload recursively)",
+                                path
+                            );
+                        }
+                    }
+                    _ if producer_conn.is_old_local(Path::new(&path)) => (),
+                    _ => tx.send(UploadItem {
+                        source,
+                        path,
+                        dir: None,
+                    }),
+                }
             }
-            drop(tx);
+            Ok(())
         });
 
         // Spin up threads to concurrently upload files per that paths received on rx channel
@@ -120,37 +817,46 @@ impl CpClient {
 
             let thread_wg = wg.clone();
             let thread_rx = rx.clone();
-            let thread_conn = self.clone();
-            let thread_completed = completed.clone();
+            let thread_conn = conn.clone();
+            let thread_stats = stats.clone();
+            let thread_limit = limit.clone();
 
             thread::spawn(move || {
-                for rx_path in thread_rx {
-                    let dest_obj = thread_conn.client.data(&*thread_conn.dest);
-                    let put_res = match dest_obj.into_type() {
-                        // If dest exists as DataFile, overwrite it
-                        Ok(DataItem::File(f)) => {
-                            let file = File::open(&*rx_path).unwrap();
-                            f.put(file).map(|_| f.to_data_uri())
-                        }
-                        // If dest exists as DataDir, add file to dir
-                        Ok(DataItem::Dir(d)) => d
-                            .put_file(&rx_path)
-                            .map(|_| d.child::<DataFile>(&rx_path).to_data_uri()),
-                        // Otherwise, try adding new file with exact path as dest
-                        Err(_) => {
-                            let file = File::open(&*rx_path).unwrap();
-                            let f = thread_conn.client.file(&*thread_conn.dest);
-                            f.put(file).map(|_| f.to_data_uri())
-                        }
-                    };
+                for item in thread_rx {
+                    if thread_conn.deadline.should_stop() {
+                        continue;
+                    }
+                    let (rx_path, rx_dir) = (item.path, item.dir);
+                    thread_limit.acquire();
+                    let started = Instant::now();
+                    let put_res =
+                        catch_panic(|| thread_conn.upload_file(&rx_path, rx_dir.as_deref()));
+                    thread_limit.release(put_res.as_ref().ok().map(|&(_, bytes, _)| bytes));
 
                     match put_res {
-                        Ok(uri) => {
-                            println!("Uploaded {}", uri);
-                            let mut count = thread_completed.lock().unwrap();
-                            *count += 1;
+                        Ok((uri, bytes, etag)) => {
+                            thread_conn.log_upload(
+                                &rx_path,
+                                &uri,
+                                bytes,
+                                started,
+                                None,
+                                etag.as_deref(),
+                            );
+                            thread_conn.report_upload(&uri, bytes, etag);
+                            thread_stats.record(item.source, Some(bytes));
+                        }
+                        Err(e) => {
+                            thread_conn.log_transfer(
+                                &rx_path,
+                                &thread_conn.dest,
+                                0,
+                                started,
+                                Some(e.clone()),
+                            );
+                            thread_conn.report(&rx_path, 0, TransferStatus::Failed(e));
+                            thread_stats.record(item.source, None);
                         }
-                        Err(e) => quit_err!("Error uploading {}: {}", rx_path, e),
                     };
                 }
                 thread_wg.done();
@@ -158,46 +864,412 @@ impl CpClient {
         }
 
         wg.wait();
-        println!("Finished uploading {} file(s)", *completed.lock().unwrap());
+        producer
+            .join()
+            .unwrap_or_else(|_| Err("unexpected panic while queuing files".to_string()))?;
+        Ok(self.summarize(&stats, false))
+    }
+
+    // Uploads a local directory as a single archive, to dest or (if dest is a data directory)
+    //   to a file in it named after the directory
+    fn upload_archive(
+        &self,
+        source: &str,
+        format: ArchiveFormat,
+        mut filter: PathFilter,
+    ) -> Result<CpSummary, String> {
+        let root = Path::new(source);
+        if !root.is_dir() {
+            return Err(format!(
+                "--archive requires a local directory, not {}",
+                source
+            ));
+        }
+        let entries = archive::list_entries(root, &mut filter)?;
+        let file = match self.resolve_dest(1)? {
+            DestKind::Dir => {
+                let name = fs::canonicalize(root)
+                    .ok()
+                    .and_then(|p| p.file_name().map(|n| n.to_string_lossy().into_owned()))
+                    .ok_or_else(|| format!("Cannot determine directory name of {}", source))?;
+                let name = format!("{}.{}", name, format.extension());
+                self.client.dir(&*self.dest).child::<DataFile>(&name)
+            }
+            _ => self.client.file(&*self.dest),
+        };
+        if self.verbose {
+            notice!(
+                Info,
+                "Archiving {} ({} entries) as {}",
+                source,
+                entries.len(),
+                format.extension()
+            );
+        }
+
+        let stats = TransferStats::new(&[source.to_string()]);
+        let started = Instant::now();
+        let count = Arc::new(AtomicU64::new(0));
+        let reader = CountingReader {
+            inner: archive::stream(format, entries),
+            count: count.clone(),
+        };
+        let uri = file.to_data_uri();
+        let put_res = put_file(&file, Body::new(reader)).and_then(|etag| {
+            if let Some(ref acl) = self.acl {
+                acl.apply(&file)?;
+            }
+            Ok(etag)
+        });
+        match put_res {
+            Ok(etag) => {
+                let bytes = count.load(Ordering::SeqCst);
+                self.log_upload(source, &uri, bytes, started, None, etag.as_deref());
+                self.report_upload(&uri, bytes, etag);
+                stats.record(0, Some(bytes));
+            }
+            Err(e) => {
+                self.log_transfer(source, &uri, 0, started, Some(e.clone()));
+                self.report(source, 0, TransferStatus::Failed(e));
+                stats.record(0, None);
+            }
+        }
+        Ok(self.summarize(&stats, false))
+    }
+
+    // Limits the transfers of the given number of worker threads
+    fn concurrency_limit(&self, workers: usize) -> ConcurrencyLimit {
+        if self.adaptive {
+            ConcurrencyLimit::adaptive(workers, self.verbose)
+        } else {
+            ConcurrencyLimit::fixed(workers)
+        }
+    }
+
+    // Looks up what dest is for the upload workers, so that they don't each look it up
+    //   A new dest that several sources (or STDIN with --name) are uploaded to is created as
+    //   a directory first, and the missing parents of a new dest are created if create_dirs
+    fn resolve_dest(&self, sources: usize) -> Result<DestKind, String> {
+        let dir = self.client.dir(&*self.dest);
+        match self.client.data(&*self.dest).into_type() {
+            Ok(DataItem::File(_)) => Ok(DestKind::File),
+            Ok(DataItem::Dir(_)) => Ok(DestKind::Dir),
+            Err(_) if sources > 1 || self.stdin_name.is_some() => {
+                if self.create_dirs {
+                    self.create_dir_all(&dir.to_data_uri())?;
+                } else if let Err(err) = dir.create(DataAcl::default()) {
+                    return Err(format!("Error creating directory {}: {}", self.dest, err));
+                }
+                Ok(DestKind::Dir)
+            }
+            Err(_) => {
+                if let (true, Some(parent)) = (self.create_dirs, dir.parent()) {
+                    self.create_dir_all(&parent.to_data_uri())?;
+                }
+                Ok(DestKind::New)
+            }
+        }
+    }
+
+    // Creates a data directory and any missing parents of it, like `mkdir -p`
+    fn create_dir_all(&self, uri: &str) -> Result<(), String> {
+        let mut missing = Vec::new();
+        let mut next = Some(self.client.dir(uri));
+        while let Some(dir) = next {
+            match dir.exists() {
+                Ok(true) => break,
+                Ok(false) => {
+                    next = dir.parent();
+                    missing.push(dir);
+                }
+                Err(err) => return Err(format!("Error checking {}: {}", dir.to_data_uri(), err)),
+            }
+        }
+        for dir in missing.iter().rev() {
+            if let Err(err) = dir.create(DataAcl::default()) {
+                return Err(format!(
+                    "Error creating directory {}: {}",
+                    dir.to_data_uri(),
+                    err
+                ));
+            }
+            if self.verbose {
+                notice!(Info, "Created directory {}", dir.to_data_uri());
+            }
+        }
+        Ok(())
+    }
+
+    // Uploads a local file (or STDIN) and sets its directory's ACL if requested, returning
+    //   the URI of the uploaded file, the number of bytes uploaded, and the file's ETag
+    fn upload_file(
+        &self,
+        path: &str,
+        dir: Option<&str>,
+    ) -> Result<(String, u64, Option<String>), String> {
+        let (uri, bytes, etag) = if path == STDIN_SOURCE {
+            self.put_stdin()?
+        } else {
+            let (uri, etag) = self.put_local(path, dir)?;
+            (uri, fs::metadata(path).map(|m| m.len()).unwrap_or(0), etag)
+        };
+        if let Some(ref acl) = self.acl {
+            acl.apply(&self.client.file(&uri))?;
+        }
+        Ok((uri, bytes, etag))
+    }
+
+    // Uploads a local file into `dir` (for recursive uploads) or else to dest
+    fn put_local(&self, path: &str, dir: Option<&str>) -> Result<(String, Option<String>), String> {
+        let file = match dir {
+            // Recursive uploads target a specific data dir
+            Some(dir) => self.client.dir(dir).child::<DataFile>(&file_name(path)),
+            // If dest exists as DataDir, add file to dir
+            None if self.dest_kind == DestKind::Dir => {
+                self.client.dir(&*self.dest).child(&file_name(path))
+            }
+            // Otherwise, overwrite dest or add a new file with exact path as dest
+            None => self.client.file(&*self.dest),
+        };
+        let local = File::open(path)
+            .map_err(|err| format!("opening file for upload '{}': {}", path, err))?;
+        let etag = put_file(&file, Body::new(local))?;
+        Ok((file.to_data_uri(), etag))
+    }
+
+    // Streams STDIN to dest, or with --name, to a file of that name in the dest directory
+    fn put_stdin(&self) -> Result<(String, u64, Option<String>), String> {
+        let file = match self.stdin_name {
+            Some(ref name) => self.client.dir(&*self.dest).child::<DataFile>(name),
+            None => {
+                if self.dest.ends_with('/') || self.dest_kind == DestKind::Dir {
+                    return Err(format!(
+                        "{} is a directory, so use --name <filename> to name the uploaded file",
+                        self.dest
+                    ));
+                }
+                self.client.file(&*self.dest)
+            }
+        };
+
+        let count = Arc::new(AtomicU64::new(0));
+        let stdin = CountingReader {
+            inner: io::stdin(),
+            count: count.clone(),
+        };
+        let etag = put_file(&file, Body::new(stdin))?;
+        Ok((file.to_data_uri(), count.load(Ordering::SeqCst), etag))
+    }
+
+    // Whether a local file was last modified before --newer-than, counting it as skipped
+    //   Files whose modification time can't be read are not skipped (their upload reports why)
+    fn is_old_local(&self, path: &Path) -> bool {
+        let threshold = match self.newer_than {
+            Some(threshold) => threshold,
+            None => return false,
+        };
+        let modified = match fs::metadata(path).and_then(|m| m.modified()) {
+            Ok(modified) => DateTime::<Utc>::from(modified),
+            Err(_) => return false,
+        };
+        let old = modified <= threshold;
+        if old {
+            self.skipped_old.fetch_add(1, Ordering::SeqCst);
+            self.report(&path.display().to_string(), 0, TransferStatus::Skipped);
+        }
+        old
+    }
+
+    fn summarize(&self, stats: &TransferStats, download: bool) -> CpSummary {
+        let skipped = self.skipped_old.load(Ordering::SeqCst);
+        stats.summary(download, skipped, self.deadline.stopped_early())
+    }
+
+    // The data dir a local directory is uploaded into, like `cp -r`:
+    //   into a child of dest if dest already exists, otherwise as dest itself
+    fn recursive_upload_root(&self, local_dir: &str) -> Result<String, String> {
+        let dest_dir = self.client.dir(&*self.dest);
+        match dest_dir.exists() {
+            Ok(true) => {
+                let name = fs::canonicalize(local_dir)
+                    .ok()
+                    .and_then(|p| p.file_name().map(|n| n.to_string_lossy().into_owned()))
+                    .ok_or_else(|| format!("Cannot determine directory name of {}", local_dir))?;
+                Ok(dest_dir.child::<DataDir>(&name).to_data_uri())
+            }
+            // send_tree creates dest itself, but not its parents
+            Ok(false) => {
+                if let (true, Some(parent)) = (self.create_dirs, dest_dir.parent()) {
+                    self.create_dir_all(&parent.to_data_uri())?;
+                }
+                Ok(dest_dir.to_data_uri())
+            }
+            Err(err) => Err(format!(
+                "Error checking {}: {}",
+                dest_dir.to_data_uri(),
+                err
+            )),
+        }
+    }
+
+    // Queues the files under a local directory for upload to `remote_dir`, creating data dirs
+    //   `rel_dir` is the path of `dir` relative to the source directory ("" for the source itself)
+    //   Data dirs that don't exist yet are only created (outermost first, from
+    //   `walk.uncreated`) once a file is queued under them, so directories with nothing to
+    //   upload are skipped
+    fn send_tree(
+        &self,
+        walk: &mut TreeWalk,
+        dir: &Path,
+        rel_dir: &str,
+        remote_dir: &str,
+    ) -> Result<(), String> {
+        let data_dir = self.client.dir(remote_dir);
+        match data_dir.exists() {
+            Ok(true) => (),
+            Ok(false) => walk.uncreated.push(remote_dir.to_string()),
+            Err(err) => return Err(format!("Error checking {}: {}", remote_dir, err)),
+        }
+
+        let mark = walk.filter.add_ignore_file(dir, rel_dir)?;
+        let mut entries = fs::read_dir(dir)
+            .and_then(|entries| entries.collect::<Result<Vec<_>, _>>())
+            .map_err(|err| format!("Error reading {}: {}", dir.display(), err))?;
+        entries.sort_by_key(|entry| entry.file_name());
+
+        for entry in entries {
+            if self.deadline.should_stop() {
+                return Ok(());
+            }
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let rel_path = match rel_dir {
+                "" => name.clone(),
+                _ => format!("{}/{}", rel_dir, name),
+            };
+            let path = entry.path();
+            let is_dir = path.is_dir();
+            if walk.filter.is_skipped(&rel_path, is_dir) {
+                continue;
+            }
+            if is_dir {
+                let child_dir = data_dir.child::<DataDir>(&name).to_data_uri();
+                self.send_tree(walk, &path, &rel_path, &child_dir)?;
+            } else if !self.is_old_local(&path) {
+                self.create_uncreated(&mut walk.uncreated)?;
+                walk.tx.send(UploadItem {
+                    source: walk.source,
+                    path: path.display().to_string(),
+                    dir: Some(remote_dir.to_string()),
+                });
+            }
+        }
+        walk.filter.remove_ignore_file(mark);
+
+        // Nothing was queued in this directory (or under it)
+        if walk.uncreated.last().map(String::as_str) == Some(remote_dir) {
+            if self.create_empty_dirs {
+                self.create_uncreated(&mut walk.uncreated)?;
+            } else {
+                walk.uncreated.pop();
+                if self.verbose {
+                    notice!(
+                        Info,
+                        "Skipping empty directory {} (use --create-empty-dirs to create it)",
+                        dir.display()
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Creates the data dirs that send_tree has deferred creating, outermost first
+    fn create_uncreated(&self, uncreated: &mut Vec<String>) -> Result<(), String> {
+        for remote_dir in uncreated.drain(..) {
+            let data_dir = self.client.dir(&remote_dir);
+            if let Err(err) = data_dir.create(DataAcl::default()) {
+                return Err(format!("Error creating directory {}: {}", remote_dir, err));
+            }
+        }
+        Ok(())
     }
 
-    fn download(&self, sources: Vec<String>) {
+    fn download(&self, sources: Vec<String>) -> Result<CpSummary, String> {
+        let sources = dedup_sources(sources, |path| self.client.file(path).to_data_uri());
+        let targets = self.download_targets(&sources)?;
+
         // As long as we aren't recursing, we can be more aggressive in limiting threads we spin up
         // TODO: when supporting datadir recursion, fall-back to max_concurrency
         let concurrency = cmp::min(sources.len(), self.max_concurrency as usize);
 
-        let (tx, rx) = chan::sync(self.max_concurrency as usize);
         let wg = chan::WaitGroup::new();
-        let completed = Arc::new(Mutex::new(0));
+        let stats = Arc::new(TransferStats::new(&sources));
+        let limit = Arc::new(self.concurrency_limit(concurrency));
 
-        // One Producer thread queuing up file paths to upload
-        thread::spawn(move || {
-            for path in sources {
-                // TODO: if recursing and is_dir: recurse_remote_and_send(&tx, path)
-                tx.send(path);
-            }
-            drop(tx);
-        });
+        // Queue up the files to download, by host for --concurrency-per-host
+        let queue = Arc::new(HostQueue::new(self.per_host));
+        let items = sources.into_iter().zip(targets).enumerate();
+        for (source, (path, target)) in items {
+            // TODO: if recursing and is_dir: recurse_remote_and_send(&tx, path)
+            let uri = self.client.file(&path).to_data_uri();
+            queue.push(concurrency::uri_host(&uri), (source, path, target));
+        }
+        queue.close();
 
         // Spin up threads to concurrently download files per that paths received on rx channel
         for _ in 0..concurrency {
             wg.add(1);
 
             let thread_wg = wg.clone();
-            let thread_rx = rx.clone();
+            let thread_queue = queue.clone();
             let thread_conn = self.clone();
-            let thread_completed = completed.clone();
+            let thread_stats = stats.clone();
+            let thread_limit = limit.clone();
 
             thread::spawn(move || {
-                for rx_path in thread_rx {
+                while let Some((host, (source, rx_path, target))) = thread_queue.pop() {
+                    if thread_conn.deadline.should_stop() {
+                        thread_queue.release(&host);
+                        continue;
+                    }
+                    thread_limit.acquire();
+                    let started = Instant::now();
                     let my_file = thread_conn.client.file(&*rx_path);
-                    match download_file(&my_file, &*thread_conn.dest) {
-                        Ok(bytes) => {
-                            println!("Downloaded {} ({}B)", rx_path, size_with_suffix(bytes));
-                            let mut count = thread_completed.lock().unwrap();
-                            *count += 1;
+                    let get_res = catch_panic(|| {
+                        download_file(
+                            &my_file,
+                            &target,
+                            thread_conn.allow_short,
+                            thread_conn.raw,
+                            thread_conn.newer_than,
+                            thread_conn.min_free_space,
+                        )
+                    });
+                    thread_limit.release(get_res.as_ref().ok().map(|bytes| bytes.unwrap_or(0)));
+                    thread_queue.release(&host);
+                    match get_res {
+                        Ok(None) => {
+                            thread_conn.skipped_old.fetch_add(1, Ordering::SeqCst);
+                            thread_conn.report(&rx_path, 0, TransferStatus::Skipped);
+                        }
+                        Ok(Some(bytes)) => {
+                            let local_path = target.display().to_string();
+                            thread_conn.log_transfer(&rx_path, &local_path, bytes, started, None);
+                            thread_conn.report(&rx_path, bytes, TransferStatus::Downloaded);
+                            thread_stats.record(source, Some(bytes));
+                        }
+                        Err(err_msg) => {
+                            thread_conn.log_transfer(
+                                &rx_path,
+                                &thread_conn.dest,
+                                0,
+                                started,
+                                Some(err_msg.clone()),
+                            );
+                            let failed = TransferStatus::Failed(err_msg);
+                            thread_conn.report(&rx_path, 0, failed);
+                            thread_stats.record(source, None);
                         }
-                        Err(err_msg) => quit_msg!("Failed to download {}: {}", rx_path, err_msg),
                     }
                 }
                 thread_wg.done();
@@ -205,38 +1277,553 @@ impl CpClient {
         }
 
         wg.wait();
-        println!(
-            "Finished downloading {} file(s)",
-            *completed.lock().unwrap()
-        );
+        Ok(self.summarize(&stats, true))
     }
-}
 
-fn download_file(data_file: &DataFile, local_path: &str) -> Result<u64, String> {
-    match data_file.get() {
-        Ok(mut response) => {
-            let full_path = match fs::metadata(local_path) {
-                Ok(ref m) if m.is_dir() => {
-                    Path::new(local_path).join(data_file.basename().unwrap())
+    // Downloads an archive and extracts it into dest (creating it if needed) as it downloads
+    fn download_archive(&self, source: &str) -> Result<CpSummary, String> {
+        let data_file = self.client.file(source);
+        let uri = data_file.to_data_uri();
+        let dest = Path::new(&*self.dest);
+        let stats = TransferStats::new(&[source.to_string()]);
+        let started = Instant::now();
+        let count = Arc::new(AtomicU64::new(0));
+        let extracted = download::get_file(&data_file, self.raw).and_then(|download| {
+            fs::create_dir_all(dest)
+                .map_err(|err| format!("Error creating {}: {}", dest.display(), err))?;
+            let reader = CountingReader {
+                inner: download,
+                count: count.clone(),
+            };
+            let name = data_file.basename().unwrap_or_default();
+            archive::extract(&name, reader, dest)
+                .map_err(|err| format!("Error extracting {}: {}", uri, err))
+        });
+        let dest_path = dest.display().to_string();
+        match extracted {
+            Ok(files) => {
+                let bytes = count.load(Ordering::SeqCst);
+                if self.verbose {
+                    notice!(Info, "Extracted {} file(s) into {}", files, dest_path);
                 }
-                _ => Path::new(local_path).to_owned(),
+                self.log_transfer(&uri, &dest_path, bytes, started, None);
+                self.report(&uri, bytes, TransferStatus::Downloaded);
+                stats.record(0, Some(bytes));
+            }
+            Err(e) => {
+                self.log_transfer(&uri, &dest_path, 0, started, Some(e.clone()));
+                self.report(&uri, 0, TransferStatus::Failed(e));
+                stats.record(0, None);
+            }
+        }
+        Ok(self.summarize(&stats, true))
+    }
+
+    // Resolves the local path each source downloads to, like `cp`: into dest if it's a
+    //   directory, otherwise as dest itself. Distinct sources that would be written to the
+    //   same file are an error, unless --rename-dupes numbers the later ones.
+    fn download_targets(&self, sources: &[String]) -> Result<Vec<PathBuf>, String> {
+        let dest_is_dir = *self.dest != STDOUT_DEST
+            && fs::metadata(&*self.dest)
+                .map(|m| m.is_dir())
+                .unwrap_or(false);
+        let mut claimed: HashMap<PathBuf, &str> = HashMap::new();
+        let mut targets = Vec::with_capacity(sources.len());
+        for source in sources {
+            let mut target = if dest_is_dir {
+                let basename = self.client.file(source).basename().unwrap_or_default();
+                Path::new(&*self.dest).join(basename)
+            } else {
+                PathBuf::from(&*self.dest)
             };
+            if let Some(prev) = claimed.get(&target) {
+                if !self.rename_dupes {
+                    return Err(format!(
+                        "{} and {} both download to {} (use --rename-dupes to keep both)",
+                        prev,
+                        source,
+                        target.display()
+                    ));
+                }
+                let renamed = (1..)
+                    .map(|n| numbered_path(&target, n))
+                    .find(|path| !claimed.contains_key(path))
+                    .unwrap();
+                if self.verbose {
+                    notice!(
+                        Warn,
+                        "Warning: {} and {} both download to {}, saving {} as {}",
+                        prev,
+                        source,
+                        target.display(),
+                        source,
+                        renamed.display()
+                    );
+                }
+                target = renamed;
+            }
+            claimed.insert(target.clone(), source);
+            targets.push(target);
+        }
+        Ok(targets)
+    }
+}
+
+// Counts the bytes read through it (e.g. while STDIN streams to the Data API)
+struct CountingReader<R> {
+    inner: R,
+    count: Arc<AtomicU64>,
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.count.fetch_add(n as u64, Ordering::SeqCst);
+        Ok(n)
+    }
+}
+
+// A local file queued for upload, tagged with the index of the source argument it came from
+struct UploadItem {
+    source: usize,
+    path: String,
+    // The data dir to upload into for recursive uploads (otherwise resolved from dest)
+    dir: Option<String>,
+}
+
+// The state of send_tree as it recurses through a source directory
+struct TreeWalk<'a> {
+    tx: &'a chan::Sender<UploadItem>,
+    // The index of the source argument being walked
+    source: usize,
+    filter: &'a mut PathFilter,
+    // Data dirs whose creation is deferred until a file is queued under them
+    uncreated: Vec<String>,
+}
+
+// Sets the ACL of each data directory uploaded into, once per directory
+struct AclUpdater {
+    access: ReadAccess,
+    updated: Mutex<HashSet<String>>,
+    verbose: bool,
+}
+
+impl AclUpdater {
+    fn new(access: ReadAccess, verbose: bool) -> AclUpdater {
+        AclUpdater {
+            access,
+            updated: Mutex::new(HashSet::new()),
+            verbose,
+        }
+    }
+
+    // Sets the ACL of the directory containing an uploaded file and reports the resulting ACL
+    //   A directory only counts as updated once that succeeds, so a failure is retried by the
+    //   next file uploaded into it
+    fn apply(&self, uploaded: &DataFile) -> Result<(), String> {
+        let dir = match uploaded.parent() {
+            Some(dir) => dir,
+            None => return Err(format!("{} has no data directory", uploaded.to_data_uri())),
+        };
+        let uri = dir.to_data_uri();
+        if self.updated.lock().unwrap().contains(&uri) {
+            return Ok(());
+        }
+
+        let err_msg = |err: String| format!("Error setting ACL of {}: {}", uri, err);
+        acl::set_acl(&dir, self.access).map_err(err_msg)?;
+        let read = acl::get_acl(&dir).map_err(err_msg)?;
+        // Files uploaded at the same time may both have set it, but it's only reported once
+        if self.updated.lock().unwrap().insert(uri.clone()) && self.verbose {
+            println!("Set ACL of {} to {}", uri, acl::describe(&read));
+        }
+        Ok(())
+    }
+}
+
+// Per-source tallies of transferred files, shared across worker threads
+struct TransferStats {
+    sources: Vec<String>,
+    tallies: Mutex<Vec<Tally>>,
+}
+
+#[derive(Clone, Copy, Default)]
+struct Tally {
+    completed: u64,
+    failed: u64,
+    bytes: u64,
+}
+
+impl TransferStats {
+    fn new(sources: &[String]) -> TransferStats {
+        TransferStats {
+            sources: sources.to_vec(),
+            tallies: Mutex::new(vec![Tally::default(); sources.len()]),
+        }
+    }
+
+    // Records a file from the given source as transferred (with its size) or as failed
+    fn record(&self, source: usize, bytes: Option<u64>) {
+        let mut tallies = self.tallies.lock().unwrap();
+        let tally = &mut tallies[source];
+        match bytes {
+            Some(bytes) => {
+                tally.completed += 1;
+                tally.bytes += bytes;
+            }
+            None => tally.failed += 1,
+        }
+    }
+
+    // Totals the tallies, counting completed files as downloaded or uploaded
+    fn summary(&self, download: bool, skipped: u64, stopped_early: bool) -> CpSummary {
+        let tallies = self.tallies.lock().unwrap();
+        let by_source: Vec<_> = self
+            .sources
+            .iter()
+            .zip(tallies.iter())
+            .map(|(source, tally)| SourceSummary {
+                source: source.clone(),
+                completed: tally.completed,
+                failed: tally.failed,
+                bytes: tally.bytes,
+            })
+            .collect();
+        let completed = by_source.iter().map(|s| s.completed).sum();
+        CpSummary {
+            uploaded: if download { 0 } else { completed },
+            downloaded: if download { completed } else { 0 },
+            skipped,
+            failed: by_source.iter().map(|s| s.failed).sum(),
+            bytes: by_source.iter().map(|s| s.bytes).sum(),
+            stopped_early,
+            by_source,
+        }
+    }
+}
+
+// The name a local file is uploaded under in a data directory
+fn file_name(path: &str) -> String {
+    Path::new(path)
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .into_owned()
+}
+
+// Writes a data file like DataFile::put, but returns the written file's ETag if the Data API
+//   sent one (which DataFile::put discards)
+fn put_file(file: &DataFile, body: Body) -> Result<Option<String>, String> {
+    let uri = file.to_data_uri();
+    let url = file.to_url().map_err(|err| err.to_string())?;
+    let response = file
+        .client()
+        .put(url)
+        .body(body)
+        .send()
+        .map_err(|err| format!("request error writing file '{}': {}", uri, err))?;
+    if !response.status().is_success() {
+        return Err(format!(
+            "{}: response error writing file '{}'",
+            download::status_err(&response),
+            uri
+        ));
+    }
+    Ok(download::header(&response, "ETag"))
+}
+
+// Prints each file as it's transferred (or fails to be)
+fn print_event(event: &TransferEvent, download: bool, to_stdout: bool) {
+    match event.status {
+        TransferStatus::Uploaded => match event.etag {
+            Some(ref etag) => println!(
+                "Uploaded {} ({}B, ETag {})",
+                event.path,
+                size_with_suffix(event.bytes),
+                etag
+            ),
+            None => println!(
+                "Uploaded {} ({}B)",
+                event.path,
+                size_with_suffix(event.bytes)
+            ),
+        },
+        TransferStatus::Downloaded => print_line(
+            &format!(
+                "Downloaded {} ({}B)",
+                event.path,
+                size_with_suffix(event.bytes)
+            ),
+            to_stdout,
+        ),
+        TransferStatus::Skipped => (),
+        TransferStatus::Failed(ref err) if download => {
+            eprintln_red!("Failed to download {}: {}", event.path, err)
+        }
+        TransferStatus::Failed(ref err) => eprintln_red!("Error uploading {}: {}", event.path, err),
+    }
+}
+
+// Prints one of cp's own lines, on STDERR if the download itself is going to STDOUT
+fn print_line(line: &str, to_stdout: bool) {
+    if to_stdout {
+        notice!(Info, "{}", line);
+    } else {
+        println!("{}", line);
+    }
+}
+
+// Whether a cp destination is a local path (so the sources are downloaded)
+fn is_local(dest: &str) -> bool {
+    let dest_parts: Vec<_> = dest.splitn(2, "://").collect();
+    dest_parts.len() < 2 || dest_parts[0] == "file"
+}
+
+// Runs a worker's transfer of one file, turning a panic into an error for that file so that
+//   the worker moves on to the next file (and still signals the WaitGroup when done)
+fn catch_panic<T, F>(transfer: F) -> Result<T, String>
+where
+    F: FnOnce() -> Result<T, String>,
+{
+    panic::catch_unwind(AssertUnwindSafe(transfer)).unwrap_or_else(|payload| {
+        let msg = match payload.downcast_ref::<&str>() {
+            Some(msg) => msg.to_string(),
+            None => match payload.downcast_ref::<String>() {
+                Some(msg) => msg.clone(),
+                None => "unknown error".to_string(),
+            },
+        };
+        Err(format!("unexpected panic: {}", msg))
+    })
+}
+
+// Numbers a path before its extension, e.g. "out/file.json" becomes "out/file-1.json"
+fn numbered_path(path: &Path, n: usize) -> PathBuf {
+    let stem = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let name = match path.extension() {
+        Some(ext) => format!("{}-{}.{}", stem, n, ext.to_string_lossy()),
+        None => format!("{}-{}", stem, n),
+    };
+    path.with_file_name(name)
+}
+
+// Drops repeated sources (compared by `key`), keeping the first occurrence
+fn dedup_sources<F>(sources: Vec<String>, key: F) -> Vec<String>
+where
+    F: Fn(&str) -> String,
+{
+    let mut seen = HashSet::new();
+    sources
+        .into_iter()
+        .filter(|source| seen.insert(key(source)))
+        .collect()
+}
 
-            let mut output = match File::create(full_path) {
-                Ok(f) => Box::new(f),
-                Err(err) => return Err(format!("Error creating file: {}", err)),
+// Downloads a data file to a local file (or STDOUT, for a path of '-'), returning the number
+//   of bytes downloaded
+//   A partial download is deleted, unless `allow_short` keeps a short one (with a warning).
+//   Returns None without writing anything if the file wasn't modified after `newer_than`.
+//   With `min_free_space`, fails without writing anything if the download would leave less
+//   free space than that on the local filesystem.
+fn download_file(
+    data_file: &DataFile,
+    full_path: &Path,
+    allow_short: bool,
+    raw: bool,
+    newer_than: Option<DateTime<Utc>>,
+    min_free_space: Option<u64>,
+) -> Result<Option<u64>, String> {
+    let to_stdout = full_path == Path::new(STDOUT_DEST);
+    let check_space = |size| match min_free_space {
+        Some(min_free) if !to_stdout => check_free_space(full_path, size, min_free),
+        _ => Ok(()),
+    };
+    // Skip the request entirely once the filesystem is already too full
+    check_space(0)?;
+    match download::get_file(data_file, raw) {
+        Ok(ref response) if newer_than.map_or(false, |t| response.last_modified <= t) => Ok(None),
+        Ok(mut response) => {
+            check_space(response.size)?;
+            let mut output: Box<dyn Write> = if to_stdout {
+                Box::new(io::stdout())
+            } else {
+                match File::create(full_path) {
+                    Ok(f) => Box::new(f),
+                    Err(err) => return Err(format!("Error creating file: {}", err)),
+                }
             };
 
             // Copy downloaded data to the output writer
-            match io::copy(&mut response, &mut output) {
-                Ok(bytes) => Ok(bytes),
+            let expected = response.size;
+            let copied = data::copy_download(&mut response, &mut output, expected);
+            let result = match copied.and_then(|copied| output.flush().map(|_| copied)) {
+                Ok(Copied::Complete(bytes)) => Ok(bytes),
+                Ok(Copied::Short(bytes, msg)) if allow_short => {
+                    notice!(
+                        Warn,
+                        "Warning: short read of {}: {}",
+                        data_file.to_data_uri(),
+                        msg
+                    );
+                    Ok(bytes)
+                }
+                Ok(Copied::Short(_, msg)) => Err(format!("Short read: {}", msg)),
                 Err(err) => Err(format!("Error copying data: {}", err)),
+            };
+            match result {
+                Ok(bytes) => Ok(Some(bytes)),
+                Err(err) => {
+                    drop(output);
+                    if !to_stdout {
+                        let _ = fs::remove_file(full_path);
+                    }
+                    Err(err)
+                }
             }
         }
-        Err(e) => Err(format!(
-            "Error downloading ({}): {}",
-            data_file.to_data_uri(),
-            e
-        )),
+        Err(err) => Err(err),
+    }
+}
+
+// Checks that writing `size` bytes to `path` leaves at least `min_free` bytes free
+fn check_free_space(path: &Path, size: u64, min_free: u64) -> Result<(), String> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let free = free_space(dir)
+        .map_err(|err| format!("Error checking free space in {}: {}", dir.display(), err))?;
+    if free.saturating_sub(size) < min_free {
+        let needed = match size {
+            0 => " to".to_string(),
+            size => format!(" to download {}B and", size_with_suffix(size)),
+        };
+        return Err(format!(
+            "Not enough free space{} keep the minimum of {}B free ({}B free in {})",
+            needed,
+            size_with_suffix(min_free),
+            size_with_suffix(free),
+            dir.display()
+        ));
+    }
+    Ok(())
+}
+
+// Bytes available to unprivileged users on the filesystem holding `dir`
+#[cfg(unix)]
+fn free_space(dir: &Path) -> io::Result<u64> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = CString::new(dir.as_os_str().as_bytes())
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+    // statvfs fills in the zeroed struct, which is plain data
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // The field types vary by platform (e.g. u32 on 32-bit Linux), so convert losslessly even
+    //   where they are already u64
+    #[allow(clippy::useless_conversion)]
+    let free = u64::from(stat.f_bavail) * u64::from(stat.f_frsize);
+    Ok(free)
+}
+
+#[cfg(not(unix))]
+fn free_space(_dir: &Path) -> io::Result<u64> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "checking free space is only supported on Unix",
+    ))
+}
+
+// What an upload's dest is
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum DestKind {
+    File,
+    Dir,
+    // Nothing exists at dest yet, so files are uploaded to it by its exact path
+    New,
+}
+
+// Audit log of transfers, written as one JSON object per line
+// Shared across worker threads, so writes are serialized and flushed per entry
+struct TransferLog {
+    file: Mutex<File>,
+}
+
+#[derive(RustcEncodable)]
+struct TransferLogEntry<'a> {
+    timestamp: String,
+    source: &'a str,
+    destination: &'a str,
+    bytes: u64,
+    status: &'a str,
+    error: Option<String>,
+    etag: Option<&'a str>,
+    duration: f64,
+}
+
+impl TransferLog {
+    fn open(path: &str) -> Result<TransferLog, String> {
+        match OpenOptions::new().create(true).append(true).open(path) {
+            Ok(file) => Ok(TransferLog {
+                file: Mutex::new(file),
+            }),
+            Err(err) => Err(format!("Unable to open log file {}: {}", path, err)),
+        }
+    }
+
+    fn record(&self, entry: &TransferLogEntry) {
+        let line = json::encode(entry).expect("transfer log entry is encodable");
+        let mut file = self.file.lock().unwrap();
+        if let Err(err) = writeln!(file, "{}", line).and_then(|_| file.flush()) {
+            eprintln_red!("Error writing to log file: {}", err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_numbered_path() {
+        let numbered = |path: &str, n| numbered_path(Path::new(path), n);
+        assert_eq!(
+            numbered("out/file.json", 1),
+            PathBuf::from("out/file-1.json")
+        );
+        assert_eq!(numbered("file.tar.gz", 2), PathBuf::from("file.tar-2.gz"));
+        assert_eq!(numbered("README", 1), PathBuf::from("README-1"));
+        assert_eq!(numbered(".bashrc", 1), PathBuf::from(".bashrc-1"));
+    }
+
+    #[test]
+    fn test_summary() {
+        let stats = TransferStats::new(&["a".to_string(), "b".to_string()]);
+        stats.record(0, Some(10));
+        stats.record(0, Some(5));
+        stats.record(1, None);
+
+        let summary = stats.summary(true, 2, false);
+        assert_eq!(
+            (summary.uploaded, summary.downloaded, summary.skipped),
+            (0, 2, 2)
+        );
+        assert_eq!((summary.failed, summary.bytes), (1, 15));
+        assert_eq!(
+            summary.by_source[1],
+            SourceSummary {
+                source: "b".to_string(),
+                completed: 0,
+                failed: 1,
+                bytes: 0,
+            }
+        );
+        assert_eq!(stats.summary(false, 0, false).uploaded, 2);
     }
 }
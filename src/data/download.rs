@@ -0,0 +1,296 @@
+// Downloads data files, decoding a compressed Content-Encoding (gzip or deflate) that a file
+//   was stored with, so that its contents are returned rather than the compressed bytes
+
+use super::archive::crc32;
+use algorithmia::data::{DataFile, HasDataPath};
+use chrono::{DateTime, TimeZone, Utc};
+use miniz_oxide::inflate::stream::{inflate, InflateState};
+use miniz_oxide::{DataFormat, MZError, MZFlush, MZStatus};
+use reqwest::blocking::Response;
+use std::io::{self, BufRead, BufReader, Read};
+
+// A data file being downloaded
+pub struct Download {
+    // The reported size in bytes, or 0 if unknown (as it is for decoded downloads, since
+    //   the reported size is of the encoded bytes)
+    pub size: u64,
+    pub last_modified: DateTime<Utc>,
+    reader: Box<dyn Read>,
+}
+
+impl Read for Download {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.reader.read(buf)
+    }
+}
+
+// Downloads a data file, decoding it per its Content-Encoding unless `raw`
+pub fn get_file(data_file: &DataFile, raw: bool) -> Result<Download, String> {
+    let uri = data_file.to_data_uri();
+    let download_err = |err: &dyn std::fmt::Display| format!("Error downloading {}: {}", uri, err);
+    let url = data_file.to_url().map_err(|err| download_err(&err))?;
+    let response = data_file
+        .client()
+        .get(url)
+        .send()
+        .map_err(|err| download_err(&err))?;
+    if !response.status().is_success() {
+        return Err(download_err(&status_err(&response)));
+    }
+    match header(&response, "X-Data-Type").as_deref() {
+        Some("file") => (),
+        Some(data_type) => return Err(download_err(&format!("{} is not a file", data_type))),
+        None => return Err(download_err(&"API response missing data type")),
+    }
+
+    let last_modified = last_modified(&response);
+    let size = response.content_length().unwrap_or(0);
+    let encoding = content_encoding(&response);
+    let (size, reader) = match encoding {
+        Some(ref encoding) if !raw => {
+            let reader = decoder(encoding, response).map_err(|err| download_err(&err))?;
+            (0, reader)
+        }
+        _ => (size, Box::new(response) as Box<dyn Read>),
+    };
+    Ok(Download {
+        size,
+        last_modified,
+        reader,
+    })
+}
+
+// The Content-Encoding of a response, if any (other than identity)
+pub fn content_encoding(response: &Response) -> Option<String> {
+    header(response, "Content-Encoding")
+        .map(|encoding| encoding.trim().to_lowercase())
+        .filter(|encoding| !encoding.is_empty() && encoding != "identity")
+}
+
+// When a file was last modified, per its Date header
+pub fn last_modified(response: &Response) -> DateTime<Utc> {
+    // Like the algorithmia client, a missing Date defaults to when the Data API launched
+    header(response, "Date")
+        .and_then(|date| DateTime::parse_from_rfc2822(&date).ok())
+        .map(|date| date.with_timezone(&Utc))
+        .unwrap_or_else(|| Utc.ymd(2015, 3, 14).and_hms(8, 0, 0))
+}
+
+// Describes an unsuccessful response, with the Data API's error message if it sent one
+pub fn status_err(response: &Response) -> String {
+    match header(response, "X-Error-Message") {
+        Some(msg) => format!("{}: {}", response.status(), msg),
+        None => response.status().to_string(),
+    }
+}
+
+pub fn header(response: &Response, name: &str) -> Option<String> {
+    let value = response.headers().get(name)?;
+    value.to_str().ok().map(String::from)
+}
+
+fn decoder(encoding: &str, response: Response) -> Result<Box<dyn Read>, String> {
+    let format = match encoding {
+        "gzip" | "x-gzip" => Format::Gzip,
+        // HTTP's "deflate" is a zlib stream
+        "deflate" => Format::Zlib,
+        other => {
+            return Err(format!(
+                "Unsupported Content-Encoding '{}' (use --raw to download the encoded bytes)",
+                other
+            ))
+        }
+    };
+    Ok(Box::new(Inflater::new(BufReader::new(response), format)))
+}
+
+// Decompresses a gzip stream as it is read (e.g. a .tar.gz archive being extracted)
+pub fn gunzip<R: BufRead>(reader: R) -> impl Read {
+    Inflater::new(reader, Format::Gzip)
+}
+
+// Decompresses a raw deflate stream as it is read, without reading past its end
+//   (e.g. a zip entry, which is followed by the next one)
+pub fn inflate_raw<R: BufRead>(reader: R) -> impl Read {
+    Inflater::new(reader, Format::Raw)
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Format {
+    Gzip,
+    Zlib,
+    Raw,
+}
+
+// Decompresses a gzip (RFC 1952), zlib (RFC 1950), or raw deflate (RFC 1951) stream as it is
+//   read. A gzip stream may have several members, which are decompressed one after another
+struct Inflater<R: BufRead> {
+    inner: R,
+    format: Format,
+    state: Box<InflateState>,
+    // Whether a gzip member's header has been read but not its trailer
+    in_member: bool,
+    // The CRC-32 and length (mod 2^32) of what has been decompressed from the current gzip
+    //   member, checked against its trailer
+    member_crc: u32,
+    member_len: u32,
+    done: bool,
+}
+
+impl<R: BufRead> Inflater<R> {
+    fn new(inner: R, format: Format) -> Self {
+        let data_format = match format {
+            Format::Gzip | Format::Raw => DataFormat::Raw,
+            Format::Zlib => DataFormat::Zlib,
+        };
+        Inflater {
+            inner,
+            format,
+            state: InflateState::new_boxed(data_format),
+            in_member: false,
+            member_crc: 0,
+            member_len: 0,
+            done: false,
+        }
+    }
+
+    fn finish_member(&mut self) -> io::Result<()> {
+        // CRC32, then ISIZE
+        let mut trailer = [0; 8];
+        self.inner.read_exact(&mut trailer)?;
+        let (mut crc, mut isize) = ([0; 4], [0; 4]);
+        crc.copy_from_slice(&trailer[..4]);
+        isize.copy_from_slice(&trailer[4..]);
+        if u32::from_le_bytes(crc) != self.member_crc {
+            return Err(invalid_data("gzip stream CRC does not match its trailer"));
+        }
+        if u32::from_le_bytes(isize) != self.member_len {
+            return Err(invalid_data(
+                "gzip stream length does not match its trailer",
+            ));
+        }
+        self.in_member = false;
+        self.state.reset(DataFormat::Raw);
+        Ok(())
+    }
+}
+
+impl<R: BufRead> Read for Inflater<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while !self.done && !buf.is_empty() {
+            if self.format == Format::Gzip && !self.in_member {
+                if self.inner.fill_buf()?.is_empty() {
+                    self.done = true;
+                    break;
+                }
+                read_gzip_header(&mut self.inner)?;
+                self.in_member = true;
+                self.member_crc = 0;
+                self.member_len = 0;
+            }
+
+            let input = self.inner.fill_buf()?;
+            let eof = input.is_empty();
+            let result = inflate(&mut self.state, input, buf, MZFlush::None);
+            self.inner.consume(result.bytes_consumed);
+            let written = result.bytes_written;
+            self.member_crc = crc32(self.member_crc, &buf[..written]);
+            self.member_len = self.member_len.wrapping_add(written as u32);
+            match result.status {
+                Ok(MZStatus::StreamEnd) if self.format == Format::Gzip => self.finish_member()?,
+                Ok(MZStatus::StreamEnd) => self.done = true,
+                Ok(_) | Err(MZError::Buf) if written == 0 && eof => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "compressed stream ended early",
+                    ))
+                }
+                Ok(_) | Err(MZError::Buf) => (),
+                Err(_) => return Err(invalid_data("invalid compressed data")),
+            }
+            if written > 0 {
+                return Ok(written);
+            }
+        }
+        Ok(0)
+    }
+}
+
+// Reads past a gzip member's header, including its optional fields
+fn read_gzip_header<R: BufRead>(reader: &mut R) -> io::Result<()> {
+    let mut header = [0; 10];
+    reader.read_exact(&mut header)?;
+    // ID1, ID2, and CM (8 for deflate)
+    if header[..3] != [0x1f, 0x8b, 8] {
+        return Err(invalid_data("invalid gzip header"));
+    }
+    let flags = header[3];
+    // FEXTRA: a little-endian length, then that many bytes
+    if flags & 0x04 != 0 {
+        let mut len = [0; 2];
+        reader.read_exact(&mut len)?;
+        let len = u64::from(u16::from_le_bytes(len));
+        io::copy(&mut reader.by_ref().take(len), &mut io::sink())?;
+    }
+    // FNAME and FCOMMENT: zero-terminated strings
+    for &flag in &[0x08, 0x10] {
+        if flags & flag != 0 {
+            reader.read_until(0, &mut Vec::new())?;
+        }
+    }
+    // FHCRC: a CRC16 of the header
+    if flags & 0x02 != 0 {
+        reader.read_exact(&mut [0; 2])?;
+    }
+    Ok(())
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // "hello\n" gzipped with the file name "hi" (to exercise FNAME)
+    static HELLO_GZIP: &[u8] = &[
+        0x1f, 0x8b, 0x08, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x03, b'h', b'i', 0x00, 0xcb, 0x48,
+        0xcd, 0xc9, 0xc9, 0xe7, 0x02, 0x00, 0x20, 0x30, 0x3a, 0x36, 0x06, 0x00, 0x00, 0x00,
+    ];
+
+    fn inflate_all(bytes: &[u8], format: Format) -> io::Result<Vec<u8>> {
+        // A tiny buffer exercises input split across reads
+        let mut inflater = Inflater::new(BufReader::with_capacity(3, bytes), format);
+        let mut out = Vec::new();
+        inflater.read_to_end(&mut out)?;
+        Ok(out)
+    }
+
+    #[test]
+    fn test_inflate_gzip() {
+        assert_eq!(inflate_all(HELLO_GZIP, Format::Gzip).unwrap(), b"hello\n");
+
+        let two_members = [HELLO_GZIP, HELLO_GZIP].concat();
+        let out = inflate_all(&two_members, Format::Gzip).unwrap();
+        assert_eq!(out, b"hello\nhello\n");
+
+        let truncated = inflate_all(&HELLO_GZIP[..20], Format::Gzip).unwrap_err();
+        assert_eq!(truncated.kind(), io::ErrorKind::UnexpectedEof);
+        assert!(inflate_all(b"hello\n", Format::Gzip).is_err());
+
+        let mut corrupt = HELLO_GZIP.to_vec();
+        corrupt[21] ^= 1;
+        let mismatch = inflate_all(&corrupt, Format::Gzip).unwrap_err();
+        assert_eq!(mismatch.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_inflate_zlib() {
+        // "hello\n" compressed by zlib
+        let zlib = [
+            0x78, 0x9c, 0xcb, 0x48, 0xcd, 0xc9, 0xc9, 0xe7, 0x02, 0x00, 0x08, 0x4b, 0x02, 0x1f,
+        ];
+        assert_eq!(inflate_all(&zlib, Format::Zlib).unwrap(), b"hello\n");
+    }
+}
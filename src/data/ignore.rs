@@ -0,0 +1,170 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+const IGNORE_FILE: &str = ".algoignore";
+
+// Decides which paths a recursive copy skips
+//
+// Paths are relative to the source root and use '/' separators. Precedence:
+//   1. a path matching an --exclude pattern is skipped
+//   2. if any --include patterns are given, a file is copied only if it matches one
+//   3. otherwise, a path matching a .algoignore pattern is skipped
+// Skipped directories are not descended into
+#[derive(Clone)]
+pub struct PathFilter {
+    include: Vec<Pattern>,
+    exclude: Vec<Pattern>,
+    ignored: Vec<Pattern>,
+}
+
+impl PathFilter {
+    pub fn new(include: &[String], exclude: &[String]) -> PathFilter {
+        PathFilter {
+            include: include.iter().map(|p| Pattern::new("", p)).collect(),
+            exclude: exclude.iter().map(|p| Pattern::new("", p)).collect(),
+            ignored: Vec::new(),
+        }
+    }
+
+    // Adds the patterns from a .algoignore file in `dir` (if any), returning a mark to pass
+    //   to remove_ignore_file once done with `dir`, so that they only apply to its subtree
+    //   `rel_dir` is the path of `dir` relative to the source root ("" for the root itself)
+    pub fn add_ignore_file(&mut self, dir: &Path, rel_dir: &str) -> Result<usize, String> {
+        let mark = self.ignored.len();
+        let ignore_path = dir.join(IGNORE_FILE);
+        let file = match File::open(&ignore_path) {
+            Ok(f) => f,
+            Err(_) => return Ok(mark),
+        };
+
+        let base = match rel_dir {
+            "" => String::new(),
+            _ => format!("{}/", rel_dir),
+        };
+        for line in BufReader::new(file).lines() {
+            let line =
+                line.map_err(|err| format!("Error reading {}: {}", ignore_path.display(), err))?;
+            let line = line.trim();
+            if !line.is_empty() && !line.starts_with('#') {
+                self.ignored.push(Pattern::new(&base, line));
+            }
+        }
+        Ok(mark)
+    }
+
+    // Removes the patterns added since add_ignore_file returned `mark`
+    pub fn remove_ignore_file(&mut self, mark: usize) {
+        self.ignored.truncate(mark);
+    }
+
+    pub fn is_skipped(&self, rel_path: &str, is_dir: bool) -> bool {
+        let matches = |patterns: &[Pattern]| patterns.iter().any(|p| p.matches(rel_path, is_dir));
+        if matches(&self.exclude) {
+            true
+        } else if !self.include.is_empty() && !is_dir {
+            !matches(&self.include)
+        } else {
+            matches(&self.ignored)
+        }
+    }
+}
+
+// A glob pattern, anchored to the directory where it was defined
+#[derive(Clone)]
+struct Pattern {
+    base: String,
+    glob: String,
+    dir_only: bool,
+}
+
+impl Pattern {
+    fn new(base: &str, pattern: &str) -> Pattern {
+        let dir_only = pattern.ends_with('/');
+        let pattern = pattern.trim_end_matches('/');
+        // Like .gitignore, a pattern without a slash matches at any depth
+        let glob = if pattern.contains('/') {
+            pattern.trim_start_matches('/').to_string()
+        } else {
+            format!("**/{}", pattern)
+        };
+        Pattern {
+            base: base.to_string(),
+            glob,
+            dir_only,
+        }
+    }
+
+    fn matches(&self, rel_path: &str, is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+        match rel_path.strip_prefix(&*self.base) {
+            Some(path) => glob_match(&self.glob, path),
+            None => false,
+        }
+    }
+}
+
+// Matches a path against a glob where '*' and '?' do not match '/' but '**' does
+pub fn glob_match(pattern: &str, path: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let path: Vec<char> = path.chars().collect();
+    match_chars(&pattern, &path)
+}
+
+fn match_chars(pattern: &[char], path: &[char]) -> bool {
+    match pattern {
+        [] => path.is_empty(),
+        ['*', '*', '/', rest @ ..] => {
+            // '**/' also matches zero directories
+            match_chars(rest, path)
+                || (0..path.len()).any(|i| path[i] == '/' && match_chars(rest, &path[i + 1..]))
+        }
+        ['*', '*', rest @ ..] => (0..=path.len()).any(|i| match_chars(rest, &path[i..])),
+        ['*', rest @ ..] => {
+            let segment_len = path.iter().position(|&c| c == '/').unwrap_or(path.len());
+            (0..=segment_len).any(|i| match_chars(rest, &path[i..]))
+        }
+        ['?', rest @ ..] => match path {
+            [c, path_rest @ ..] if *c != '/' => match_chars(rest, path_rest),
+            _ => false,
+        },
+        [p, rest @ ..] => match path {
+            [c, path_rest @ ..] if c == p => match_chars(rest, path_rest),
+            _ => false,
+        },
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("*.log", "debug.log"));
+        assert!(!glob_match("*.log", "logs/debug.log"));
+        assert!(glob_match("**/*.log", "debug.log"));
+        assert!(glob_match("**/*.log", "logs/deep/debug.log"));
+        assert!(glob_match("build/**", "build/a/b.o"));
+        assert!(glob_match("file?.txt", "file1.txt"));
+        assert!(!glob_match("file?.txt", "file10.txt"));
+        assert!(!glob_match("a/*/c", "a/b/x/c"));
+    }
+
+    #[test]
+    fn test_filter_precedence() {
+        let mut filter = PathFilter::new(&["*.json".to_string()], &["secret.json".to_string()]);
+        filter.ignored.push(Pattern::new("", "*.json"));
+        filter.ignored.push(Pattern::new("sub/", "tmp/"));
+
+        // --exclude beats --include, which beats .algoignore
+        assert!(filter.is_skipped("secret.json", false));
+        assert!(!filter.is_skipped("a/data.json", false));
+        assert!(filter.is_skipped("notes.txt", false));
+        // Directories are only skipped by --exclude or .algoignore
+        assert!(!filter.is_skipped("tmp", true));
+        assert!(filter.is_skipped("sub/tmp", true));
+    }
+}
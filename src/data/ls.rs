@@ -1,11 +1,16 @@
+use super::acl::{self, DirAcls};
+use super::ignore::glob_match;
 use crate::config::Profile;
+use crate::output::{self, OutputFormat};
 use crate::{color_choice, data, CmdRunner};
 use algorithmia::data::{DataItem, HasDataPath};
+use algorithmia::error::Error;
 use algorithmia::Algorithmia;
 use docopt::Docopt;
+use rustc_serialize::json::Json;
 use std::cmp;
-use std::io::Write;
-use std::ops::Deref;
+use std::collections::BTreeMap;
+use std::io::{self, Write};
 use std::vec::IntoIter;
 use termcolor::{Color, ColorSpec, StandardStream, WriteColor};
 use terminal_size::{terminal_size, Width};
@@ -17,21 +22,39 @@ static USAGE: &'static str = r##"Usage:
   List contents of a directory via the Agorithmia Data API
 
   <data-dir>    Specifies the Algorithmia Data URI
-                The 'data://' prefix is optional
-                Defaults to 'data://' root path
+                The 'data://' prefix is optional (paths without it are relative
+                  to the profile's base_data_path, if set)
+                Defaults to the base_data_path, or else the 'data://' root path
+                Its last segment can be a glob (e.g. 'data://.my/foo/*.csv') to
+                  only list the matching entries, like --filter
 
   Options:
-    -l          Use long listing format
+    -l                  Use long listing format
+    --filter <glob>     Only list entries whose names match the glob, where '*' matches any
+                          characters and '?' matches one (e.g. '*.csv')
+    --acl               Show who can read each entry (implies -l): a directory's own ACL, or
+                          for a file, the ACL of the directory containing it
+    --format <format>   Output format: 'text' (default) or 'env' to print each entry as
+                          shell-escaped DATA_<n>_NAME=value lines (with DATA_COUNT, and
+                          DATA_ACL and DATA_<n>_ACL with --acl)
+                        'json' prints an array of objects with name, path, type, size,
+                          and last_modified (and acl with --acl), while 'jsonl' prints one
+                          object per line. Both stream entries as they are listed
 "##;
 
 #[derive(RustcDecodable, Debug)]
 struct Args {
     arg_data_dir: Option<String>,
     flag_l: bool,
+    flag_acl: bool,
+    flag_filter: Option<String>,
+    flag_format: Option<String>,
 }
 
 pub struct Ls {
     client: Algorithmia,
+    base_path: Option<String>,
+    output_format: Option<String>,
 }
 
 impl CmdRunner for Ls {
@@ -44,12 +67,41 @@ impl CmdRunner for Ls {
             .and_then(|d| d.argv(argv).decode())
             .unwrap_or_else(|e| e.exit());
 
-        let data_uri = args
-            .arg_data_dir
-            .as_ref()
-            .map(Deref::deref)
-            .unwrap_or("data://");
-        self.list_dir(data_uri, args.flag_l);
+        let data_uri = match (args.arg_data_dir.as_deref(), &self.base_path) {
+            (None, None) => "data://".to_string(),
+            (path, base_path) => data::resolve_path(base_path.as_deref(), path.unwrap_or("")),
+        };
+        let (data_uri, pattern) = match (split_glob(&data_uri), args.flag_filter) {
+            (Some(_), Some(_)) => {
+                quit_msg!("Use either a glob in <data-dir> or --filter, not both")
+            }
+            (Some((dir, pattern)), None) => (dir.to_string(), Some(pattern.to_string())),
+            (None, filter) => (data_uri, filter),
+        };
+        let pattern = pattern.as_deref();
+        let format = OutputFormat::resolve(
+            args.flag_format.as_deref(),
+            self.output_format.as_deref(),
+            &[
+                OutputFormat::Text,
+                OutputFormat::Env,
+                OutputFormat::Json,
+                OutputFormat::Jsonl,
+            ],
+        );
+        let acls = if args.flag_acl {
+            let acls = acl::get_dir_acls(&self.client.dir(&data_uri))
+                .unwrap_or_else(|err| quit_msg!("Error getting ACLs: {}", err));
+            Some(acls)
+        } else {
+            None
+        };
+        match format {
+            OutputFormat::Env => self.list_dir_env(&data_uri, pattern, acls.as_ref()),
+            OutputFormat::Json => self.list_dir_json(&data_uri, pattern, acls.as_ref(), false),
+            OutputFormat::Jsonl => self.list_dir_json(&data_uri, pattern, acls.as_ref(), true),
+            OutputFormat::Text => self.list_dir(&data_uri, pattern, args.flag_l, acls.as_ref()),
+        }
     }
 }
 
@@ -57,15 +109,93 @@ impl Ls {
     pub fn new(profile: Profile) -> Self {
         Ls {
             client: profile.client(),
+            base_path: profile.base_data_path().map(String::from),
+            output_format: profile.output_format().map(String::from),
+        }
+    }
+
+    fn list_dir_env(&self, path: &str, pattern: Option<&str>, acls: Option<&DirAcls>) {
+        let items: Vec<DataItem> = self
+            .client
+            .dir(path)
+            .list()
+            .filter(|entry| is_listed(entry, pattern))
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap_or_else(|err| quit_err!("Error listing directory: {}", err));
+
+        let mut vars = vec![("DATA_COUNT".to_string(), items.len().to_string())];
+        if let Some(acls) = acls {
+            vars.push(("DATA_ACL".to_string(), acl::describe(&acls.acl)));
+        }
+        for (i, item) in items.iter().enumerate() {
+            let mut var = |field: &str, value: String| {
+                vars.push((format!("DATA_{}_{}", i, field), value));
+            };
+            match item {
+                DataItem::Dir(d) => {
+                    var("NAME", d.basename().unwrap());
+                    var("TYPE", "dir".to_string());
+                    var("URI", d.to_data_uri());
+                }
+                DataItem::File(f) => {
+                    var("NAME", f.basename().unwrap());
+                    var("TYPE", "file".to_string());
+                    var("URI", f.to_data_uri());
+                    var("SIZE", f.size.to_string());
+                    var("LAST_MODIFIED", f.last_modified.to_rfc3339());
+                }
+            }
+            if let Some(acls) = acls {
+                var("ACL", entry_acl(acls, item));
+            }
+        }
+        output::print_env(&vars);
+    }
+
+    // Prints each entry as a JSON object as it is listed, either as elements of one array
+    //   or (with `lines`) one per line
+    fn list_dir_json(
+        &self,
+        path: &str,
+        pattern: Option<&str>,
+        acls: Option<&DirAcls>,
+        lines: bool,
+    ) {
+        let stdout = io::stdout();
+        let mut out = stdout.lock();
+        let mut written = Ok(());
+        if !lines {
+            written = written.and_then(|_| write!(out, "["));
+        }
+        let dir = self.client.dir(path);
+        let entries = dir.list().filter(|entry| is_listed(entry, pattern));
+        for (i, entry_result) in entries.enumerate() {
+            let item =
+                entry_result.unwrap_or_else(|err| quit_err!("Error listing directory: {}", err));
+            let entry = entry_json(&item, acls);
+            written = written.and_then(|_| match (lines, i) {
+                (true, _) => writeln!(out, "{}", entry),
+                (false, 0) => write!(out, "{}", entry),
+                (false, _) => write!(out, ",\n{}", entry),
+            });
+        }
+        if !lines {
+            written = written.and_then(|_| writeln!(out, "]"));
+        }
+        if let Err(err) = written {
+            quit_err!("Error writing output: {}", err);
         }
     }
 
-    fn list_dir(&self, path: &str, long: bool) {
+    fn list_dir(&self, path: &str, pattern: Option<&str>, long: bool, acls: Option<&DirAcls>) {
         let my_dir = self.client.dir(path);
 
         let mut t_out = StandardStream::stdout(color_choice());
-        if long {
-            for entry_result in my_dir.list() {
+        if long || acls.is_some() {
+            for entry_result in my_dir.list().filter(|entry| is_listed(entry, pattern)) {
+                if let (Some(acls), Ok(item)) = (acls, &entry_result) {
+                    let _ = write!(t_out, "{:13} ", entry_acl(acls, item));
+                }
                 match entry_result {
                     Ok(DataItem::Dir(d)) => {
                         let _ = write!(t_out, "{:19} {:>5} ", "--         --", "[dir]");
@@ -92,6 +222,7 @@ impl Ls {
         } else {
             let items: Vec<DataItem> = my_dir
                 .list()
+                .filter(|entry| is_listed(entry, pattern))
                 .collect::<Result<Vec<_>, _>>()
                 .unwrap_or_else(|err| quit_err!("Error listing directory: {}", err));
 
@@ -144,6 +275,70 @@ impl Ls {
     }
 }
 
+// Splits a data URI whose last segment is a glob into the directory to list and the glob
+fn split_glob(uri: &str) -> Option<(&str, &str)> {
+    let (dir, name) = uri.split_at(uri.rfind('/')? + 1);
+    if !name.contains(|c| c == '*' || c == '?') {
+        return None;
+    }
+    // Keep the slashes of a root like "data://"
+    let dir = if dir.ends_with("://") {
+        dir
+    } else {
+        &dir[..dir.len() - 1]
+    };
+    Some((dir, name))
+}
+
+// Whether an entry's name matches the glob (if any), passing listing errors through
+fn is_listed(entry: &Result<DataItem, Error>, pattern: Option<&str>) -> bool {
+    let name = match (entry, pattern) {
+        (Ok(DataItem::Dir(d)), Some(_)) => d.basename().unwrap(),
+        (Ok(DataItem::File(f)), Some(_)) => f.basename().unwrap(),
+        _ => return true,
+    };
+    pattern.map_or(true, |pattern| glob_match(pattern, &name))
+}
+
+// The ACL that applies to an entry: a directory's own, or a file's directory's
+fn entry_acl(acls: &DirAcls, item: &DataItem) -> String {
+    match item {
+        DataItem::Dir(d) => match acls.folders.get(&d.basename().unwrap()) {
+            Some(read) => acl::describe(read),
+            None => "?".to_string(),
+        },
+        DataItem::File(_) => acl::describe(&acls.acl),
+    }
+}
+
+// An entry as a JSON object (size and last_modified are null for directories)
+fn entry_json(item: &DataItem, acls: Option<&DirAcls>) -> Json {
+    let mut entry = BTreeMap::new();
+    let mut field = |key: &str, value: Json| {
+        entry.insert(key.to_string(), value);
+    };
+    match item {
+        DataItem::Dir(d) => {
+            field("name", Json::String(d.basename().unwrap()));
+            field("path", Json::String(d.to_data_uri()));
+            field("type", Json::String("dir".to_string()));
+            field("size", Json::Null);
+            field("last_modified", Json::Null);
+        }
+        DataItem::File(f) => {
+            field("name", Json::String(f.basename().unwrap()));
+            field("path", Json::String(f.to_data_uri()));
+            field("type", Json::String("file".to_string()));
+            field("size", Json::U64(f.size));
+            field("last_modified", Json::String(f.last_modified.to_rfc3339()));
+        }
+    }
+    if let Some(acls) = acls {
+        field("acl", Json::String(entry_acl(acls, item)));
+    }
+    Json::Object(entry)
+}
+
 enum FileType {
     Image,
     Video,
@@ -189,3 +384,19 @@ impl FileType {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_split_glob() {
+        assert_eq!(
+            split_glob("data://.my/foo/*.csv"),
+            Some(("data://.my/foo", "*.csv"))
+        );
+        assert_eq!(split_glob("data://.my/f?o"), Some(("data://.my", "f?o")));
+        assert_eq!(split_glob("data://*"), Some(("data://", "*")));
+        assert_eq!(split_glob("data://.my/foo"), None);
+    }
+}
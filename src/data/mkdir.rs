@@ -1,5 +1,5 @@
 use crate::config::Profile;
-use crate::CmdRunner;
+use crate::{data, CmdRunner};
 use algorithmia::data::{DataAcl, HasDataPath};
 use algorithmia::Algorithmia;
 use docopt::Docopt;
@@ -11,7 +11,8 @@ static USAGE: &'static str = r##"Usage:
   Create an Agorithmia data directory
 
   <data-dir>    Specifies the Algorithmia Data URI
-                The 'data://' prefix is optional
+                The 'data://' prefix is optional (paths without it are relative
+                  to the profile's base_data_path, if set)
 "##;
 
 #[derive(RustcDecodable, Debug)]
@@ -21,6 +22,7 @@ struct Args {
 
 pub struct MkDir {
     client: Algorithmia,
+    base_path: Option<String>,
 }
 impl CmdRunner for MkDir {
     fn get_usage() -> &'static str {
@@ -32,7 +34,8 @@ impl CmdRunner for MkDir {
             .and_then(|d| d.argv(argv).decode())
             .unwrap_or_else(|e| e.exit());
 
-        self.create_dir(&*args.arg_data_dir);
+        let path = data::resolve_path(self.base_path.as_deref(), &args.arg_data_dir);
+        self.create_dir(&path);
     }
 }
 
@@ -40,6 +43,7 @@ impl MkDir {
     pub fn new(profile: Profile) -> Self {
         MkDir {
             client: profile.client(),
+            base_path: profile.base_data_path().map(String::from),
         }
     }
 
@@ -0,0 +1,108 @@
+use super::{download, size_with_suffix};
+use crate::config::Profile;
+use crate::{data, CmdRunner};
+use algorithmia::data::{DataFile, HasDataPath};
+use algorithmia::Algorithmia;
+use docopt::Docopt;
+use mime::Mime;
+use std::fs;
+use std::vec::IntoIter;
+
+static USAGE: &str = r##"Usage:
+  mia mkfile [options] <data-file> (--content <text> | --content-file <file>)
+
+  Create (or overwrite) a data file with the given content, without a local file to copy
+
+  <data-file>   Specifies the Algorithmia Data URI
+                The 'data://' prefix is optional (paths without it are relative
+                  to the profile's base_data_path, if set)
+
+  Options:
+    --content <text>        The content of the file
+    --content-file <file>   Read the content from a local file instead
+    --content-type <mime>   Store the file with this Content-Type (e.g. text/csv)
+
+  Examples:
+    mia mkfile data://.my/foo/hello.txt --content 'hi'
+                                                      Create hello.txt containing 'hi'
+    mia mkfile .my/foo/cfg.json --content '{"n": 1}' --content-type application/json
+                                                      Create cfg.json stored as JSON
+"##;
+
+#[derive(RustcDecodable, Debug)]
+struct Args {
+    arg_data_file: String,
+    flag_content: Option<String>,
+    flag_content_file: Option<String>,
+    flag_content_type: Option<String>,
+}
+
+pub struct MkFile {
+    client: Algorithmia,
+    base_path: Option<String>,
+}
+impl CmdRunner for MkFile {
+    fn get_usage() -> &'static str {
+        USAGE
+    }
+
+    fn cmd_main(&self, argv: IntoIter<String>) {
+        let args: Args = Docopt::new(USAGE)
+            .and_then(|d| d.argv(argv).decode())
+            .unwrap_or_else(|e| e.exit());
+
+        let content_type = args.flag_content_type.map(|mime| {
+            mime.parse::<Mime>()
+                .unwrap_or_else(|_| quit_msg!("Invalid --content-type: {}", mime))
+        });
+        let content = match (args.flag_content, args.flag_content_file) {
+            (Some(content), _) => content.into_bytes(),
+            (None, Some(path)) => {
+                fs::read(&path).unwrap_or_else(|err| quit_err!("Error reading {}: {}", path, err))
+            }
+            (None, None) => unreachable!("docopt requires --content or --content-file"),
+        };
+
+        let path = data::resolve_path(self.base_path.as_deref(), &args.arg_data_file);
+        let data_file = self.client.file(&path);
+        let size = content.len() as u64;
+        match put_content(&data_file, content, content_type) {
+            Ok(_) => println!(
+                "Created file {} ({}B)",
+                data_file.to_data_uri(),
+                size_with_suffix(size)
+            ),
+            Err(err) => quit_msg!("Error creating file {}: {}", data_file.to_data_uri(), err),
+        }
+    }
+}
+
+impl MkFile {
+    pub fn new(profile: Profile) -> Self {
+        MkFile {
+            client: profile.client(),
+            base_path: profile.base_data_path().map(String::from),
+        }
+    }
+}
+
+// Writes a data file, with a Content-Type if given (which DataFile::put can't set)
+fn put_content(
+    data_file: &DataFile,
+    content: Vec<u8>,
+    content_type: Option<Mime>,
+) -> Result<(), String> {
+    let url = data_file.to_url().map_err(|err| err.to_string())?;
+    let mut request = data_file.client().put(url);
+    if let Some(content_type) = content_type {
+        request = request.header("Content-Type", content_type.to_string());
+    }
+    let response = request
+        .body(content)
+        .send()
+        .map_err(|err| err.to_string())?;
+    if !response.status().is_success() {
+        return Err(download::status_err(&response));
+    }
+    Ok(())
+}
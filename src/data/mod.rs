@@ -1,16 +1,154 @@
-pub use self::cat::Cat;
-pub use self::cp::Cp;
+pub use self::acl::{Acl, ReadAccess};
+pub use self::archive::ArchiveFormat;
+pub use self::cat::{Cat, CatOptions, Grep, Span};
+pub use self::cp::{
+    Cp, CpOptions, CpSummary, SourceSummary, TransferCallback, TransferEvent, TransferStatus,
+};
+pub use self::download::get_file;
 pub use self::ls::Ls;
 pub use self::mkdir::MkDir;
+pub use self::mkfile::MkFile;
 pub use self::rm::Rm;
 pub use self::rmdir::RmDir;
+pub use self::stat::{DataStat, Stat};
 
+mod acl;
+mod archive;
 mod cat;
+mod charset;
+mod concurrency;
 mod cp;
+mod download;
+mod ignore;
 mod ls;
 mod mkdir;
+mod mkfile;
+mod progress;
 mod rm;
 mod rmdir;
+mod stat;
+
+use chrono::{DateTime, Duration, NaiveDate, NaiveDateTime, Utc};
+use std::io::{self, Read, Write};
+
+// Resolves a data path against a base data path (from the profile or --base-path)
+//   Paths with a scheme (e.g. 'data://') are absolute and are returned unchanged
+pub fn resolve_path(base_path: Option<&str>, path: &str) -> String {
+    match base_path {
+        Some(base) if !path.contains("://") => {
+            let base = base.trim_end_matches('/');
+            let path = path.trim_start_matches('/');
+            if path.is_empty() {
+                base.to_string()
+            } else {
+                format!("{}/{}", base, path)
+            }
+        }
+        _ => path.to_string(),
+    }
+}
+
+// Parses a size like "512", "10k", "5M", or "1.5G" (powers of 1024) into bytes
+//   Parsed as decimal digits rather than a float, so that a size too large for a u64 is an
+//   error instead of saturating
+pub fn parse_size(input: &str) -> Result<u64, String> {
+    let trimmed = input.trim();
+    let (number, multiplier) = match trimmed.chars().last().map(|c| c.to_ascii_uppercase()) {
+        Some('K') => (&trimmed[..trimmed.len() - 1], 1024),
+        Some('M') => (&trimmed[..trimmed.len() - 1], 1024 * 1024),
+        Some('G') => (&trimmed[..trimmed.len() - 1], 1024 * 1024 * 1024),
+        _ => (trimmed, 1),
+    };
+    let mut parts = number.splitn(2, '.');
+    let (whole, fraction) = (parts.next().unwrap_or_default(), parts.next());
+    let is_digits = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+    if !is_digits(whole) || !fraction.map_or(true, is_digits) {
+        return Err(format!("invalid size '{}'", input));
+    }
+
+    // A fraction adds its share of the multiplier, rounded down (to the byte)
+    let fraction_bytes = fraction.map_or(0, |digits| {
+        let digits = &digits[..digits.len().min(18)];
+        let scale = 10u128.pow(digits.len() as u32);
+        let numerator = digits.parse::<u128>().unwrap_or(0);
+        (numerator * u128::from(multiplier) / scale) as u64
+    });
+    whole
+        .parse::<u64>()
+        .ok()
+        .and_then(|whole| whole.checked_mul(multiplier))
+        .and_then(|bytes| bytes.checked_add(fraction_bytes))
+        .ok_or_else(|| format!("size '{}' is too large", input))
+}
+
+// Parses a time threshold, either relative to `now` like "90s", "30m", "24h", "7d", or "2w",
+//   or an absolute timestamp like "2024-05-01T12:00:00Z" (RFC 3339), "2024-05-01T12:00:00",
+//   or "2024-05-01" (the latter two in UTC)
+pub fn parse_time_threshold(input: &str, now: DateTime<Utc>) -> Result<DateTime<Utc>, String> {
+    let trimmed = input.trim();
+    let invalid = || format!("invalid duration or timestamp '{}'", input);
+    if let Ok(time) = DateTime::parse_from_rfc3339(trimmed) {
+        return Ok(time.with_timezone(&Utc));
+    }
+    if let Ok(time) = NaiveDateTime::parse_from_str(trimmed, "%Y-%m-%dT%H:%M:%S") {
+        return Ok(DateTime::from_utc(time, Utc));
+    }
+    if let Ok(date) = NaiveDate::parse_from_str(trimmed, "%Y-%m-%d") {
+        return Ok(DateTime::from_utc(date.and_hms(0, 0, 0), Utc));
+    }
+
+    let unit = trimmed.chars().last().ok_or_else(invalid)?;
+    let number = trimmed[..trimmed.len() - unit.len_utf8()]
+        .parse::<i64>()
+        .map_err(|_| invalid())?;
+    let duration = match unit {
+        's' => Duration::seconds(number),
+        'm' => Duration::minutes(number),
+        'h' => Duration::hours(number),
+        'd' => Duration::days(number),
+        'w' => Duration::weeks(number),
+        _ => return Err(invalid()),
+    };
+    Ok(now - duration)
+}
+
+// The outcome of copying a downloaded file
+pub enum Copied {
+    Complete(u64),
+    // Fewer bytes were received than the Data API reported for the file (or more)
+    Short(u64, String),
+}
+
+// Copies a download, checking the number of bytes received against its reported size
+//   An expected size of 0 is treated as unknown (the API omitted Content-Length).
+//   A read error partway through a file of known size (e.g. the connection closing early)
+//   counts as a short read, while other errors are returned as is
+pub fn copy_download(
+    reader: &mut dyn Read,
+    writer: &mut dyn Write,
+    expected: u64,
+) -> io::Result<Copied> {
+    let mut buf = [0; 64 * 1024];
+    let mut copied = 0;
+    loop {
+        let n = match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(_) if expected > copied => break,
+            Err(err) => return Err(err),
+        };
+        writer.write_all(&buf[..n])?;
+        copied += n as u64;
+    }
+
+    if expected > 0 && copied != expected {
+        let msg = format!("received {} of {} bytes", copied, expected);
+        Ok(Copied::Short(copied, msg))
+    } else {
+        Ok(Copied::Complete(copied))
+    }
+}
 
 pub fn size_with_suffix(size: u64) -> String {
     match size / 1024 {
@@ -27,3 +165,62 @@ pub fn size_with_suffix(size: u64) -> String {
         },
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_resolve_path() {
+        let base = Some("data://.my/projectx/");
+        assert_eq!(
+            resolve_path(base, "foo.json"),
+            "data://.my/projectx/foo.json"
+        );
+        assert_eq!(
+            resolve_path(base, "/a/b.txt"),
+            "data://.my/projectx/a/b.txt"
+        );
+        assert_eq!(resolve_path(base, ""), "data://.my/projectx");
+        assert_eq!(resolve_path(base, "data://.my/other"), "data://.my/other");
+        assert_eq!(resolve_path(None, ".my/foo"), ".my/foo");
+    }
+
+    #[test]
+    fn test_parse_time_threshold() {
+        let now = DateTime::parse_from_rfc3339("2024-05-10T12:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let parse = |input| parse_time_threshold(input, now).map(|t| t.to_rfc3339());
+        assert_eq!(parse("24h"), Ok("2024-05-09T12:00:00+00:00".to_string()));
+        assert_eq!(parse("7d"), Ok("2024-05-03T12:00:00+00:00".to_string()));
+        assert_eq!(parse("90s"), Ok("2024-05-10T11:58:30+00:00".to_string()));
+        assert_eq!(
+            parse("2024-05-01"),
+            Ok("2024-05-01T00:00:00+00:00".to_string())
+        );
+        assert_eq!(
+            parse("2024-05-01T08:30:00+02:00"),
+            Ok("2024-05-01T06:30:00+00:00".to_string())
+        );
+        assert!(parse("7y").is_err());
+        assert!(parse("d").is_err());
+        assert!(parse("").is_err());
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("512"), Ok(512));
+        assert_eq!(parse_size("10k"), Ok(10 * 1024));
+        assert_eq!(parse_size("5M"), Ok(5 * 1024 * 1024));
+        assert_eq!(parse_size("1.5G"), Ok(1024 * 1024 * 1536));
+        assert_eq!(parse_size("0.5k"), Ok(512));
+        assert_eq!(parse_size("18446744073709551615"), Ok(u64::MAX));
+        assert!(parse_size("-1").is_err());
+        assert!(parse_size("lots").is_err());
+        assert!(parse_size("1e30").is_err());
+        assert!(parse_size("1.").is_err());
+        assert!(parse_size("99999999999G").is_err());
+        assert!(parse_size("18446744073709551616").is_err());
+    }
+}
@@ -0,0 +1,229 @@
+// Progress of `mia cp --progress-fd`, written as one JSON object per line to a file
+//   descriptor that a wrapping program (e.g. a GUI) opened for mia, so it can render progress
+//   without parsing STDOUT and STDERR (which are printed as usual)
+//
+// Each transferred, skipped, or failed file is a "file" event with running tallies, and the
+//   copy ends with a "done" event with the totals, or an "error" event with the message and
+//   the tallies so far if cp exits early (e.g. an error or --deadline), for example:
+//   {"event":"file","path":"data://.my/a.txt","status":"uploaded","error":null,"file_bytes":1024,
+//    "etag":"\"8c2f0e1a\"","completed":1,"failed":0,"skipped":0,"bytes":1024,
+//    "stopped_early":false}
+
+use super::cp::{CpSummary, TransferEvent, TransferStatus};
+use rustc_serialize::json;
+use std::fs::File;
+use std::io::Write;
+use std::sync::Mutex;
+
+pub struct ProgressFd {
+    state: Mutex<State>,
+}
+
+struct State {
+    file: File,
+    tally: Tally,
+    // Set after a failed write (e.g. the reader went away), so the error is only printed once
+    broken: bool,
+}
+
+#[derive(Default)]
+struct Tally {
+    completed: u64,
+    failed: u64,
+    skipped: u64,
+    bytes: u64,
+}
+
+#[derive(RustcEncodable)]
+struct ProgressEvent<'a> {
+    event: &'a str,
+    // For "file" events, the file and how it went (`error` is also the message of "error")
+    path: Option<&'a str>,
+    status: Option<&'a str>,
+    error: Option<&'a str>,
+    file_bytes: Option<u64>,
+    // The ETag of an uploaded file, if the Data API sent one
+    etag: Option<&'a str>,
+    // The tallies so far (or in total, for "done")
+    completed: u64,
+    failed: u64,
+    skipped: u64,
+    bytes: u64,
+    stopped_early: bool,
+}
+
+impl ProgressFd {
+    // Writes events to an open file descriptor (other than STDIN, STDOUT, or STDERR)
+    #[cfg(unix)]
+    pub fn open(fd: i32) -> Result<ProgressFd, String> {
+        use std::os::unix::io::FromRawFd;
+
+        if fd <= 2 {
+            return Err(format!(
+                "--progress-fd must be a descriptor other than STDIN, STDOUT, or STDERR (not {})",
+                fd
+            ));
+        }
+        // Safe as long as nothing else in mia owns the descriptor, which it only knows by number
+        let file = unsafe { File::from_raw_fd(fd) };
+        if let Err(err) = file.metadata() {
+            // Don't close a descriptor that was never ours
+            std::mem::forget(file);
+            return Err(format!("--progress-fd {} is not open: {}", fd, err));
+        }
+        Ok(ProgressFd {
+            state: Mutex::new(State {
+                file,
+                tally: Tally::default(),
+                broken: false,
+            }),
+        })
+    }
+
+    #[cfg(not(unix))]
+    pub fn open(_fd: i32) -> Result<ProgressFd, String> {
+        Err("--progress-fd is only supported on Unix".to_string())
+    }
+
+    pub fn file(&self, event: &TransferEvent) {
+        let mut state = self.state.lock().unwrap();
+        let line = state.tally.file_event(event);
+        state.write(&line);
+    }
+
+    pub fn done(&self, summary: &CpSummary) {
+        let mut state = self.state.lock().unwrap();
+        state.write(&done_event(summary));
+    }
+
+    // Ends the events when cp exits without a summary
+    pub fn error(&self, message: &str) {
+        let mut state = self.state.lock().unwrap();
+        let line = state.tally.error_event(message);
+        state.write(&line);
+    }
+}
+
+impl State {
+    fn write(&mut self, line: &str) {
+        if self.broken {
+            return;
+        }
+        if let Err(err) = writeln!(self.file, "{}", line).and_then(|_| self.file.flush()) {
+            eprintln_red!("Error writing progress events: {}", err);
+            self.broken = true;
+        }
+    }
+}
+
+impl Tally {
+    // Counts a file, returning its event
+    fn file_event(&mut self, event: &TransferEvent) -> String {
+        let (status, error) = match event.status {
+            TransferStatus::Uploaded => ("uploaded", None),
+            TransferStatus::Downloaded => ("downloaded", None),
+            TransferStatus::Skipped => ("skipped", None),
+            TransferStatus::Failed(ref err) => ("failed", Some(err.as_str())),
+        };
+        match event.status {
+            TransferStatus::Skipped => self.skipped += 1,
+            TransferStatus::Failed(_) => self.failed += 1,
+            _ => {
+                self.completed += 1;
+                self.bytes += event.bytes;
+            }
+        }
+        encode(&ProgressEvent {
+            event: "file",
+            path: Some(&event.path),
+            status: Some(status),
+            error,
+            file_bytes: Some(event.bytes),
+            etag: event.etag.as_deref(),
+            completed: self.completed,
+            failed: self.failed,
+            skipped: self.skipped,
+            bytes: self.bytes,
+            stopped_early: false,
+        })
+    }
+
+    fn error_event(&self, message: &str) -> String {
+        encode(&ProgressEvent {
+            event: "error",
+            path: None,
+            status: None,
+            error: Some(message),
+            file_bytes: None,
+            etag: None,
+            completed: self.completed,
+            failed: self.failed,
+            skipped: self.skipped,
+            bytes: self.bytes,
+            stopped_early: true,
+        })
+    }
+}
+
+fn done_event(summary: &CpSummary) -> String {
+    encode(&ProgressEvent {
+        event: "done",
+        path: None,
+        status: None,
+        error: None,
+        file_bytes: None,
+        etag: None,
+        completed: summary.uploaded + summary.downloaded,
+        failed: summary.failed,
+        skipped: summary.skipped,
+        bytes: summary.bytes,
+        stopped_early: summary.stopped_early,
+    })
+}
+
+fn encode(event: &ProgressEvent) -> String {
+    json::encode(event).expect("progress event is encodable")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_progress_events() {
+        let mut tally = Tally::default();
+        let event = |path: &str, bytes, status| TransferEvent {
+            path: path.to_string(),
+            bytes,
+            status,
+            etag: None,
+        };
+        let uploaded = tally.file_event(&TransferEvent {
+            etag: Some("\"v1\"".to_string()),
+            ..event("a", 10, TransferStatus::Uploaded)
+        });
+        assert!(uploaded.contains(r#""file_bytes":10,"etag":"\"v1\"","#));
+        tally.file_event(&event("b", 0, TransferStatus::Skipped));
+        let line = tally.file_event(&event("c", 0, TransferStatus::Failed("nope".into())));
+        assert_eq!(
+            line,
+            r#"{"event":"file","path":"c","status":"failed","error":"nope","file_bytes":0,"etag":null,"completed":1,"failed":1,"skipped":1,"bytes":10,"stopped_early":false}"#
+        );
+
+        let summary = CpSummary {
+            uploaded: 1,
+            failed: 1,
+            skipped: 1,
+            bytes: 10,
+            ..CpSummary::default()
+        };
+        assert_eq!(
+            done_event(&summary),
+            r#"{"event":"done","path":null,"status":null,"error":null,"file_bytes":null,"etag":null,"completed":1,"failed":1,"skipped":1,"bytes":10,"stopped_early":false}"#
+        );
+        assert_eq!(
+            tally.error_event("timed out"),
+            r#"{"event":"error","path":null,"status":null,"error":"timed out","file_bytes":null,"etag":null,"completed":1,"failed":1,"skipped":1,"bytes":10,"stopped_early":true}"#
+        );
+    }
+}
@@ -1,26 +1,49 @@
 use crate::config::Profile;
-use crate::CmdRunner;
-use algorithmia::data::HasDataPath;
+use crate::{data, CmdRunner};
+use algorithmia::data::{DataDir, DataItem, HasDataPath};
 use algorithmia::Algorithmia;
 use docopt::Docopt;
+use isatty::stdin_isatty;
+use std::io::{self, BufRead, Write};
+use std::sync::{Arc, Mutex};
 use std::vec::IntoIter;
+use std::{cmp, thread};
 
 static USAGE: &'static str = r##"Usage:
-  mia rm <data-file>
+  mia rm [options] <data-path>
 
   Removes a file from the Agorithmia Data API
+  With -r, removes a directory by deleting its files in parallel and then its directories
 
-  <data-file>       Specifies the Algorithmia Data URI
-                    The 'data://' prefix is optional
+  <data-path>       Specifies the Algorithmia Data URI
+                    The 'data://' prefix is optional (paths without it are relative
+                      to the profile's base_data_path, if set)
+
+  Options:
+    -r, --recursive     Remove a directory and all of its contents
+    -c <CONCURRENCY>    Number of threads for deleting files in parallel [Default: 8]
+    --require-confirm   Require typing the directory name (or the whole URI of a root such
+                          as data://.my) before a recursive delete (always required when
+                          deleting more than 100 files)
+    -f, --force         Skip the confirmation for recursive deletes
+
+  Examples:
+    mia rm .my/foo/file1.jpg                    Delete a single file
+    mia rm -r -c 16 .my/foo                     Delete 'foo' and its contents using 16 threads
 "##;
 
 #[derive(RustcDecodable, Debug)]
 struct Args {
-    arg_data_file: String,
+    arg_data_path: String,
+    flag_recursive: bool,
+    flag_c: u32,
+    flag_require_confirm: bool,
+    flag_force: bool,
 }
 
 pub struct Rm {
     client: Algorithmia,
+    base_path: Option<String>,
 }
 impl CmdRunner for Rm {
     fn get_usage() -> &'static str {
@@ -32,7 +55,25 @@ impl CmdRunner for Rm {
             .and_then(|d| d.argv(argv).decode())
             .unwrap_or_else(|e| e.exit());
 
-        self.delete_file(&*args.arg_data_file);
+        let path = data::resolve_path(self.base_path.as_deref(), &args.arg_data_path);
+        if args.flag_recursive {
+            match self.client.data(&*path).into_type() {
+                Ok(DataItem::Dir(_)) => {
+                    let confirm = if args.flag_force {
+                        Confirm::Never
+                    } else if args.flag_require_confirm {
+                        Confirm::Always
+                    } else {
+                        Confirm::OverThreshold
+                    };
+                    self.delete_recursive(&path, args.flag_c, confirm)
+                }
+                Ok(DataItem::File(_)) => self.delete_file(&path),
+                Err(err) => quit_err!("Error deleting {}: {}", path, err),
+            }
+        } else {
+            self.delete_file(&path);
+        }
     }
 }
 
@@ -40,6 +81,7 @@ impl Rm {
     pub fn new(profile: Profile) -> Self {
         Rm {
             client: profile.client(),
+            base_path: profile.base_data_path().map(String::from),
         }
     }
 
@@ -50,4 +92,166 @@ impl Rm {
             Err(err) => quit_err!("Error deleting file: {}", err),
         };
     }
+
+    fn delete_recursive(&self, path: &str, max_concurrency: u32, confirm: Confirm) {
+        let root = self.client.dir(path);
+        let mut files = Vec::new();
+        let mut dirs = Vec::new();
+        collect_tree(&root, &mut files, &mut dirs);
+
+        let needs_confirm = match confirm {
+            Confirm::Always => true,
+            Confirm::OverThreshold => files.len() > CONFIRM_THRESHOLD,
+            Confirm::Never => false,
+        };
+        if needs_confirm {
+            confirm_by_typing(&root, files.len());
+        }
+
+        let concurrency = cmp::min(files.len(), max_concurrency as usize);
+        let (tx, rx) = chan::sync(max_concurrency as usize);
+        let wg = chan::WaitGroup::new();
+        let deleted = Arc::new(Mutex::new(0));
+        let failed = Arc::new(Mutex::new(0));
+
+        // One Producer thread queuing up file paths to delete
+        thread::spawn(move || {
+            for file in files {
+                tx.send(file);
+            }
+            drop(tx);
+        });
+
+        // Spin up threads to concurrently delete files per the paths received on rx channel
+        for _ in 0..concurrency {
+            wg.add(1);
+
+            let thread_wg = wg.clone();
+            let thread_rx = rx.clone();
+            let thread_client = self.client.clone();
+            let thread_deleted = deleted.clone();
+            let thread_failed = failed.clone();
+
+            thread::spawn(move || {
+                for rx_path in thread_rx {
+                    match thread_client.file(&rx_path).delete() {
+                        Ok(_) => {
+                            println!("Deleted file {}", rx_path);
+                            *thread_deleted.lock().unwrap() += 1;
+                        }
+                        Err(err) => {
+                            eprintln_red!("Error deleting file {}: {}", rx_path, err);
+                            *thread_failed.lock().unwrap() += 1;
+                        }
+                    }
+                }
+                thread_wg.done();
+            });
+        }
+
+        wg.wait();
+
+        // Directories are collected parents-first, so delete in reverse once their files are gone
+        let mut failed_dirs = 0;
+        for dir_uri in dirs.iter().rev() {
+            match self.client.dir(dir_uri).delete(false) {
+                Ok(_) => println!("Deleted directory {}", dir_uri),
+                Err(err) => {
+                    eprintln_red!("Error deleting directory {}: {}", dir_uri, err);
+                    failed_dirs += 1;
+                }
+            }
+        }
+
+        println!("Finished deleting {} file(s)", *deleted.lock().unwrap());
+        let failed = *failed.lock().unwrap();
+        if failed > 0 || failed_dirs > 0 {
+            quit_msg!(
+                "Failed to delete {} file(s) and {} directory(s)",
+                failed,
+                failed_dirs
+            );
+        }
+    }
+}
+
+// Number of files a recursive delete may remove without confirmation
+const CONFIRM_THRESHOLD: usize = 100;
+
+enum Confirm {
+    Always,
+    OverThreshold,
+    Never,
+}
+
+// Requires the user to type the directory name before proceeding
+//   For a root (e.g. data://.my), whose name is easy to type by mistake, the whole URI must
+//   be typed instead
+fn confirm_by_typing(dir: &DataDir, file_count: usize) {
+    let (what, name) = match confirm_name(dir.path()) {
+        Some(name) => ("directory name", name.to_string()),
+        None => ("directory URI", dir.to_data_uri()),
+    };
+    if !stdin_isatty() {
+        quit_msg!(
+            "Refusing to delete {} ({} file(s)) without confirmation. Use --force to skip it.",
+            dir.to_data_uri(),
+            file_count
+        );
+    }
+
+    println!(
+        "About to delete {} and its {} file(s)",
+        dir.to_data_uri(),
+        file_count
+    );
+    print!("Type the {} ({}) to confirm: ", what, name);
+    let _ = io::stdout().flush();
+
+    let mut line = String::new();
+    let stdin = io::stdin();
+    stdin
+        .lock()
+        .read_line(&mut line)
+        .unwrap_or_else(|err| quit_err!("Cannot read input: {}", err));
+    if line.trim() != name {
+        quit_msg!("Confirmation did not match. Nothing was deleted.");
+    }
+}
+
+// Walks a data directory, collecting file URIs and directory URIs (parents before children)
+fn collect_tree(dir: &DataDir, files: &mut Vec<String>, dirs: &mut Vec<String>) {
+    dirs.push(dir.to_data_uri());
+    for entry in dir.list() {
+        match entry {
+            Ok(DataItem::File(f)) => files.push(f.to_data_uri()),
+            Ok(DataItem::Dir(d)) => collect_tree(&d, files, dirs),
+            Err(err) => quit_err!("Error listing directory {}: {}", dir.to_data_uri(), err),
+        }
+    }
+}
+
+// The directory name to type for a path like "data/.my/dir" (maybe with a trailing slash), or
+//   None for a root like "data/.my" or "data"
+fn confirm_name(path: &str) -> Option<&str> {
+    let path = path.trim_end_matches('/');
+    match path.rsplitn(2, '/').next() {
+        Some(name) if path.matches('/').count() >= 2 && !name.is_empty() => Some(name),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_confirm_name() {
+        assert_eq!(confirm_name("data/.my/photos"), Some("photos"));
+        assert_eq!(confirm_name("data/.my/photos/"), Some("photos"));
+        assert_eq!(confirm_name("dropbox/a/b"), Some("b"));
+        assert_eq!(confirm_name("data/.my/"), None);
+        assert_eq!(confirm_name("data/.my"), None);
+        assert_eq!(confirm_name("data"), None);
+    }
 }
@@ -1,5 +1,5 @@
 use crate::config::Profile;
-use crate::CmdRunner;
+use crate::{data, CmdRunner};
 use algorithmia::data::HasDataPath;
 use algorithmia::Algorithmia;
 use docopt::Docopt;
@@ -11,7 +11,8 @@ static USAGE: &'static str = r##"Usage:
   Removes a directory from the Agorithmia Data API
 
   <data-dir>        Specifies the Algorithmia Data URI
-                    The 'data://' prefix is optional
+                    The 'data://' prefix is optional (paths without it are relative
+                      to the profile's base_data_path, if set)
 
   Options:
     -f, --force     Force deletion even directory has contents
@@ -26,6 +27,7 @@ struct Args {
 
 pub struct RmDir {
     client: Algorithmia,
+    base_path: Option<String>,
 }
 impl CmdRunner for RmDir {
     fn get_usage() -> &'static str {
@@ -37,7 +39,8 @@ impl CmdRunner for RmDir {
             .and_then(|d| d.argv(argv).decode())
             .unwrap_or_else(|e| e.exit());
 
-        self.delete_dir(&*args.arg_data_dir, args.flag_force);
+        let path = data::resolve_path(self.base_path.as_deref(), &args.arg_data_dir);
+        self.delete_dir(&path, args.flag_force);
     }
 }
 
@@ -45,6 +48,7 @@ impl RmDir {
     pub fn new(profile: Profile) -> Self {
         RmDir {
             client: profile.client(),
+            base_path: profile.base_data_path().map(String::from),
         }
     }
 
@@ -0,0 +1,184 @@
+use super::{acl, download};
+use crate::config::Profile;
+use crate::{data, CmdRunner};
+use algorithmia::data::{DataItem, HasDataPath};
+use algorithmia::Algorithmia;
+use chrono::{DateTime, Utc};
+use docopt::Docopt;
+use std::vec::IntoIter;
+
+static USAGE: &str = r##"Usage:
+  mia stat <data-path>
+
+  Show the attributes of a file or directory in the Algorithmia Data API without
+    downloading it
+
+  <data-path>   Specifies the Algorithmia Data URI
+                The 'data://' prefix is optional (paths without it are relative
+                  to the profile's base_data_path, if set)
+
+  For a file, prints its size, content type, last modified time, and ACL (the ACL of the
+    directory containing it). For a directory, prints how many files and subdirectories
+    it contains, and its ACL.
+
+  Examples:
+    mia stat data://.my/foo/x.json        Show the size and last modified time of x.json
+    mia stat .my/foo                      Show how many entries your 'foo' directory has
+"##;
+
+#[derive(RustcDecodable, Debug)]
+struct Args {
+    arg_data_path: String,
+}
+
+// The attributes of a data file or directory
+#[derive(Debug)]
+pub enum DataStat {
+    File {
+        uri: String,
+        size: u64,
+        content_type: Option<String>,
+        last_modified: DateTime<Utc>,
+        acl: Vec<String>,
+    },
+    Dir {
+        uri: String,
+        files: u64,
+        dirs: u64,
+        acl: Vec<String>,
+    },
+}
+
+pub struct Stat {
+    client: Algorithmia,
+    base_path: Option<String>,
+}
+
+impl CmdRunner for Stat {
+    fn get_usage() -> &'static str {
+        USAGE
+    }
+
+    fn cmd_main(&self, argv: IntoIter<String>) {
+        let args: Args = Docopt::new(USAGE)
+            .and_then(|d| d.argv(argv).decode())
+            .unwrap_or_else(|e| e.exit());
+
+        match self.run(&args.arg_data_path) {
+            Ok(stat) => print_stat(&stat),
+            Err(err) => quit_msg!("{}", err),
+        }
+    }
+}
+
+impl Stat {
+    pub fn new(profile: Profile) -> Self {
+        Stat {
+            client: profile.client(),
+            base_path: profile.base_data_path().map(String::from),
+        }
+    }
+
+    // Gets the attributes of a data path (resolved against the base data path)
+    pub fn run(&self, path: &str) -> Result<DataStat, String> {
+        let path = data::resolve_path(self.base_path.as_deref(), path);
+        let object = self.client.data(&path);
+        let uri = object.to_data_uri();
+        let stat_err = |err: &dyn std::fmt::Display| format!("Error getting {}: {}", uri, err);
+
+        // A HEAD request gets a file's metadata without its contents
+        let url = object.to_url().map_err(|err| stat_err(&err))?;
+        let response = object
+            .client()
+            .head(url)
+            .send()
+            .map_err(|err| stat_err(&err))?;
+        if !response.status().is_success() {
+            return Err(stat_err(&download::status_err(&response)));
+        }
+        match download::header(&response, "X-Data-Type").as_deref() {
+            Some("file") => {
+                let size = download::header(&response, "Content-Length")
+                    .and_then(|len| len.parse().ok())
+                    .unwrap_or(0);
+                let content_type = download::header(&response, "Content-Type");
+                let last_modified = download::last_modified(&response);
+                let acl = match object.parent() {
+                    Some(dir) => acl::get_acl(&dir).map_err(|err| stat_err(&err))?,
+                    None => Vec::new(),
+                };
+                Ok(DataStat::File {
+                    uri,
+                    size,
+                    content_type,
+                    last_modified,
+                    acl,
+                })
+            }
+            Some("directory") => {
+                let dir = self.client.dir(&path);
+                let (mut files, mut dirs) = (0, 0);
+                for entry in dir.list() {
+                    match entry.map_err(|err| stat_err(&err))? {
+                        DataItem::File(_) => files += 1,
+                        DataItem::Dir(_) => dirs += 1,
+                    }
+                }
+                let acl = acl::get_acl(&dir).map_err(|err| stat_err(&err))?;
+                Ok(DataStat::Dir {
+                    uri,
+                    files,
+                    dirs,
+                    acl,
+                })
+            }
+            Some(data_type) => Err(stat_err(&format!("unknown data type '{}'", data_type))),
+            None => Err(stat_err(&"API response missing data type")),
+        }
+    }
+}
+
+fn print_stat(stat: &DataStat) {
+    match stat {
+        DataStat::File {
+            uri,
+            size,
+            content_type,
+            last_modified,
+            acl,
+        } => {
+            println!("Path:           {}", uri);
+            println!("Type:           file");
+            println!(
+                "Size:           {} ({} bytes)",
+                data::size_with_suffix(*size),
+                size
+            );
+            println!(
+                "Content type:   {}",
+                content_type.as_deref().unwrap_or("(unknown)")
+            );
+            println!(
+                "Last modified:  {}",
+                last_modified.format("%Y-%m-%d %H:%M:%S UTC")
+            );
+            println!("ACL:            {}", acl::describe(acl));
+        }
+        DataStat::Dir {
+            uri,
+            files,
+            dirs,
+            acl,
+        } => {
+            println!("Path:           {}", uri);
+            println!("Type:           directory");
+            println!(
+                "Entries:        {} ({} files, {} directories)",
+                files + dirs,
+                files,
+                dirs
+            );
+            println!("ACL:            {}", acl::describe(acl));
+        }
+    }
+}
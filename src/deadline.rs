@@ -0,0 +1,75 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+// How long work in progress at the deadline may take to finish before the command exits anyway
+pub const GRACE_SECS: u64 = 5;
+
+// A wall-clock budget for a whole command (set with --deadline)
+//
+// A watchdog thread marks the deadline expired, after which producers and workers stop
+//   starting new work so the command can summarize what completed. Work already in
+//   progress is allowed to finish rather than being cut off midway, for up to GRACE_SECS
+//   when the command also calls exit_after
+#[derive(Clone)]
+pub struct Deadline {
+    secs: Option<u64>,
+    expired: Arc<AtomicBool>,
+    stopped_early: Arc<AtomicBool>,
+}
+
+impl Deadline {
+    // Starts the watchdog, if there is a deadline
+    pub fn start(secs: Option<u64>) -> Deadline {
+        let expired = Arc::new(AtomicBool::new(false));
+        if let Some(secs) = secs {
+            let watchdog = expired.clone();
+            thread::spawn(move || {
+                thread::sleep(Duration::from_secs(secs));
+                watchdog.store(true, Ordering::SeqCst);
+            });
+        }
+        Deadline {
+            secs,
+            expired,
+            stopped_early: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    // Checks whether to stop before starting more work, noting that work was left undone
+    pub fn should_stop(&self) -> bool {
+        let expired = self.expired.load(Ordering::SeqCst);
+        if expired {
+            self.stopped_early.store(true, Ordering::SeqCst);
+        }
+        expired
+    }
+
+    // Whether any work was skipped because the deadline expired
+    pub fn stopped_early(&self) -> bool {
+        self.stopped_early.load(Ordering::SeqCst)
+    }
+
+    pub fn secs(&self) -> u64 {
+        self.secs.unwrap_or(0)
+    }
+
+    // See exit_after (the function)
+    pub fn exit_after<F: FnOnce() + Send + 'static>(&self, on_overrun: F) {
+        exit_after(self.secs, on_overrun);
+    }
+}
+
+// Calls `on_overrun` (which should summarize and exit) if the command is still running
+//   GRACE_SECS after a deadline of `secs`, e.g. on a stalled transfer or while waiting for
+//   more STDIN, so that the deadline bounds the total runtime. Only for command line use,
+//   since it ends the process
+pub fn exit_after<F: FnOnce() + Send + 'static>(secs: Option<u64>, on_overrun: F) {
+    if let Some(secs) = secs {
+        thread::spawn(move || {
+            thread::sleep(Duration::from_secs(secs + GRACE_SECS));
+            on_overrun();
+        });
+    }
+}
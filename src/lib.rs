@@ -0,0 +1,149 @@
+// The operations behind the mia commands, for use as a library
+//   Each command runner (e.g. data::Cp) parses its command line arguments into options
+//   and calls a `run` method that other Rust tools can call directly
+
+#[macro_use]
+extern crate serde_derive;
+
+use isatty::stderr_isatty;
+use std::error::Error as StdError;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::vec::IntoIter;
+use termcolor::{Color, ColorChoice};
+
+pub(crate) type DynError = Box<dyn std::error::Error + 'static + Send + Sync>;
+
+pub(crate) const GRAY: Color = Color::Ansi256(8);
+pub(crate) const BRIGHT_RED: Color = Color::Ansi256(9);
+
+// Exit code when only some of a command's files or inputs failed (errors otherwise exit 1,
+//   and usage errors 2)
+pub const PARTIAL_FAILURE_CODE: i32 = 3;
+
+// Prints a notice to STDERR like eprintln!, also logging it at the given logging::Level
+macro_rules! notice {
+    ($level:ident, $($arg:tt)*) => ({
+        let msg = format!($($arg)*);
+        eprintln!("{}", msg);
+        crate::logging::record(crate::logging::Level::$level, &msg);
+    });
+}
+
+macro_rules! eprintln_red {
+    ($($arg:tt)*) => ({
+        use std::io::Write as _;
+        use termcolor::WriteColor as _;
+        let msg = format!($($arg)*);
+        let mut t_err = ::termcolor::StandardStream::stderr(crate::color_choice());
+        let _ = t_err.set_color(::termcolor::ColorSpec::new().set_fg(Some(crate::BRIGHT_RED)));
+        let _ = writeln!(t_err, "{}", msg);
+        let _ = t_err.reset();
+        crate::logging::record(crate::logging::Level::Error, &msg);
+    });
+}
+
+macro_rules! quit_msg {
+    ($($arg:tt)*) => ({
+        crate::exit_with(1, &format!($($arg)*))
+    });
+}
+
+// Exits with the given code after a final line explaining it (see exit_with)
+macro_rules! quit_code {
+    ($code:expr, $($arg:tt)*) => ({
+        crate::exit_with($code, &format!($($arg)*))
+    });
+}
+
+// Exits with the given code, ending with a line explaining it (so that the reason for a
+//   non-zero exit is the last thing in a CI log). Anything after the reason's first line
+//   (e.g. usage text) is printed before it
+pub(crate) fn exit_with(code: i32, reason: &str) -> ! {
+    let mut lines = reason.splitn(2, '\n');
+    let first = lines.next().unwrap_or_default();
+    if let Some(rest) = lines.next() {
+        eprintln!("{}", rest.trim_start_matches('\n'));
+    }
+    notice!(Error, "Exiting with code {}: {}", code, first);
+    std::process::exit(code)
+}
+
+// Exits with code 1 explained by an error, followed by what caused it
+pub(crate) fn exit_err(msg: &str, err: &dyn StdError) -> ! {
+    eprintln_red!("Exiting with code 1: {}", msg);
+    print_cause_chain(err);
+    std::process::exit(1)
+}
+
+fn print_cause_chain(e: &dyn StdError) {
+    let mut err = e;
+    while let Some(cause) = err.source() {
+        notice!(Error, "  caused by: {}", cause);
+        err = cause as &dyn StdError;
+    }
+}
+
+macro_rules! quit_err {
+    ($err:tt) => {{
+        crate::exit_err(&format!("{}", $err), &$err)
+    }};
+    ($fmt:expr, $err:tt) => {{
+        crate::exit_err(&format!($fmt, $err), &$err)
+    }};
+    ($fmt:expr, $arg:expr, $err:tt) => {{
+        crate::exit_err(&format!($fmt, $arg, $err), &$err)
+    }};
+}
+
+pub mod algo;
+pub mod auth;
+pub mod config;
+pub mod configure;
+pub mod data;
+pub mod deadline;
+pub mod logging;
+pub mod output;
+pub mod update;
+pub mod version; // Module is generated by cargo build script
+
+// Color set with --color or a profile's `color` (COLOR_AUTO until set)
+static COLOR: AtomicU8 = AtomicU8::new(COLOR_AUTO);
+const COLOR_AUTO: u8 = 0;
+const COLOR_ALWAYS: u8 = 1;
+const COLOR_NEVER: u8 = 2;
+
+// Sets whether output is colored: auto (when STDERR is a terminal), always, or never
+pub fn set_color(color: &str) -> Result<(), String> {
+    let color = match color {
+        "auto" => COLOR_AUTO,
+        "always" => COLOR_ALWAYS,
+        "never" => COLOR_NEVER,
+        other => {
+            return Err(format!(
+                "Unsupported color '{}'. Supported: auto, always, never",
+                other
+            ))
+        }
+    };
+    COLOR.store(color, Ordering::Relaxed);
+    Ok(())
+}
+
+pub fn color_choice() -> ColorChoice {
+    match COLOR.load(Ordering::Relaxed) {
+        COLOR_ALWAYS => ColorChoice::Always,
+        COLOR_NEVER => ColorChoice::Never,
+        _ if stderr_isatty() => ColorChoice::Auto,
+        _ => ColorChoice::Never,
+    }
+}
+
+pub trait CmdRunner {
+    fn cmd_main(&self, argv: IntoIter<String>);
+    fn get_usage() -> &'static str;
+
+    fn print_usage() -> ! {
+        println!("{}", Self::get_usage());
+        std::process::exit(0)
+    }
+}
@@ -0,0 +1,119 @@
+// Structured logging of the notices printed to STDERR (set up with --log and --log-level)
+//
+// Each notice at or above the log level is appended to the log file as a JSON line with its
+//   time, level, and message, for running commands under orchestration. What is printed to
+//   STDERR is unchanged, and nothing is logged unless a log file is set.
+
+use chrono::{SecondsFormat, Utc};
+use once_cell::sync::OnceCell;
+use rustc_serialize::json::Json;
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::sync::Mutex;
+
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub enum Level {
+    Error,
+    Warn,
+    Info,
+    Debug,
+}
+
+impl Level {
+    pub fn parse(level: &str) -> Result<Level, String> {
+        match level {
+            "error" => Ok(Level::Error),
+            "warn" => Ok(Level::Warn),
+            "info" => Ok(Level::Info),
+            "debug" => Ok(Level::Debug),
+            other => Err(format!(
+                "Unsupported log level '{}'. Supported: error, warn, info, debug",
+                other
+            )),
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Level::Error => "error",
+            Level::Warn => "warn",
+            Level::Info => "info",
+            Level::Debug => "debug",
+        }
+    }
+}
+
+struct Logger {
+    file: Mutex<File>,
+    level: Level,
+}
+
+static LOGGER: OnceCell<Logger> = OnceCell::new();
+
+// Appends notices at `level` or more severe to the file at `path`
+pub fn init(path: &str, level: Level) -> Result<(), String> {
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|err| format!("Error opening log file {}: {}", path, err))?;
+    let logger = Logger {
+        file: Mutex::new(file),
+        level,
+    };
+    LOGGER
+        .set(logger)
+        .map_err(|_| "The log file is already set".to_string())
+}
+
+// Logs a notice, if a log file is set and the notice is at or above its level
+pub fn record(level: Level, msg: &str) {
+    let logger = match LOGGER.get() {
+        Some(logger) if level <= logger.level => logger,
+        _ => return,
+    };
+    let line = format_record(
+        level,
+        msg,
+        &Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+    );
+    // Each record is written whole, so records logged by worker threads don't interleave
+    if let Ok(mut file) = logger.file.lock() {
+        let _ = writeln!(file, "{}", line);
+    }
+}
+
+fn format_record(level: Level, msg: &str, time: &str) -> String {
+    let mut record = BTreeMap::new();
+    record.insert("time".to_string(), Json::String(time.to_string()));
+    record.insert(
+        "level".to_string(),
+        Json::String(level.as_str().to_string()),
+    );
+    record.insert(
+        "message".to_string(),
+        Json::String(msg.trim_end().to_string()),
+    );
+    Json::Object(record).to_string()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_format_record() {
+        let record = format_record(
+            Level::Warn,
+            "short read of \"x\"\n",
+            "2024-05-01T12:00:00.000Z",
+        );
+        assert_eq!(
+            record,
+            r#"{"level":"warn","message":"short read of \"x\"","time":"2024-05-01T12:00:00.000Z"}"#
+        );
+        assert!(Level::Error < Level::Debug);
+        assert!(Level::parse("verbose").is_err());
+    }
+}
@@ -0,0 +1,140 @@
+use std::io::{self, Write};
+
+// Output formats for command results (selected with --format)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OutputFormat {
+    // Human-friendly output (the default)
+    Text,
+    // KEY=value lines that can be sourced by a shell
+    Env,
+    // A JSON array
+    Json,
+    // One JSON value per line (NDJSON), written as results arrive
+    Jsonl,
+}
+
+impl OutputFormat {
+    // Parses --format, quitting if it isn't one of the formats the command supports
+    pub fn parse(format: Option<&str>, supported: &[OutputFormat]) -> OutputFormat {
+        let parsed = format.map_or(Some(OutputFormat::Text), OutputFormat::from_name);
+        match parsed {
+            Some(parsed) if supported.contains(&parsed) => parsed,
+            _ => {
+                let names: Vec<&str> = supported.iter().map(|f| f.name()).collect();
+                quit_msg!(
+                    "Unsupported format '{}'. Supported: {}",
+                    format.unwrap_or(""),
+                    names.join(", ")
+                )
+            }
+        }
+    }
+
+    // Picks the format for a command: --format if given, else the profile's output_format
+    //   if the command supports it, else text
+    pub fn resolve(
+        format: Option<&str>,
+        profile_format: Option<&str>,
+        supported: &[OutputFormat],
+    ) -> OutputFormat {
+        if format.is_some() {
+            return OutputFormat::parse(format, supported);
+        }
+        match profile_format.map(OutputFormat::from_name) {
+            Some(Some(format)) if supported.contains(&format) => format,
+            Some(None) => quit_msg!(
+                "Unsupported output_format '{}' in config. Supported: text, env, json, jsonl",
+                profile_format.unwrap_or("")
+            ),
+            _ => OutputFormat::Text,
+        }
+    }
+
+    fn from_name(name: &str) -> Option<OutputFormat> {
+        match name {
+            "text" => Some(OutputFormat::Text),
+            "env" => Some(OutputFormat::Env),
+            "json" => Some(OutputFormat::Json),
+            "jsonl" => Some(OutputFormat::Jsonl),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            OutputFormat::Text => "text",
+            OutputFormat::Env => "env",
+            OutputFormat::Json => "json",
+            OutputFormat::Jsonl => "jsonl",
+        }
+    }
+}
+
+// Writes each variable as a KEY=value line with the value shell-escaped
+pub fn write_env<W: Write>(out: &mut W, vars: &[(String, String)]) -> io::Result<()> {
+    for (key, value) in vars {
+        writeln!(out, "{}={}", key, shell_quote(value))?;
+    }
+    Ok(())
+}
+
+pub fn print_env(vars: &[(String, String)]) {
+    let stdout = io::stdout();
+    let written = write_env(&mut stdout.lock(), vars);
+    if let Err(err) = written {
+        quit_err!("Error writing output: {}", err);
+    }
+}
+
+// Quotes a value so that a POSIX shell reads it back literally
+//   Values with only safe characters are left as-is, otherwise they are single-quoted
+//   (with each embedded single quote written as '\'')
+pub fn shell_quote(value: &str) -> String {
+    let is_safe = |c: char| c.is_ascii_alphanumeric() || "_-.,:/@%+=".contains(c);
+    if !value.is_empty() && value.chars().all(is_safe) {
+        value.to_string()
+    } else {
+        format!("'{}'", value.replace('\'', r"'\''"))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_shell_quote() {
+        assert_eq!(shell_quote("1.23"), "1.23");
+        assert_eq!(shell_quote("data://.my/foo"), "data://.my/foo");
+        assert_eq!(shell_quote(""), "''");
+        assert_eq!(shell_quote("hello world"), "'hello world'");
+        assert_eq!(shell_quote("$(rm -rf /)"), "'$(rm -rf /)'");
+        assert_eq!(shell_quote("it's"), r"'it'\''s'");
+    }
+
+    #[test]
+    fn test_resolve() {
+        let supported = &[OutputFormat::Text, OutputFormat::Env];
+        let resolve =
+            |format, profile_format| OutputFormat::resolve(format, profile_format, supported);
+        assert_eq!(resolve(None, None), OutputFormat::Text);
+        assert_eq!(resolve(None, Some("env")), OutputFormat::Env);
+        assert_eq!(resolve(Some("text"), Some("env")), OutputFormat::Text);
+        // A profile's format that the command doesn't support falls back to text
+        assert_eq!(resolve(None, Some("json")), OutputFormat::Text);
+    }
+
+    #[test]
+    fn test_write_env() {
+        let mut out = Vec::new();
+        let vars = vec![
+            ("ALGO_DURATION".to_string(), "1.23".to_string()),
+            ("ALGO_RESULT".to_string(), "a\nb".to_string()),
+        ];
+        write_env(&mut out, &vars).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "ALGO_DURATION=1.23\nALGO_RESULT='a\nb'\n"
+        );
+    }
+}
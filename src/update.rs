@@ -0,0 +1,128 @@
+use crate::config;
+use crate::version;
+use isatty::stdout_isatty;
+use rustc_serialize::json::Json;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::PathBuf;
+use std::time::Duration;
+use std::{env, thread};
+
+static RELEASES_URL: &str =
+    "https://api.github.com/repos/algorithmiaio/algorithmia_cli/releases/latest";
+static RELEASES_PAGE: &str = "https://github.com/algorithmiaio/algorithmia_cli/releases";
+
+// Both the release check and the notice happen at most once per day
+const CHECK_INTERVAL_SECS: i64 = 24 * 60 * 60;
+
+#[derive(Default, Deserialize, Serialize)]
+struct UpdateCache {
+    checked_at: i64,
+    notified_at: i64,
+    latest: Option<String>,
+}
+
+// Prints a notice to STDERR if a newer release was found by a previous check, and starts a
+//   background check if the cached result is stale. It never blocks the command or writes to
+//   STDOUT, and is skipped when ALGORITHMIA_NO_UPDATE_CHECK or CI is set or STDOUT isn't a TTY
+pub fn check() {
+    if env::var_os("ALGORITHMIA_NO_UPDATE_CHECK").is_some()
+        || env::var_os("CI").is_some()
+        || !stdout_isatty()
+    {
+        return;
+    }
+
+    let path = cache_path();
+    let mut cache = read_cache(&path);
+    let now = chrono::Utc::now().timestamp();
+
+    if let Some(ref latest) = cache.latest {
+        if is_newer(latest, version::PKG_VERSION) && now - cache.notified_at >= CHECK_INTERVAL_SECS
+        {
+            notice!(
+                Info,
+                "mia {} is available (you have {}): {}",
+                latest,
+                version::PKG_VERSION,
+                RELEASES_PAGE
+            );
+            cache.notified_at = now;
+            write_cache(&path, &cache);
+        }
+    }
+
+    if now - cache.checked_at >= CHECK_INTERVAL_SECS {
+        // Only a finished check is recorded, since the thread dies with a command that exits
+        //   before the fetch completes (and the next command then tries again)
+        thread::spawn(move || {
+            if let Some(latest) = fetch_latest() {
+                cache.checked_at = now;
+                cache.latest = Some(latest);
+                write_cache(&path, &cache);
+            }
+        });
+    }
+}
+
+fn cache_path() -> PathBuf {
+    config::get_config_path().with_file_name("update-check")
+}
+
+fn read_cache(path: &PathBuf) -> UpdateCache {
+    let mut contents = String::new();
+    File::open(path)
+        .and_then(|mut f| f.read_to_string(&mut contents))
+        .ok()
+        .and_then(|_| toml::from_str(&contents).ok())
+        .unwrap_or_default()
+}
+
+// Failing to write the cache only means checking again next time
+fn write_cache(path: &PathBuf, cache: &UpdateCache) {
+    if let Ok(contents) = toml::to_string(cache) {
+        let _ = fs::write(path, contents);
+    }
+}
+
+// Fetches the version of the latest release (without its 'v' prefix)
+fn fetch_latest() -> Option<String> {
+    let client = reqwest::blocking::Client::builder()
+        .timeout(Duration::from_secs(10))
+        .user_agent(format!("mia/{}", version::PKG_VERSION))
+        .build()
+        .ok()?;
+    let body = client
+        .get(RELEASES_URL)
+        .send()
+        .and_then(|response| response.error_for_status())
+        .and_then(|response| response.text())
+        .ok()?;
+    let release = Json::from_str(&body).ok()?;
+    let tag = release.find("tag_name")?.as_string()?;
+    Some(tag.trim_start_matches('v').to_string())
+}
+
+// Compares dotted numeric versions (e.g. "1.0.10" is newer than "1.0.2")
+fn is_newer(candidate: &str, current: &str) -> bool {
+    let parse = |v: &str| -> Option<Vec<u64>> { v.split('.').map(|n| n.parse().ok()).collect() };
+    match (parse(candidate), parse(current)) {
+        (Some(candidate), Some(current)) => candidate > current,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_is_newer() {
+        assert!(is_newer("1.0.3", "1.0.2"));
+        assert!(is_newer("1.0.10", "1.0.2"));
+        assert!(is_newer("2.0", "1.9.9"));
+        assert!(!is_newer("1.0.2", "1.0.2"));
+        assert!(!is_newer("1.0.1", "1.0.2"));
+        assert!(!is_newer("1.1.0-beta", "1.0.2"));
+    }
+}
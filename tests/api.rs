@@ -0,0 +1,592 @@
+// Tests cp, cat, stat, and run through their library functions against a mock API (see support)
+
+mod support;
+
+use algorithmia::algo::AlgoOptions;
+use chrono::{TimeZone, Utc};
+use mia::algo::{InputData, Run};
+use mia::data::{
+    ArchiveFormat, Cat, CatOptions, Cp, CpOptions, DataStat, Span, Stat, TransferEvent,
+    TransferStatus,
+};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use support::MockApi;
+
+// A fresh local directory for a test's files
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("mia-test-{}-{}", std::process::id(), name));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn local_files(dir: &Path, files: &[(&str, &str)]) -> Vec<String> {
+    files
+        .iter()
+        .map(|(name, contents)| {
+            let path = dir.join(name);
+            fs::write(&path, contents).unwrap();
+            path.display().to_string()
+        })
+        .collect()
+}
+
+#[test]
+fn test_upload_files() {
+    let api = MockApi::start();
+    api.add_dir("data://.my/photos");
+    let dir = temp_dir("upload");
+    let sources = local_files(&dir, &[("a.txt", "alpha"), ("b.txt", "bravo!")]);
+
+    let cp = Cp::new(api.profile());
+    let summary = cp
+        .run(sources, "data://.my/photos", CpOptions::default())
+        .unwrap();
+    assert_eq!(
+        (summary.uploaded, summary.failed, summary.bytes),
+        (2, 0, 11)
+    );
+    assert_eq!(api.file("data://.my/photos/a.txt"), Some(b"alpha".to_vec()));
+    assert_eq!(
+        api.file("data://.my/photos/b.txt"),
+        Some(b"bravo!".to_vec())
+    );
+}
+
+#[test]
+fn test_upload_etag() {
+    let api = MockApi::start();
+    let dir = temp_dir("upload-etag");
+    let sources = local_files(&dir, &[("a.txt", "alpha")]);
+
+    let etags = Arc::new(Mutex::new(Vec::new()));
+    let recorded = etags.clone();
+    let opts = CpOptions {
+        on_event: Some(Arc::new(move |event: &TransferEvent| {
+            recorded.lock().unwrap().push(event.etag.clone());
+        })),
+        ..CpOptions::default()
+    };
+    Cp::new(api.profile())
+        .run(sources, "data://.my/a.txt", opts)
+        .unwrap();
+    assert_eq!(*etags.lock().unwrap(), vec![Some("\"v1\"".to_string())]);
+}
+
+#[test]
+fn test_upload_files_to_new_dir() {
+    let api = MockApi::start();
+    let dir = temp_dir("upload-new-dir");
+    let sources = local_files(&dir, &[("a.txt", "alpha"), ("b.txt", "bravo!")]);
+
+    // Several files uploaded to a new dest are uploaded into it as a directory
+    let summary = Cp::new(api.profile())
+        .run(sources, "data://.my/new", CpOptions::default())
+        .unwrap();
+    assert_eq!((summary.uploaded, summary.failed), (2, 0));
+    assert!(api.has_dir("data://.my/new"));
+    assert_eq!(api.file("data://.my/new/a.txt"), Some(b"alpha".to_vec()));
+}
+
+#[test]
+fn test_upload_recursive_creates_dirs() {
+    let api = MockApi::start();
+    let dir = temp_dir("recursive");
+    fs::create_dir_all(dir.join("proj/sub")).unwrap();
+    fs::write(dir.join("proj/main.rs"), "fn main() {}").unwrap();
+    fs::write(dir.join("proj/sub/notes.log"), "skipped").unwrap();
+    fs::write(dir.join("proj/sub/lib.rs"), "").unwrap();
+
+    let opts = CpOptions {
+        recursive: true,
+        exclude: vec!["*.log".to_string()],
+        ..CpOptions::default()
+    };
+    let source = dir.join("proj").display().to_string();
+    let summary = Cp::new(api.profile())
+        .run(vec![source], "data://.my/proj", opts)
+        .unwrap();
+    assert_eq!((summary.uploaded, summary.failed), (2, 0));
+    assert!(api.has_dir("data://.my/proj/sub"));
+    assert!(api.file("data://.my/proj/sub/lib.rs").is_some());
+    assert!(api.file("data://.my/proj/sub/notes.log").is_none());
+}
+
+#[test]
+fn test_upload_recursive_ignore_files_are_scoped() {
+    let api = MockApi::start();
+    api.add_dir("data://.my/dest");
+    let dir = temp_dir("recursive-ignore");
+    fs::create_dir_all(dir.join("a/sub")).unwrap();
+    fs::create_dir_all(dir.join("b/sub")).unwrap();
+    fs::write(dir.join("a/.algoignore"), "*.tmp\n").unwrap();
+    fs::write(dir.join("a/keep.txt"), "kept").unwrap();
+    fs::write(dir.join("a/junk.tmp"), "skipped").unwrap();
+    fs::write(dir.join("a/sub/.algoignore"), "notes.txt\n").unwrap();
+    fs::write(dir.join("a/sub/notes.txt"), "skipped").unwrap();
+    fs::write(dir.join("b/x.tmp"), "kept").unwrap();
+    fs::write(dir.join("b/sub/notes.txt"), "kept").unwrap();
+
+    // Only b has no .algoignore, so all of its files are uploaded
+    let opts = CpOptions {
+        recursive: true,
+        ..CpOptions::default()
+    };
+    let sources = vec![
+        dir.join("a").display().to_string(),
+        dir.join("b").display().to_string(),
+    ];
+    let summary = Cp::new(api.profile())
+        .run(sources, "data://.my/dest", opts)
+        .unwrap();
+    assert_eq!((summary.uploaded, summary.failed), (5, 0));
+    assert!(api.file("data://.my/dest/a/junk.tmp").is_none());
+    assert!(api.file("data://.my/dest/a/sub/notes.txt").is_none());
+    assert_eq!(api.file("data://.my/dest/b/x.tmp"), Some(b"kept".to_vec()));
+    assert!(api.file("data://.my/dest/b/sub/notes.txt").is_some());
+}
+
+#[test]
+fn test_upload_recursive_empty_dirs() {
+    let api = MockApi::start();
+    let dir = temp_dir("recursive-empty");
+    fs::create_dir_all(dir.join("proj/empty/nested")).unwrap();
+    fs::create_dir_all(dir.join("proj/logs")).unwrap();
+    fs::write(dir.join("proj/logs/run.log"), "skipped").unwrap();
+    fs::write(dir.join("proj/zero.txt"), "").unwrap();
+    let source = dir.join("proj").display().to_string();
+
+    let opts = CpOptions {
+        recursive: true,
+        exclude: vec!["*.log".to_string()],
+        ..CpOptions::default()
+    };
+    let summary = Cp::new(api.profile())
+        .run(vec![source.clone()], "data://.my/proj", opts)
+        .unwrap();
+    assert_eq!((summary.uploaded, summary.failed, summary.bytes), (1, 0, 0));
+    assert_eq!(api.file("data://.my/proj/zero.txt"), Some(Vec::new()));
+    assert!(!api.has_dir("data://.my/proj/empty"));
+    assert!(!api.has_dir("data://.my/proj/logs"));
+
+    let opts = CpOptions {
+        recursive: true,
+        exclude: vec!["*.log".to_string()],
+        create_empty_dirs: true,
+        ..CpOptions::default()
+    };
+    Cp::new(api.profile())
+        .run(vec![source], "data://.my/copy", opts)
+        .unwrap();
+    assert!(api.has_dir("data://.my/copy/empty/nested"));
+    assert!(api.has_dir("data://.my/copy/logs"));
+}
+
+#[test]
+fn test_upload_archive() {
+    let api = MockApi::start();
+    api.add_dir("data://.my/inputs");
+    let dir = temp_dir("archive");
+    fs::create_dir_all(dir.join("proj/sub")).unwrap();
+    fs::write(dir.join("proj/sub/a.txt"), "alpha").unwrap();
+    fs::write(dir.join("proj/run.log"), "skipped").unwrap();
+    let source = dir.join("proj").display().to_string();
+
+    let opts = CpOptions {
+        archive: Some(ArchiveFormat::Tar),
+        exclude: vec!["*.log".to_string()],
+        ..CpOptions::default()
+    };
+    let summary = Cp::new(api.profile())
+        .run(vec![source], "data://.my/inputs", opts)
+        .unwrap();
+    assert_eq!((summary.uploaded, summary.failed), (1, 0));
+
+    // A header block for "sub/" and another for "sub/a.txt" with its data block, then the end
+    let tar = api.file("data://.my/inputs/proj.tar").unwrap();
+    assert_eq!(tar.len(), 512 * 5);
+    assert_eq!(&tar[..4], b"sub/");
+    assert_eq!(&tar[512..521], b"sub/a.txt");
+    assert_eq!(&tar[1024..1029], b"alpha");
+    assert!(!tar.windows(7).any(|w| w == b"run.log"));
+}
+
+#[test]
+fn test_upload_failure_is_counted() {
+    let api = MockApi::start();
+    let dir = temp_dir("upload-failure");
+    let sources = local_files(&dir, &[("a.txt", "alpha")]);
+
+    // The parent directory doesn't exist (and isn't created), so the Data API rejects the upload
+    let opts = CpOptions {
+        create_dirs: false,
+        ..CpOptions::default()
+    };
+    let summary = Cp::new(api.profile())
+        .run(sources, "data://.my/missing/a.txt", opts)
+        .unwrap();
+    assert_eq!((summary.uploaded, summary.failed), (0, 1));
+    assert_eq!(summary.by_source[0].failed, 1);
+}
+
+#[test]
+fn test_upload_creates_parent_dirs() {
+    let api = MockApi::start();
+    let dir = temp_dir("upload-parents");
+    let sources = local_files(&dir, &[("a.txt", "alpha")]);
+
+    let summary = Cp::new(api.profile())
+        .run(sources, "data://.my/foo/bar/baz.txt", CpOptions::default())
+        .unwrap();
+    assert_eq!((summary.uploaded, summary.failed), (1, 0));
+    assert!(api.has_dir("data://.my/foo"));
+    assert!(api.has_dir("data://.my/foo/bar"));
+    assert_eq!(api.file("data://.my/foo/bar/baz.txt").unwrap(), b"alpha");
+}
+
+#[test]
+fn test_concurrent_uploads_are_limited() {
+    let api = MockApi::start();
+    api.add_dir("data://.my/many");
+    api.set_upload_delay(Duration::from_millis(100));
+    let dir = temp_dir("concurrent");
+    let names: Vec<_> = (0..6).map(|i| format!("{}.txt", i)).collect();
+    let files: Vec<_> = names.iter().map(|name| (name.as_str(), "data")).collect();
+    let sources = local_files(&dir, &files);
+
+    let opts = CpOptions {
+        concurrency: 3,
+        ..CpOptions::default()
+    };
+    let summary = Cp::new(api.profile())
+        .run(sources, "data://.my/many", opts)
+        .unwrap();
+    assert_eq!(summary.uploaded, 6);
+    let max = api.max_uploads_in_flight();
+    assert!(
+        max > 1 && max <= 3,
+        "{} uploads were in flight at once",
+        max
+    );
+}
+
+#[test]
+fn test_concurrency_per_host() {
+    let api = MockApi::start();
+    api.add_dir("data://.my/many");
+    api.set_upload_delay(Duration::from_millis(50));
+    let dir = temp_dir("per-host");
+    let sources = local_files(&dir, &[("a.txt", "a"), ("b.txt", "b"), ("c.txt", "c")]);
+
+    // Every upload goes to the same host
+    let opts = CpOptions {
+        concurrency: 3,
+        concurrency_per_host: Some(1),
+        ..CpOptions::default()
+    };
+    let summary = Cp::new(api.profile())
+        .run(sources, "data://.my/many", opts)
+        .unwrap();
+    assert_eq!(summary.uploaded, 3);
+    assert_eq!(api.max_uploads_in_flight(), 1);
+}
+
+#[test]
+fn test_download_files() {
+    let api = MockApi::start();
+    api.add_file("data://.my/in/a.txt", b"alpha", Utc::now());
+    api.add_file("data://.my/in/b.txt", b"bravo!", Utc::now());
+    let dir = temp_dir("download");
+
+    let sources = vec![
+        "data://.my/in/a.txt".to_string(),
+        "data://.my/in/b.txt".to_string(),
+        "data://.my/in/missing.txt".to_string(),
+    ];
+    let dest = dir.display().to_string();
+    let summary = Cp::new(api.profile())
+        .run(sources, &dest, CpOptions::default())
+        .unwrap();
+    assert_eq!((summary.downloaded, summary.failed), (2, 1));
+    assert_eq!(summary.bytes, 11);
+    assert_eq!(fs::read(dir.join("b.txt")).unwrap(), b"bravo!");
+    assert!(!dir.join("missing.txt").exists());
+}
+
+#[test]
+fn test_transfer_events() {
+    let api = MockApi::start();
+    api.add_file(
+        "data://.my/in/old.txt",
+        b"old",
+        Utc.ymd(2020, 1, 1).and_hms(0, 0, 0),
+    );
+    api.add_file("data://.my/in/new.txt", b"new!", Utc::now());
+    let dir = temp_dir("events");
+
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let recorded = events.clone();
+    let opts = CpOptions {
+        concurrency: 1,
+        on_event: Some(Arc::new(move |event: &TransferEvent| {
+            let status = match event.status {
+                TransferStatus::Failed(_) => "failed",
+                TransferStatus::Skipped => "skipped",
+                _ => "downloaded",
+            };
+            let mut events = recorded.lock().unwrap();
+            events.push((event.path.clone(), event.bytes, status));
+        })),
+        newer_than: Some(Utc.ymd(2021, 1, 1).and_hms(0, 0, 0)),
+        ..CpOptions::default()
+    };
+    let sources = vec![
+        "data://.my/in/old.txt".to_string(),
+        "data://.my/in/new.txt".to_string(),
+        "data://.my/in/missing.txt".to_string(),
+    ];
+    let dest = dir.display().to_string();
+    Cp::new(api.profile()).run(sources, &dest, opts).unwrap();
+
+    let events = events.lock().unwrap();
+    assert_eq!(
+        *events,
+        vec![
+            ("data://.my/in/old.txt".to_string(), 0, "skipped"),
+            ("data://.my/in/new.txt".to_string(), 4, "downloaded"),
+            ("data://.my/in/missing.txt".to_string(), 0, "failed"),
+        ]
+    );
+}
+
+#[test]
+fn test_download_duplicate_targets() {
+    let api = MockApi::start();
+    api.add_file("data://.my/x/data.json", b"1", Utc::now());
+    api.add_file("data://.my/y/data.json", b"2", Utc::now());
+    let dir = temp_dir("duplicates");
+    let sources = vec![
+        "data://.my/x/data.json".to_string(),
+        "data://.my/y/data.json".to_string(),
+    ];
+    let dest = dir.display().to_string();
+    let cp = Cp::new(api.profile());
+
+    let err = cp
+        .run(sources.clone(), &dest, CpOptions::default())
+        .unwrap_err();
+    assert!(err.contains("both download to"), "{}", err);
+
+    let opts = CpOptions {
+        rename_dupes: true,
+        ..CpOptions::default()
+    };
+    assert_eq!(cp.run(sources, &dest, opts).unwrap().downloaded, 2);
+    assert_eq!(fs::read(dir.join("data-1.json")).unwrap(), b"2");
+}
+
+#[test]
+fn test_download_newer_than() {
+    let api = MockApi::start();
+    api.add_file(
+        "data://.my/in/old.txt",
+        b"old",
+        Utc.ymd(2020, 1, 1).and_hms(0, 0, 0),
+    );
+    api.add_file("data://.my/in/new.txt", b"new", Utc::now());
+    let dir = temp_dir("newer-than");
+
+    let opts = CpOptions {
+        newer_than: Some(Utc.ymd(2021, 1, 1).and_hms(0, 0, 0)),
+        ..CpOptions::default()
+    };
+    let sources = vec![
+        "data://.my/in/old.txt".to_string(),
+        "data://.my/in/new.txt".to_string(),
+    ];
+    let dest = dir.display().to_string();
+    let summary = Cp::new(api.profile()).run(sources, &dest, opts).unwrap();
+    assert_eq!((summary.downloaded, summary.skipped), (1, 1));
+    assert!(!dir.join("old.txt").exists());
+}
+
+#[test]
+fn test_cat() {
+    let api = MockApi::start();
+    api.add_file("data://.my/log.txt", b"one\ntwo\nthree\n", Utc::now());
+    let cat = Cat::new(api.profile());
+
+    let mut out = Vec::new();
+    cat.run("data://.my/log.txt", &CatOptions::default(), &mut out)
+        .unwrap();
+    assert_eq!(out, b"one\ntwo\nthree\n");
+
+    let opts = CatOptions {
+        span: Span::Tail(6),
+        ..CatOptions::default()
+    };
+    let mut out = Vec::new();
+    cat.run("data://.my/log.txt", &opts, &mut out).unwrap();
+    assert_eq!(out, b"three\n");
+
+    let opts = CatOptions {
+        span: Span::Lines(2, Some(2)),
+        ..CatOptions::default()
+    };
+    let mut out = Vec::new();
+    cat.run("data://.my/log.txt", &opts, &mut out).unwrap();
+    assert_eq!(out, b"two\n");
+
+    let err = cat
+        .run(
+            "data://.my/missing.txt",
+            &CatOptions::default(),
+            &mut Vec::new(),
+        )
+        .unwrap_err();
+    assert!(
+        err.starts_with("Error downloading data://.my/missing.txt"),
+        "{}",
+        err
+    );
+}
+
+// "hello\n" as compressed by `gzip`
+static HELLO_GZIP: &[u8] = &[
+    0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x03, 0xcb, 0x48, 0xcd, 0xc9, 0xc9, 0xe7,
+    0x02, 0x00, 0x20, 0x30, 0x3a, 0x36, 0x06, 0x00, 0x00, 0x00,
+];
+
+#[test]
+fn test_content_encoding() {
+    let api = MockApi::start();
+    api.add_encoded_file("data://.my/hello.txt", HELLO_GZIP, "gzip");
+    api.add_encoded_file("data://.my/hello.br", b"?", "br");
+    let cat = Cat::new(api.profile());
+
+    let mut out = Vec::new();
+    cat.run("data://.my/hello.txt", &CatOptions::default(), &mut out)
+        .unwrap();
+    assert_eq!(out, b"hello\n");
+
+    let opts = CatOptions {
+        raw: true,
+        ..CatOptions::default()
+    };
+    let mut out = Vec::new();
+    cat.run("data://.my/hello.txt", &opts, &mut out).unwrap();
+    assert_eq!(out, HELLO_GZIP);
+
+    let opts = CatOptions {
+        span: Span::Head(4),
+        ..CatOptions::default()
+    };
+    let mut out = Vec::new();
+    cat.run("data://.my/hello.txt", &opts, &mut out).unwrap();
+    assert_eq!(out, b"hell");
+
+    let opts = CatOptions {
+        span: Span::Tail(4),
+        ..CatOptions::default()
+    };
+    let err = cat
+        .run("data://.my/hello.txt", &opts, &mut Vec::new())
+        .unwrap_err();
+    assert!(err.contains("use --raw"), "{}", err);
+
+    let err = cat
+        .run(
+            "data://.my/hello.br",
+            &CatOptions::default(),
+            &mut Vec::new(),
+        )
+        .unwrap_err();
+    assert!(err.contains("Unsupported Content-Encoding 'br'"), "{}", err);
+
+    let dir = temp_dir("content-encoding");
+    let dest = dir.display().to_string();
+    let sources = vec!["data://.my/hello.txt".to_string()];
+    let summary = Cp::new(api.profile())
+        .run(sources, &dest, CpOptions::default())
+        .unwrap();
+    assert_eq!((summary.downloaded, summary.bytes), (1, 6));
+    assert_eq!(fs::read(dir.join("hello.txt")).unwrap(), b"hello\n");
+}
+
+#[test]
+fn test_stat() {
+    let api = MockApi::start();
+    let modified = Utc.ymd(2024, 5, 1).and_hms(12, 0, 0);
+    api.add_file("data://.my/foo/a.txt", b"hello", modified);
+    api.add_file("data://.my/foo/b.txt", b"", modified);
+    api.add_dir("data://.my/foo");
+    api.add_dir("data://.my/foo/sub");
+    api.add_file("data://.my/foo/sub/c.txt", b"nested", modified);
+    let stat = Stat::new(api.profile());
+
+    match stat.run("data://.my/foo/a.txt").unwrap() {
+        DataStat::File {
+            uri,
+            size,
+            last_modified,
+            acl,
+            ..
+        } => {
+            assert_eq!(uri, "data://.my/foo/a.txt");
+            assert_eq!(size, 5);
+            assert_eq!(last_modified, modified);
+            assert!(acl.is_empty());
+        }
+        other => panic!("Expected a file, received {:?}", other),
+    }
+
+    match stat.run("data://.my/foo").unwrap() {
+        DataStat::Dir { files, dirs, .. } => assert_eq!((files, dirs), (2, 1)),
+        other => panic!("Expected a directory, received {:?}", other),
+    }
+
+    let err = stat.run("data://.my/missing.txt").unwrap_err();
+    assert!(
+        err.starts_with("Error getting data://.my/missing.txt: 404"),
+        "{}",
+        err
+    );
+}
+
+#[test]
+fn test_run() {
+    let api = MockApi::start();
+    let run = Run::new(api.profile());
+
+    let json = InputData::Json(r#"{"n":[1,2]}"#.to_string());
+    let result = run.run("demo/echo", json, AlgoOptions::default()).unwrap();
+    assert_eq!(result.content_type, "json");
+    assert_eq!(result.result.to_json().unwrap(), r#"{"n":[1,2]}"#);
+    assert!((result.duration - 0.5).abs() < f32::EPSILON);
+
+    let text = InputData::Text("hello".to_string());
+    let result = run
+        .run("demo/echo/1.0.0", text, AlgoOptions::default())
+        .unwrap();
+    assert_eq!(result.content_type, "text");
+    assert_eq!(result.result.as_string(), Some("hello"));
+
+    let binary = InputData::Binary(vec![0, 159, 146, 150]);
+    let result = run
+        .run("demo/echo", binary, AlgoOptions::default())
+        .unwrap();
+    assert_eq!(result.result.as_bytes(), Some(&[0, 159, 146, 150][..]));
+}
+
+#[test]
+fn test_run_api_error() {
+    let api = MockApi::start();
+    let input = InputData::Text("hello".to_string());
+    let err = Run::new(api.profile())
+        .run("demo/fail", input, AlgoOptions::default())
+        .unwrap_err();
+    assert_eq!(err, "API error: algorithm failed");
+}
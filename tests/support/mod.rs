@@ -0,0 +1,392 @@
+// An in-memory stand-in for the Algorithmia Data and Algo APIs, served over HTTP on a local
+//   port so that cp, cat, and run can be tested end to end through the library functions
+//
+// Data API: files and directories are keyed by their connector path (e.g. "data/.my/foo.txt"),
+//   and "data/.my" always exists. Files are sent with a Date header of when they were written,
+//   and directories are listed in a single page without ACLs.
+// Algo API: any algorithm echoes its input as its result, except algorithms named "fail",
+//   which respond with an API error.
+
+use chrono::{DateTime, Utc};
+use mia::config::Profile;
+use rustc_serialize::base64::{self, ToBase64};
+use rustc_serialize::json::Json;
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use url::Url;
+
+pub struct MockApi {
+    addr: SocketAddr,
+    state: Arc<Mutex<State>>,
+}
+
+#[derive(Default)]
+struct State {
+    files: BTreeMap<String, (Vec<u8>, DateTime<Utc>)>,
+    // The Content-Encoding files are sent with, if any
+    encodings: BTreeMap<String, String>,
+    dirs: BTreeSet<String>,
+    // How long each file upload takes, to observe concurrent uploads
+    upload_delay: Duration,
+    uploads_in_flight: usize,
+    max_uploads_in_flight: usize,
+    // Uploads so far, to give each uploaded file a new ETag
+    uploads: usize,
+}
+
+struct Request {
+    method: String,
+    path: String,
+    headers: BTreeMap<String, String>,
+    body: Vec<u8>,
+}
+
+struct Response {
+    status: &'static str,
+    headers: Vec<(&'static str, String)>,
+    body: Vec<u8>,
+}
+
+impl MockApi {
+    pub fn start() -> MockApi {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("mock API can bind a local port");
+        let addr = listener.local_addr().unwrap();
+        let mut state = State::default();
+        state.dirs.insert("data/.my".to_string());
+        let state = Arc::new(Mutex::new(state));
+
+        let server_state = state.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let conn_state = server_state.clone();
+                thread::spawn(move || serve(stream, &conn_state));
+            }
+        });
+        MockApi { addr, state }
+    }
+
+    // A profile whose client sends requests to this mock
+    pub fn profile(&self) -> Profile {
+        let url = Url::parse(&format!("http://{}", self.addr)).unwrap();
+        Profile::new("simMockApiKey".to_string(), Some(url), None)
+    }
+
+    pub fn add_file(&self, data_path: &str, contents: &[u8], modified: DateTime<Utc>) {
+        let path = connector_path(data_path);
+        let mut state = self.state.lock().unwrap();
+        state.files.insert(path, (contents.to_vec(), modified));
+    }
+
+    // Adds a file that is sent with a Content-Encoding header (`contents` being the encoded bytes)
+    pub fn add_encoded_file(&self, data_path: &str, contents: &[u8], encoding: &str) {
+        self.add_file(data_path, contents, Utc::now());
+        let mut state = self.state.lock().unwrap();
+        let path = connector_path(data_path);
+        state.encodings.insert(path, encoding.to_string());
+    }
+
+    pub fn add_dir(&self, data_path: &str) {
+        let path = connector_path(data_path);
+        self.state.lock().unwrap().dirs.insert(path);
+    }
+
+    pub fn file(&self, data_path: &str) -> Option<Vec<u8>> {
+        let state = self.state.lock().unwrap();
+        state
+            .files
+            .get(&connector_path(data_path))
+            .map(|(contents, _)| contents.clone())
+    }
+
+    pub fn has_dir(&self, data_path: &str) -> bool {
+        let state = self.state.lock().unwrap();
+        state.dirs.contains(&connector_path(data_path))
+    }
+
+    pub fn set_upload_delay(&self, delay: Duration) {
+        self.state.lock().unwrap().upload_delay = delay;
+    }
+
+    pub fn max_uploads_in_flight(&self) -> usize {
+        self.state.lock().unwrap().max_uploads_in_flight
+    }
+}
+
+// "data://.my/foo" is served at /v1/connector/data/.my/foo
+fn connector_path(data_path: &str) -> String {
+    data_path
+        .replacen("://", "/", 1)
+        .trim_end_matches('/')
+        .to_string()
+}
+
+// Serves requests on a connection until the client closes it (clients reuse connections)
+fn serve(stream: TcpStream, state: &Mutex<State>) {
+    let mut writer = match stream.try_clone() {
+        Ok(writer) => writer,
+        Err(_) => return,
+    };
+    let mut reader = BufReader::new(stream);
+    while let Ok(Some(request)) = read_request(&mut reader) {
+        let response = handle(&request, state);
+        if write_response(&mut writer, &request, response).is_err() {
+            return;
+        }
+    }
+}
+
+fn read_request(reader: &mut BufReader<TcpStream>) -> io::Result<Option<Request>> {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+    let mut parts = line.split_whitespace();
+    let method = parts.next().unwrap_or_default().to_string();
+    let target = parts.next().unwrap_or_default();
+    let path = target.split('?').next().unwrap_or_default().to_string();
+
+    let mut headers = BTreeMap::new();
+    loop {
+        let mut header = String::new();
+        reader.read_line(&mut header)?;
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        let mut split = header.splitn(2, ':');
+        if let (Some(name), Some(value)) = (split.next(), split.next()) {
+            headers.insert(name.trim().to_lowercase(), value.trim().to_string());
+        }
+    }
+
+    let mut body = Vec::new();
+    if let Some(len) = headers.get("content-length") {
+        let len = len.parse::<u64>().unwrap_or(0);
+        reader.by_ref().take(len).read_to_end(&mut body)?;
+    } else if headers.get("transfer-encoding").map(String::as_str) == Some("chunked") {
+        loop {
+            let mut size = String::new();
+            reader.read_line(&mut size)?;
+            let size = usize::from_str_radix(size.trim(), 16).unwrap_or(0);
+            let mut chunk = vec![0; size + 2];
+            reader.read_exact(&mut chunk)?;
+            if size == 0 {
+                break;
+            }
+            body.extend_from_slice(&chunk[..size]);
+        }
+    }
+    Ok(Some(Request {
+        method,
+        path,
+        headers,
+        body,
+    }))
+}
+
+fn write_response(writer: &mut TcpStream, request: &Request, response: Response) -> io::Result<()> {
+    let mut head = format!("HTTP/1.1 {}\r\n", response.status);
+    for (name, value) in &response.headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    head.push_str(&format!("Content-Length: {}\r\n\r\n", response.body.len()));
+    writer.write_all(head.as_bytes())?;
+    if request.method != "HEAD" {
+        writer.write_all(&response.body)?;
+    }
+    writer.flush()
+}
+
+fn handle(request: &Request, state: &Mutex<State>) -> Response {
+    if let Some(algo) = request.path.strip_prefix("/v1/algo/") {
+        return call_algo(algo, request);
+    }
+    let path = match request.path.strip_prefix("/v1/connector/") {
+        Some(path) => path.trim_end_matches('/').to_string(),
+        None => return not_found(),
+    };
+    match &*request.method {
+        "HEAD" | "GET" => get_data(&path, request, state),
+        "PUT" => put_file(path, request, state),
+        "POST" => create_dir(&path, request, state),
+        _ => not_found(),
+    }
+}
+
+fn get_data(path: &str, request: &Request, state: &Mutex<State>) -> Response {
+    let state = state.lock().unwrap();
+    if let Some((contents, modified)) = state.files.get(path) {
+        let mut headers = vec![
+            ("X-Data-Type", "file".to_string()),
+            (
+                "Date",
+                modified.format("%a, %d %b %Y %H:%M:%S GMT").to_string(),
+            ),
+        ];
+        if let Some(encoding) = state.encodings.get(path) {
+            headers.push(("Content-Encoding", encoding.clone()));
+        }
+        // Only suffix ranges (e.g. "bytes=-16") and "bytes=<first>-<last>" are supported
+        let range = request.headers.get("range").map(String::as_str);
+        let (status, body) = match range.and_then(|r| r.strip_prefix("bytes=")) {
+            Some(range) => {
+                let mut split = range.splitn(2, '-');
+                let (first, last) = (split.next().unwrap(), split.next().unwrap_or(""));
+                let len = contents.len();
+                let (start, end) = match first.parse::<usize>() {
+                    Ok(first) => (first, last.parse::<usize>().map_or(len, |l| l + 1)),
+                    Err(_) => (len.saturating_sub(last.parse().unwrap_or(0)), len),
+                };
+                let (start, end) = (start.min(len), end.min(len));
+                headers.push((
+                    "Content-Range",
+                    format!("bytes {}-{}/{}", start, end.saturating_sub(1), len),
+                ));
+                ("206 Partial Content", contents[start..end].to_vec())
+            }
+            None => ("200 OK", contents.clone()),
+        };
+        return Response {
+            status,
+            headers,
+            body,
+        };
+    }
+    if state.dirs.contains(path) {
+        return Response {
+            status: "200 OK",
+            headers: vec![("X-Data-Type", "directory".to_string())],
+            body: list_dir(path, &state).into_bytes(),
+        };
+    }
+    not_found()
+}
+
+// Lists the files and directories directly in a directory, in a single page
+fn list_dir(path: &str, state: &State) -> String {
+    let prefix = format!("{}/", path);
+    let child = |key: &str| {
+        let name = key.strip_prefix(&prefix)?;
+        if name.contains('/') {
+            None
+        } else {
+            Some(Json::String(name.to_string()).to_string())
+        }
+    };
+    let folders: Vec<String> = state
+        .dirs
+        .iter()
+        .filter_map(|dir| child(dir))
+        .map(|name| format!(r#"{{"name":{}}}"#, name))
+        .collect();
+    let files: Vec<String> = state
+        .files
+        .iter()
+        .filter_map(|(file, (contents, modified))| {
+            Some(format!(
+                r#"{{"filename":{},"size":{},"last_modified":"{}"}}"#,
+                child(file)?,
+                contents.len(),
+                modified.to_rfc3339()
+            ))
+        })
+        .collect();
+    format!(
+        r#"{{"folders":[{}],"files":[{}]}}"#,
+        folders.join(","),
+        files.join(",")
+    )
+}
+
+fn put_file(path: String, request: &Request, state: &Mutex<State>) -> Response {
+    let delay = {
+        let mut state = state.lock().unwrap();
+        state.uploads_in_flight += 1;
+        state.max_uploads_in_flight = state.max_uploads_in_flight.max(state.uploads_in_flight);
+        state.upload_delay
+    };
+    thread::sleep(delay);
+
+    let mut state = state.lock().unwrap();
+    state.uploads_in_flight -= 1;
+    let parent = path.rsplitn(2, '/').nth(1).unwrap_or_default();
+    if !state.dirs.contains(parent) {
+        return not_found();
+    }
+    state
+        .files
+        .insert(path.clone(), (request.body.clone(), Utc::now()));
+    state.uploads += 1;
+    let uri = path.replacen("/", "://", 1);
+    let mut response = json_response(format!(r#"{{"result":"{}"}}"#, uri));
+    response
+        .headers
+        .push(("ETag", format!("\"v{}\"", state.uploads)));
+    response
+}
+
+fn create_dir(parent: &str, request: &Request, state: &Mutex<State>) -> Response {
+    let mut state = state.lock().unwrap();
+    let name = Json::from_str(&String::from_utf8_lossy(&request.body))
+        .ok()
+        .and_then(|json| {
+            json.find("name")
+                .and_then(Json::as_string)
+                .map(String::from)
+        });
+    match name {
+        Some(ref name) if state.dirs.contains(parent) => {
+            state.dirs.insert(format!("{}/{}", parent, name));
+            json_response("{}".to_string())
+        }
+        _ => not_found(),
+    }
+}
+
+// Responds with the input as the result (typed by the request's Content-Type)
+fn call_algo(algo: &str, request: &Request) -> Response {
+    if algo.split('/').nth(1) == Some("fail") {
+        return json_response(
+            r#"{"error":{"message":"algorithm failed","stacktrace":"at fail()"}}"#.to_string(),
+        );
+    }
+    let content_type = request.headers.get("content-type").cloned();
+    let (content_type, result) = match content_type.as_deref() {
+        Some("application/json") => ("json", String::from_utf8_lossy(&request.body).into_owned()),
+        Some(t) if t.starts_with("text/plain") => {
+            let text = String::from_utf8_lossy(&request.body).into_owned();
+            ("text", Json::String(text).to_string())
+        }
+        _ => {
+            let encoded = request.body.to_base64(base64::STANDARD);
+            ("binary", Json::String(encoded).to_string())
+        }
+    };
+    json_response(format!(
+        r#"{{"result":{},"metadata":{{"content_type":"{}","duration":0.5}}}}"#,
+        result, content_type
+    ))
+}
+
+fn json_response(body: String) -> Response {
+    Response {
+        status: "200 OK",
+        headers: vec![("Content-Type", "application/json".to_string())],
+        body: body.into_bytes(),
+    }
+}
+
+fn not_found() -> Response {
+    Response {
+        status: "404 Not Found",
+        headers: vec![
+            ("Content-Type", "application/json".to_string()),
+            ("X-Error-Message", "not found".to_string()),
+        ],
+        body: br#"{"error":{"message":"not found"}}"#.to_vec(),
+    }
+}